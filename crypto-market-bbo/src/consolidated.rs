@@ -0,0 +1,220 @@
+use crypto_msg_parser::{BboMsg, MarketType};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The best price of one side and the venue quoting it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Quote {
+    pub exchange: String,
+    pub price: f64,
+    pub quantity_base: f64,
+}
+
+/// The latest BBO of one venue.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VenueQuote {
+    pub exchange: String,
+    /// The normalized pair, the key of the topic the venue is published on
+    pub symbol: String,
+    pub bid_price: f64,
+    pub bid_quantity_base: f64,
+    pub ask_price: f64,
+    pub ask_quantity_base: f64,
+    /// Exchange timestamp of the quote
+    pub timestamp: i64,
+    /// Milliseconds since the quote was received
+    pub age_ms: i64,
+    /// Stale quotes don't take part in the best bid/ask
+    pub stale: bool,
+}
+
+#[derive(Serialize)]
+struct ConsolidatedMsg<'a> {
+    pair: &'a str,
+    market_type: String,
+    timestamp: i64,
+    bid: Option<Quote>,
+    ask: Option<Quote>,
+    venues: Vec<VenueQuote>,
+}
+
+// a BBO and the local time it was received
+struct Entry {
+    bbo: BboMsg,
+    received_at: i64,
+}
+
+/// Best bid and ask of one instrument across venues.
+pub struct ConsolidatedBbo {
+    pair: String,
+    market_type: MarketType,
+    max_age_ms: i64,
+    // exchange -> latest BBO
+    venues: BTreeMap<String, Entry>,
+}
+
+impl ConsolidatedBbo {
+    /// Quotes older than `max_age_ms` are ignored until they are updated.
+    pub fn new(pair: &str, market_type: MarketType, max_age_ms: i64) -> Self {
+        ConsolidatedBbo {
+            pair: pair.to_string(),
+            market_type,
+            max_age_ms,
+            venues: BTreeMap::new(),
+        }
+    }
+
+    /// Replace the quote of a venue, received at `now` in milliseconds.
+    pub fn update(&mut self, bbo: BboMsg, now: i64) {
+        self.venues.insert(
+            bbo.exchange.clone(),
+            Entry {
+                bbo,
+                received_at: now,
+            },
+        );
+    }
+
+    pub fn venues(&self, now: i64) -> Vec<VenueQuote> {
+        self.venues
+            .values()
+            .map(|entry| {
+                let age_ms = now - entry.received_at;
+                VenueQuote {
+                    exchange: entry.bbo.exchange.clone(),
+                    symbol: entry.bbo.symbol.clone(),
+                    bid_price: entry.bbo.bid_price,
+                    bid_quantity_base: entry.bbo.bid_quantity_base,
+                    ask_price: entry.bbo.ask_price,
+                    ask_quantity_base: entry.bbo.ask_quantity_base,
+                    timestamp: entry.bbo.timestamp,
+                    age_ms,
+                    stale: age_ms > self.max_age_ms,
+                }
+            })
+            .collect()
+    }
+
+    /// The highest bid and lowest ask of the venues that are not stale.
+    pub fn best(&self, now: i64) -> (Option<Quote>, Option<Quote>) {
+        let mut bid: Option<Quote> = None;
+        let mut ask: Option<Quote> = None;
+        for venue in self.venues(now).into_iter().filter(|venue| !venue.stale) {
+            if venue.bid_price > 0.0 && !matches!(bid, Some(ref b) if b.price >= venue.bid_price) {
+                bid = Some(Quote {
+                    exchange: venue.exchange.clone(),
+                    price: venue.bid_price,
+                    quantity_base: venue.bid_quantity_base,
+                });
+            }
+            if venue.ask_price > 0.0 && !matches!(ask, Some(ref a) if a.price <= venue.ask_price) {
+                ask = Some(Quote {
+                    exchange: venue.exchange.clone(),
+                    price: venue.ask_price,
+                    quantity_base: venue.ask_quantity_base,
+                });
+            }
+        }
+        (bid, ask)
+    }
+
+    /// The consolidated BBO and all venue quotes as JSON.
+    pub fn to_json(&self, now: i64) -> String {
+        let (bid, ask) = self.best(now);
+        let msg = ConsolidatedMsg {
+            pair: &self.pair,
+            market_type: self.market_type.to_string(),
+            timestamp: now,
+            bid,
+            ask,
+            venues: self.venues(now),
+        };
+        serde_json::to_string(&msg).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto_msg_parser::MessageType;
+
+    const MAX_AGE_MS: i64 = 5000;
+
+    fn bbo(exchange: &str, bid_price: f64, ask_price: f64) -> BboMsg {
+        BboMsg {
+            exchange: exchange.to_string(),
+            market_type: MarketType::Spot,
+            symbol: "BTC/USDT".to_string(),
+            pair: "BTC/USDT".to_string(),
+            msg_type: MessageType::BBO,
+            timestamp: 1654041600000,
+            ask_price,
+            ask_quantity_base: 2.0,
+            ask_quantity_quote: ask_price * 2.0,
+            ask_quantity_contract: None,
+            bid_price,
+            bid_quantity_base: 1.0,
+            bid_quantity_quote: bid_price,
+            bid_quantity_contract: None,
+            id: None,
+            json: String::new(),
+        }
+    }
+
+    fn consolidated() -> ConsolidatedBbo {
+        let mut consolidated = ConsolidatedBbo::new("BTC/USDT", MarketType::Spot, MAX_AGE_MS);
+        consolidated.update(bbo("binance", 29950.1, 29950.4), 1000);
+        consolidated.update(bbo("okx", 29949.9, 29950.3), 2000);
+        consolidated
+    }
+
+    fn exchanges(best: &(Option<Quote>, Option<Quote>)) -> (Option<&str>, Option<&str>) {
+        (
+            best.0.as_ref().map(|bid| bid.exchange.as_str()),
+            best.1.as_ref().map(|ask| ask.exchange.as_str()),
+        )
+    }
+
+    #[test]
+    fn picks_the_best_bid_and_ask_across_venues() {
+        let best = consolidated().best(2000);
+        assert_eq!(exchanges(&best), (Some("binance"), Some("okx")));
+        assert_eq!(best.0.unwrap().price, 29950.1);
+        assert_eq!(best.1.unwrap().price, 29950.3);
+    }
+
+    #[test]
+    fn leaves_stale_venues_out() {
+        let consolidated = consolidated();
+        // binance was updated at 1000, okx at 2000
+        let now = 1000 + MAX_AGE_MS + 1;
+        let venues = consolidated.venues(now);
+        assert!(venues[0].stale);
+        assert_eq!(venues[0].age_ms, MAX_AGE_MS + 1);
+        assert!(!venues[1].stale);
+        assert_eq!(
+            exchanges(&consolidated.best(now)),
+            (Some("okx"), Some("okx"))
+        );
+    }
+
+    #[test]
+    fn has_no_best_quote_once_every_venue_is_stale() {
+        let consolidated = consolidated();
+        let now = 2000 + MAX_AGE_MS + 1;
+        assert!(consolidated.venues(now).iter().all(|venue| venue.stale));
+        assert_eq!(consolidated.best(now), (None, None));
+    }
+
+    #[test]
+    fn keeps_the_first_venue_on_ties() {
+        let mut consolidated = ConsolidatedBbo::new("BTC/USDT", MarketType::Spot, MAX_AGE_MS);
+        consolidated.update(bbo("okx", 29950.0, 29951.0), 1000);
+        consolidated.update(bbo("binance", 29950.0, 29951.0), 1000);
+        // venues are visited in exchange order
+        assert_eq!(
+            exchanges(&consolidated.best(1000)),
+            (Some("binance"), Some("binance"))
+        );
+    }
+}
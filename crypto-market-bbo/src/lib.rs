@@ -0,0 +1,5 @@
+pub(crate) mod consolidated;
+pub(crate) mod service;
+
+pub use consolidated::{ConsolidatedBbo, Quote, VenueQuote};
+pub use service::{create_bbo_thread, BboError, BboResult};
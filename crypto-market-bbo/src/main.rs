@@ -0,0 +1,61 @@
+use clap::clap_app;
+use crypto_market_bbo::create_bbo_thread;
+use crypto_market_topic::Transport;
+use crypto_msg_parser::MarketType;
+use log::*;
+use std::{str::FromStr, time::Duration};
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() {
+    env_logger::init();
+    Transport::init_or_exit();
+
+    let matches: clap::ArgMatches = clap_app!(bbo =>
+            (about: "Consolidate the best bid/offer of one pair across exchanges")
+            (@arg MARKET_TYPE:  +required "market_type")
+            (@arg PAIRS: +required +use_delimiter "comma_seperated normalized pairs, e.g. BTC/USDT")
+            (@arg EXCHANGES: -e --exchanges +required +takes_value +use_delimiter "comma_seperated exchanges")
+            (@arg MAX_AGE: -a --max_age +takes_value "milliseconds after which a venue quote is stale, 5000 by default")
+            (@arg INTERVAL: -i --interval +takes_value "milliseconds between publications without updates, 1000 by default")
+    )
+    .get_matches();
+
+    let market_type_str = matches.value_of("MARKET_TYPE").unwrap();
+    let market_type = match MarketType::from_str(market_type_str) {
+        Ok(market_type) => market_type,
+        Err(_) => {
+            println!("Unknown market type: {}", market_type_str);
+            return;
+        }
+    };
+    let exchanges: Vec<String> = matches
+        .values_of("EXCHANGES")
+        .unwrap()
+        .map(|v| v.to_string())
+        .collect();
+    let max_age = matches
+        .value_of("MAX_AGE")
+        .map(|v| v.parse::<u64>().expect("max_age input error"))
+        .unwrap_or(5000);
+    let interval = matches
+        .value_of("INTERVAL")
+        .map(|v| v.parse::<u64>().expect("interval input error"))
+        .unwrap_or(1000);
+
+    let mut tasks = Vec::new();
+    for pair in matches.values_of("PAIRS").unwrap() {
+        tasks.push(tokio::task::spawn(create_bbo_thread(
+            pair.to_string(),
+            market_type,
+            exchanges.clone(),
+            Duration::from_millis(max_age),
+            Duration::from_millis(interval),
+        )));
+    }
+
+    for result in futures::future::join_all(tasks).await {
+        if let Err(err) = result.expect("create_bbo_thread failed") {
+            error!("{}", err);
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use super::ConsolidatedBbo;
+
+use crypto_market_topic::{MessageType, Publisher, Subscriber, Topic, TransportError};
+use crypto_msg_parser::{BboMsg, MarketType};
+use log::*;
+use std::time::Duration;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use wmjtyd_libstock::data::bbo::decode_bbo;
+
+async fn subscribe(topic: Topic, tx: UnboundedSender<BboMsg>) {
+    let mut socket = match Subscriber::for_topic(&topic).await {
+        Ok(v) => v,
+        Err(err) => {
+            error!("sub error: {}; msg: {}", topic, err);
+            return;
+        }
+    };
+
+    loop {
+        match socket.recv().await {
+            Ok(buf) => match decode_bbo(&buf) {
+                Ok(msg) => {
+                    if tx.send(msg).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => warn!("{}: failed to decode bbo: {:?}", topic, err),
+            },
+            Err(err) => {
+                error!("{}: failed to receive payload: {}", topic, err);
+                break;
+            }
+        }
+    }
+}
+
+/// Consolidate the BBO of one normalized pair across `exchanges`.
+///
+/// Subscribes to the `normalized_{exchange}_{market_type}_bbo_{pair}` topics
+/// published with `--normalized_topics`, and publishes the best bid/ask with
+/// its venue and the quote of every venue as JSON on
+/// `consolidated_{market_type}_bbo_{pair}`, after every update and at least
+/// every `interval` so staleness is reported while venues are quiet.
+pub async fn create_bbo_thread(
+    pair: String,
+    market_type: MarketType,
+    exchanges: Vec<String>,
+    max_age: Duration,
+    interval: Duration,
+) -> BboResult<()> {
+    let topic = Topic::new("consolidated", market_type, MessageType::BBO, &pair);
+    let mut socket = Publisher::for_topic(&topic).await?;
+
+    let (tx, mut rx) = unbounded_channel();
+    for exchange in exchanges.iter() {
+        let topic = Topic::new(exchange, market_type, MessageType::BBO, "").normalized(&pair);
+        tokio::task::spawn(subscribe(topic, tx.clone()));
+    }
+    drop(tx);
+
+    let mut bbo = ConsolidatedBbo::new(&pair, market_type, max_age.as_millis() as i64);
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            msg = rx.recv() => {
+                match msg {
+                    Some(msg) => bbo.update(msg, chrono::Utc::now().timestamp_millis()),
+                    None => break,
+                }
+            }
+            _ = ticker.tick() => {}
+        }
+
+        let json = bbo.to_json(chrono::Utc::now().timestamp_millis());
+        if let Err(err) = socket.send(json.as_bytes()).await {
+            error!("{}: failed to publish: {}", topic, err);
+        }
+    }
+
+    warn!("{}: all subscriptions have ended", topic);
+    Ok(())
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum BboError {
+    #[error("transport error: {0}")]
+    TransportError(#[from] TransportError),
+}
+
+pub type BboResult<T> = Result<T, BboError>;
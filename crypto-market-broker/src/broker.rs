@@ -0,0 +1,155 @@
+use super::stats::{now_ms, SharedStats};
+use log::*;
+use std::os::raw::{c_int, c_void};
+
+// Added in libzmq 4.2, the zmq crate has no setter for it
+const ZMQ_XPUB_VERBOSER: c_int = 78;
+const ZMQ_XPUB_VERBOSER_SINCE: (i32, i32, i32) = (4, 2, 0);
+
+/// Pass every subscription and unsubscription of the XPUB `socket`, not just
+/// the first subscription and last unsubscription of a prefix, so they can
+/// be counted per subscriber.
+///
+/// libzmq before 4.2 rejects the option with a bare EINVAL, so its version is
+/// checked first to tell why.
+fn set_xpub_verboser(socket: &mut zmq::Socket) -> BrokerResult<()> {
+    let version = zmq::version();
+    if version < ZMQ_XPUB_VERBOSER_SINCE {
+        return Err(BrokerError::UnsupportedLibzmq(version));
+    }
+    let value: c_int = 1;
+    // SAFETY: the socket pointer is owned by `socket`, which outlives the
+    // call, and libzmq reads exactly `size_of::<c_int>()` bytes of `value`
+    let rc = unsafe {
+        zmq_sys::zmq_setsockopt(
+            socket.as_mut_ptr(),
+            ZMQ_XPUB_VERBOSER,
+            &value as *const c_int as *const c_void,
+            std::mem::size_of::<c_int>(),
+        )
+    };
+    if rc == -1 {
+        // SAFETY: zmq_errno only reads the errno of the calling thread
+        return Err(zmq::Error::from_raw(unsafe { zmq_sys::zmq_errno() }).into());
+    }
+    Ok(())
+}
+
+/// Forward every message of the publishers connected to, or connected by,
+/// the XSUB `frontend` to the subscribers of the XPUB `backend`.
+///
+/// Crawlers connect to the frontend with `ZMQ_BROKER_FRONTEND`, and the
+/// broker connects to the endpoints of `upstreams` for publishers that bind.
+/// Subscriptions of the backend are forwarded upstream, so only the topics
+/// with a matching prefix leave the crawlers.
+pub fn run_broker(
+    frontend: &str,
+    backend: &str,
+    upstreams: &[String],
+    stats: SharedStats,
+) -> BrokerResult<()> {
+    let context = zmq::Context::new();
+
+    let xsub = context.socket(zmq::XSUB)?;
+    xsub.bind(frontend)
+        .map_err(|err| BrokerError::BindFailed(frontend.to_string(), err))?;
+    for upstream in upstreams {
+        xsub.connect(upstream)
+            .map_err(|err| BrokerError::ConnectFailed(upstream.to_string(), err))?;
+    }
+
+    let mut xpub = context.socket(zmq::XPUB)?;
+    set_xpub_verboser(&mut xpub)?;
+    xpub.bind(backend)
+        .map_err(|err| BrokerError::BindFailed(backend.to_string(), err))?;
+
+    info!(
+        "broker frontend {} backend {}, {} upstreams",
+        frontend,
+        backend,
+        upstreams.len()
+    );
+
+    loop {
+        let mut items = [
+            xsub.as_poll_item(zmq::POLLIN),
+            xpub.as_poll_item(zmq::POLLIN),
+        ];
+        zmq::poll(&mut items, -1)?;
+
+        if items[0].is_readable() {
+            let frames = xsub.recv_multipart(0)?;
+            if let Some(topic) = frames.first() {
+                let bytes = frames.iter().skip(1).map(|frame| frame.len()).sum();
+                stats.lock().unwrap().record_message(
+                    &String::from_utf8_lossy(topic),
+                    bytes,
+                    now_ms(),
+                );
+            }
+            xpub.send_multipart(frames, 0)?;
+        }
+
+        if items[1].is_readable() {
+            let frame = xpub.recv_bytes(0)?;
+            // 1 + prefix to subscribe, 0 + prefix to unsubscribe
+            if let Some((&flag, prefix)) = frame.split_first() {
+                let prefix = String::from_utf8_lossy(prefix);
+                debug!(
+                    "{} {}",
+                    if flag == 1 {
+                        "subscribe"
+                    } else {
+                        "unsubscribe"
+                    },
+                    prefix
+                );
+                stats
+                    .lock()
+                    .unwrap()
+                    .record_subscription(&prefix, flag == 1);
+            }
+            xsub.send(frame, 0)?;
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum BrokerError {
+    #[error("failed to bind {0}: {1}")]
+    BindFailed(String, zmq::Error),
+
+    #[error("failed to connect {0}: {1}")]
+    ConnectFailed(String, zmq::Error),
+
+    #[error("libzmq {0:?} has no ZMQ_XPUB_VERBOSER, 4.2 or later is required")]
+    UnsupportedLibzmq((i32, i32, i32)),
+
+    #[error("zeromq error: {0}")]
+    ZmqError(#[from] zmq::Error),
+}
+
+pub type BrokerResult<T> = Result<T, BrokerError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sets_xpub_verboser() {
+        let context = zmq::Context::new();
+        let mut xpub = context.socket(zmq::XPUB).unwrap();
+        set_xpub_verboser(&mut xpub).unwrap();
+    }
+
+    #[test]
+    fn reports_setsockopt_errors() {
+        let context = zmq::Context::new();
+        // only XPUB sockets have the option
+        let mut sub = context.socket(zmq::SUB).unwrap();
+        assert!(matches!(
+            set_xpub_verboser(&mut sub),
+            Err(BrokerError::ZmqError(zmq::Error::EINVAL))
+        ));
+    }
+}
@@ -0,0 +1,17 @@
+//! An XSUB/XPUB broker in front of the crawlers.
+//!
+//! Unlike the rest of the workspace, which speaks ZeroMQ through the pure
+//! Rust `zeromq` crate of `crypto-market-topic`, the broker links libzmq
+//! through the `zmq` crate. `zeromq` has no XSUB socket, so subscriptions
+//! can't be forwarded upstream, and no `ZMQ_XPUB_VERBOSER`, so they can't be
+//! counted per subscriber. Both implement ZMTP 3, so crawlers and consumers
+//! on `zeromq` connect to the broker unchanged.
+//!
+//! The `zmq` crate has no setter for `ZMQ_XPUB_VERBOSER` either, it is the
+//! one option set through `zmq-sys`, see `set_xpub_verboser`.
+
+pub(crate) mod broker;
+pub(crate) mod stats;
+
+pub use broker::{run_broker, BrokerError, BrokerResult};
+pub use stats::{serve_topics, BrokerStats, SharedStats};
@@ -0,0 +1,46 @@
+use clap::clap_app;
+use crypto_market_broker::{run_broker, serve_topics, SharedStats};
+use crypto_market_topic::{Topic, Transport};
+use log::*;
+
+fn main() {
+    env_logger::init();
+    Transport::init_or_exit();
+
+    let matches: clap::ArgMatches = clap_app!(broker =>
+            (about: "Aggregate crawler topics behind one XPUB endpoint")
+            (@arg FRONTEND: -f --frontend +takes_value "XSUB endpoint publishers connect to, tcp://0.0.0.0:5555 by default")
+            (@arg BACKEND: -b --backend +takes_value "XPUB endpoint subscribers connect to, tcp://0.0.0.0:5556 by default")
+            (@arg TOPICS_ADDR: -t --topics_addr +takes_value "address serving the live topic list, 0.0.0.0:9101 by default")
+            (@arg UPSTREAMS: -u --upstreams +takes_value +use_delimiter "comma_seperated topics published on their own endpoints")
+    )
+    .get_matches();
+
+    let frontend = matches.value_of("FRONTEND").unwrap_or("tcp://0.0.0.0:5555");
+    let backend = matches.value_of("BACKEND").unwrap_or("tcp://0.0.0.0:5556");
+    let topics_addr = matches
+        .value_of("TOPICS_ADDR")
+        .unwrap_or("0.0.0.0:9101")
+        .to_string();
+
+    // Topics of crawlers that bind their own sockets rather than connect to
+    // the frontend
+    let mut upstreams: Vec<String> = Vec::new();
+    for topic in matches.values_of("UPSTREAMS").into_iter().flatten() {
+        let topic = topic.parse::<Topic>().expect("topic input error");
+        let endpoint = Transport::global()
+            .and_then(|transport| transport.subscriber(&topic))
+            .expect("endpoint input error");
+        if !upstreams.iter().any(|url| url == endpoint.as_str()) {
+            upstreams.push(endpoint.as_str().to_string());
+        }
+    }
+
+    let stats = SharedStats::default();
+    let topics_stats = stats.clone();
+    std::thread::spawn(move || serve_topics(topics_addr, topics_stats));
+
+    if let Err(err) = run_broker(frontend, backend, &upstreams, stats) {
+        error!("{}", err);
+    }
+}
@@ -0,0 +1,189 @@
+use log::*;
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub type SharedStats = Arc<Mutex<BrokerStats>>;
+
+#[derive(Default)]
+struct TopicStats {
+    messages: u64,
+    bytes: u64,
+    last_message_at: u64,
+}
+
+/// Topics seen on the frontend and subscriptions made on the backend.
+///
+/// The XPUB socket is verboser, so every subscription and unsubscription
+/// of a prefix is counted, including those of subscribers that disconnect.
+#[derive(Default)]
+pub struct BrokerStats {
+    topics: BTreeMap<String, TopicStats>,
+    subscriptions: BTreeMap<String, u64>,
+}
+
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+impl BrokerStats {
+    pub fn record_message(&mut self, topic: &str, bytes: usize, now: u64) {
+        let stats = self.topics.entry(topic.to_string()).or_default();
+        stats.messages += 1;
+        stats.bytes += bytes as u64;
+        stats.last_message_at = now;
+    }
+
+    pub fn record_subscription(&mut self, prefix: &str, subscribe: bool) {
+        if subscribe {
+            *self.subscriptions.entry(prefix.to_string()).or_default() += 1;
+        } else if let Some(count) = self.subscriptions.get_mut(prefix) {
+            *count -= 1;
+            if *count == 0 {
+                self.subscriptions.remove(prefix);
+            }
+        }
+    }
+
+    fn subscribers(&self, topic: &str) -> u64 {
+        self.subscriptions
+            .iter()
+            .filter(|(prefix, _)| topic.starts_with(prefix.as_str()))
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    /// The live topic list with the number of subscriptions matching each
+    /// topic, and the subscriptions by prefix.
+    pub fn to_json(&self, now: u64) -> String {
+        let topics: Vec<_> = self
+            .topics
+            .iter()
+            .map(|(topic, stats)| {
+                json!({
+                    "topic": topic,
+                    "messages": stats.messages,
+                    "bytes": stats.bytes,
+                    "last_message_age_ms": now.saturating_sub(stats.last_message_at),
+                    "subscribers": self.subscribers(topic),
+                })
+            })
+            .collect();
+        let subscriptions: Vec<_> = self
+            .subscriptions
+            .iter()
+            .map(|(prefix, count)| json!({ "prefix": prefix, "count": count }))
+            .collect();
+
+        json!({ "topics": topics, "subscriptions": subscriptions }).to_string()
+    }
+}
+
+fn handle_connection(mut stream: TcpStream, stats: &SharedStats) -> std::io::Result<()> {
+    let mut buf = [0u8; 1024];
+    let size = stream.read(&mut buf)?;
+    let request = String::from_utf8_lossy(&buf[..size]);
+
+    let response = if request.starts_with("GET /topics ") {
+        let body = stats.lock().unwrap().to_json(now_ms());
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+
+    stream.write_all(response.as_bytes())?;
+    stream.flush()
+}
+
+/// Serve the live topic list as JSON on `http://{addr}/topics`.
+pub fn serve_topics(addr: String, stats: SharedStats) {
+    let listener = match TcpListener::bind(&addr) {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("failed to serve topics on {}: {}", addr, err);
+            return;
+        }
+    };
+    info!("serving topics on http://{}/topics", addr);
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if let Err(err) = handle_connection(stream, &stats) {
+                    debug!("topics connection failed: {}", err);
+                }
+            }
+            Err(err) => error!("failed to accept a topics connection: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn json(stats: &BrokerStats, now: u64) -> Value {
+        serde_json::from_str(&stats.to_json(now)).unwrap()
+    }
+
+    #[test]
+    fn counts_messages_per_topic() {
+        let mut stats = BrokerStats::default();
+        stats.record_message("binance_spot_trade_BTCUSDT", 10, 1000);
+        stats.record_message("binance_spot_trade_BTCUSDT", 20, 1500);
+        stats.record_message("binance_spot_bbo_BTCUSDT", 5, 1200);
+
+        let json = json(&stats, 2000);
+        let topics = json["topics"].as_array().unwrap();
+        assert_eq!(topics.len(), 2);
+        assert_eq!(topics[0]["topic"], "binance_spot_bbo_BTCUSDT");
+        assert_eq!(topics[1]["topic"], "binance_spot_trade_BTCUSDT");
+        assert_eq!(topics[1]["messages"], 2);
+        assert_eq!(topics[1]["bytes"], 30);
+        assert_eq!(topics[1]["last_message_age_ms"], 500);
+    }
+
+    #[test]
+    fn counts_subscriptions_per_subscriber() {
+        let mut stats = BrokerStats::default();
+        stats.record_message("binance_spot_trade_BTCUSDT", 10, 1000);
+        stats.record_subscription("binance_spot_trade_", true);
+        stats.record_subscription("binance_spot_trade_", true);
+        stats.record_subscription("binance_spot_trade_BTCUSDT", true);
+        assert_eq!(stats.subscribers("binance_spot_trade_BTCUSDT"), 3);
+        assert_eq!(stats.subscribers("binance_spot_bbo_BTCUSDT"), 0);
+
+        // one of the two prefix subscribers leaves
+        stats.record_subscription("binance_spot_trade_", false);
+        assert_eq!(stats.subscribers("binance_spot_trade_BTCUSDT"), 2);
+        assert_eq!(json(&stats, 1000)["topics"][0]["subscribers"], 2);
+
+        stats.record_subscription("binance_spot_trade_", false);
+        stats.record_subscription("binance_spot_trade_BTCUSDT", false);
+        assert_eq!(stats.subscribers("binance_spot_trade_BTCUSDT"), 0);
+        assert!(json(&stats, 1000)["subscriptions"]
+            .as_array()
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn ignores_unknown_unsubscriptions() {
+        let mut stats = BrokerStats::default();
+        stats.record_subscription("binance_", false);
+        assert!(stats.subscriptions.is_empty());
+    }
+}
@@ -0,0 +1,206 @@
+use lazy_static::lazy_static;
+use log::*;
+use reopen::{Handle, Reopen};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, Once,
+    },
+};
+
+lazy_static! {
+    // The reopen handles of every open file, by file id
+    static ref HANDLES: Mutex<HashMap<u64, Handle>> = Mutex::new(HashMap::new());
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static SIGHUP_HANDLER: Once = Once::new();
+
+// One handler for all files, registered with the first one
+#[cfg(not(windows))]
+fn register_sighup() {
+    use signal_hook::{consts::signal::SIGHUP, iterator::Signals};
+
+    let mut signals = match Signals::new([SIGHUP]) {
+        Ok(signals) => signals,
+        Err(err) => {
+            error!(
+                "Failed to register SIGHUP, files will not be reopened: {}",
+                err
+            );
+            return;
+        }
+    };
+    let thread = std::thread::Builder::new()
+        .name("sighup".to_string())
+        .spawn(move || {
+            for _ in signals.forever() {
+                info!("SIGHUP received, reopening the files");
+                reopen_all();
+            }
+        });
+    if let Err(err) = thread {
+        error!(
+            "Failed to start the SIGHUP thread, files will not be reopened: {}",
+            err
+        );
+    }
+}
+
+// Windows has a very limited set of signals, files are never reopened there
+#[cfg(windows)]
+fn register_sighup() {}
+
+/// Reopen every open file before its next write, as SIGHUP does.
+pub fn reopen_all() {
+    for handle in HANDLES.lock().unwrap().values() {
+        handle.reopen();
+    }
+}
+
+fn open<P: AsRef<Path>>(p: P) -> io::Result<BufWriter<fs::File>> {
+    info!("reopen {}", p.as_ref().display());
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(true)
+        .open(p)?;
+    Ok(BufWriter::new(file))
+}
+
+/// A file appended to and reopened on SIGHUP, so it can be rotated.
+pub struct FileWriter {
+    id: u64,
+    file: Reopen<BufWriter<fs::File>>,
+    path: String,
+}
+
+impl FileWriter {
+    /// Open `path` for appending, creating its directories.
+    pub fn new(path: &str) -> io::Result<Self> {
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let path_clone = path.to_string();
+        let file = Reopen::new(Box::new(move || open(&path_clone)))?;
+
+        SIGHUP_HANDLER.call_once(register_sighup);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        HANDLES.lock().unwrap().insert(id, file.handle());
+
+        Ok(FileWriter {
+            id,
+            file,
+            path: path.to_string(),
+        })
+    }
+
+    /// Append a line, errors are logged.
+    pub fn write(&mut self, s: &str) {
+        if let Err(e) = writeln!(self.file, "{}", s) {
+            error!("{}, {}", self.path, e);
+        }
+    }
+
+    /// Append bytes as they are, errors are logged.
+    pub fn write_bytes(&mut self, data: &[u8]) {
+        if let Err(e) = self.file.write_all(data) {
+            error!("{}, {}", self.path, e);
+        }
+    }
+
+    /// Flush what is buffered, errors are logged.
+    pub fn flush(&mut self) {
+        if let Err(e) = self.file.flush() {
+            error!("{}, {}", self.path, e);
+        }
+    }
+
+    /// Flush before the file is dropped.
+    pub fn close(&mut self) {
+        self.flush();
+    }
+}
+
+impl Drop for FileWriter {
+    fn drop(&mut self) {
+        HANDLES.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "crypto-market-file-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn creates_directories_and_appends() {
+        let dir = temp_dir("append");
+        let path = dir.join("a/b/topic.json");
+        let path = path.to_str().unwrap();
+
+        let mut writer = FileWriter::new(path).unwrap();
+        writer.write("first");
+        writer.close();
+        drop(writer);
+        let mut writer = FileWriter::new(path).unwrap();
+        writer.write("second");
+        writer.close();
+
+        assert_eq!(fs::read_to_string(path).unwrap(), "first\nsecond\n");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn reopens_a_rotated_file() {
+        let dir = temp_dir("rotate");
+        let path = dir.join("topic.json");
+        let rotated = dir.join("topic.json.1");
+
+        let mut writer = FileWriter::new(path.to_str().unwrap()).unwrap();
+        writer.write("before");
+        writer.close();
+        fs::rename(&path, &rotated).unwrap();
+        reopen_all();
+        writer.write("after");
+        writer.close();
+
+        assert_eq!(fs::read_to_string(&rotated).unwrap(), "before\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "after\n");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn writes_bytes() {
+        let dir = temp_dir("bytes");
+        let path = dir.join("topic.bin");
+
+        let mut writer = FileWriter::new(path.to_str().unwrap()).unwrap();
+        writer.write_bytes(&[0, 1, 2]);
+        writer.flush();
+        assert_eq!(fs::read(&path).unwrap(), vec![0, 1, 2]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn reports_an_unwritable_path() {
+        let dir = temp_dir("unwritable");
+        fs::create_dir_all(&dir).unwrap();
+        // a directory can not be opened as a file
+        assert!(FileWriter::new(dir.to_str().unwrap()).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,327 @@
+use crate::watchdog::WatchdogConfig;
+use crate::writers::SinkKind;
+
+use crypto_crawler::{MarketType, MessageType};
+use std::str::FromStr;
+
+/// One `[[feeds]]` entry of the crawler config.
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize, Clone)]
+pub struct FeedConfig {
+    pub exchange: String,
+    pub market_type: String,
+    pub msg_type: String,
+    /// All symbols of the market are crawled if empty
+    #[serde(default)]
+    pub symbols: Vec<String>,
+    /// Candlestick periods in seconds, one pipeline per period
+    #[serde(default)]
+    pub periods: Vec<String>,
+    /// Periods of candlesticks built from trades, e.g. `1s`, `1m`, `1h`
+    #[serde(default)]
+    pub bar_periods: Vec<String>,
+    /// Also publish on `normalized_` topics keyed by the normalized pair
+    #[serde(default)]
+    pub normalized_topics: bool,
+}
+
+/// Crawler process config, loaded from a TOML file.
+///
+/// ```toml
+/// data_dir = "/carbonbot_data"
+/// metrics_addr = "0.0.0.0:9100"
+///
+/// [[feeds]]
+/// exchange = "binance"
+/// market_type = "spot"
+/// msg_type = "trade"
+/// symbols = ["BTCUSDT", "ETHUSDT"]
+///
+/// [[feeds]]
+/// exchange = "binance"
+/// market_type = "spot"
+/// msg_type = "candlestick"
+/// symbols = ["BTCUSDT"]
+/// periods = ["60", "300"]
+///
+/// [[feeds]]
+/// exchange = "bitmex"
+/// market_type = "inverse_swap"
+/// msg_type = "trade"
+/// symbols = ["XBTUSD"]
+/// normalized_topics = true
+/// bar_periods = ["1s", "5s", "1m", "15m", "1h"]
+/// ```
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize, Clone)]
+pub struct CrawlerConfig {
+    pub data_dir: Option<String>,
+    pub redis_url: Option<String>,
+    /// Directory of the raw message captures, see `crypto-market-reparse`
+    pub capture_dir: Option<String>,
+    /// Sinks of the parsed messages, `zeromq`, `redis`, `file` or `stdout`
+    pub sinks: Option<Vec<String>>,
+    /// Directory of the `file` sink
+    pub sink_dir: Option<String>,
+    /// Address of the Prometheus metrics endpoint, e.g. `0.0.0.0:9100`
+    pub metrics_addr: Option<String>,
+    /// Slack alerts on stale topics, parse failures and exited crawlers
+    pub watchdog: Option<WatchdogConfig>,
+    #[serde(default)]
+    pub feeds: Vec<FeedConfig>,
+}
+
+/// Where the crawled messages go, shared by every feed of a process.
+#[derive(Debug, Clone, Default)]
+pub struct Outputs {
+    /// Raw messages archived as JSON lines
+    pub data_dir: Option<String>,
+    pub redis_url: Option<String>,
+    /// Raw messages captured for re-parsing
+    pub capture_dir: Option<String>,
+    /// Every parsed message is sent to all of them
+    pub sinks: Vec<SinkKind>,
+    pub sink_dir: Option<String>,
+}
+
+/// Parse sink names, ZeroMQ and, with a Redis URL, Redis if there are none.
+pub fn parse_sinks(
+    names: Option<Vec<String>>,
+    redis_url: Option<&str>,
+) -> Result<Vec<SinkKind>, String> {
+    match names {
+        Some(names) => names.iter().map(|name| name.parse()).collect(),
+        None if redis_url.is_some() => Ok(vec![SinkKind::Zeromq, SinkKind::Redis]),
+        None => Ok(vec![SinkKind::Zeromq]),
+    }
+}
+
+/// A feed ready to be crawled, with its market and message type resolved.
+#[derive(Debug, Clone)]
+pub struct Feed {
+    pub exchange: String,
+    pub market_type: MarketType,
+    pub msg_type: MessageType,
+    pub symbols: Option<Vec<String>>,
+    pub period: String,
+    /// Periods in seconds of candlesticks built from trades
+    pub bar_periods: Vec<u64>,
+    pub normalized_topics: bool,
+}
+
+/// Parse a period like `1s`, `5m`, `1h`, `1d`, or a plain number of seconds.
+pub fn parse_period(period: &str) -> Option<u64> {
+    let period = period.trim();
+    let (value, unit) = match period.find(|c: char| !c.is_ascii_digit()) {
+        Some(pos) => period.split_at(pos),
+        None => (period, "s"),
+    };
+    let value = value.parse::<u64>().ok()?;
+    let seconds = match unit {
+        "s" => value,
+        "m" => value * 60,
+        "h" => value * 3600,
+        "d" => value * 86400,
+        _ => return None,
+    };
+    if seconds == 0 {
+        None
+    } else {
+        Some(seconds)
+    }
+}
+
+/// Check the period of a candlestick feed, a plain number of seconds that
+/// the crawler subscribes to.
+pub fn check_period(msg_type: MessageType, period: &str) -> Result<(), String> {
+    if msg_type != MessageType::Candlestick {
+        return Ok(());
+    }
+    match period.parse::<u64>() {
+        Ok(seconds) if seconds > 0 => Ok(()),
+        _ if period.is_empty() => Err("A candlestick feed needs a period".to_string()),
+        _ => Err(format!("Invalid period: {}", period)),
+    }
+}
+
+impl CrawlerConfig {
+    pub fn new(toml_data: &str) -> Result<Self, String> {
+        toml::from_str(toml_data).map_err(|e| format!("Invalid config: {}", e))
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let content =
+            std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        CrawlerConfig::new(&content).map_err(|e| format!("{}, {}", path, e))
+    }
+
+    /// Resolve all feeds, a candlestick feed with several periods becomes
+    /// one feed per period.
+    pub fn feeds(&self) -> Result<Vec<Feed>, String> {
+        let mut feeds = Vec::new();
+        for feed in self.feeds.iter() {
+            feeds.extend(feed.resolve()?);
+        }
+        Ok(feeds)
+    }
+}
+
+impl FeedConfig {
+    pub fn resolve(&self) -> Result<Vec<Feed>, String> {
+        let market_type = MarketType::from_str(&self.market_type)
+            .map_err(|_| format!("Unknown market type: {}", self.market_type))?;
+        let msg_type = MessageType::from_str(&self.msg_type)
+            .map_err(|_| format!("Unknown msg type: {}", self.msg_type))?;
+        let bar_periods = self
+            .bar_periods
+            .iter()
+            .map(|period| {
+                parse_period(period).ok_or_else(|| format!("Invalid bar period: {}", period))
+            })
+            .collect::<Result<Vec<u64>, String>>()?;
+        let symbols = if self.symbols.is_empty() {
+            None
+        } else {
+            Some(self.symbols.clone())
+        };
+
+        let periods = if self.periods.is_empty() {
+            vec![String::new()]
+        } else {
+            self.periods.clone()
+        };
+        for period in periods.iter() {
+            check_period(msg_type, period)?;
+        }
+
+        Ok(periods
+            .into_iter()
+            .map(|period| Feed {
+                exchange: self.exchange.clone(),
+                market_type,
+                msg_type,
+                symbols: symbols.clone(),
+                period,
+                bar_periods: bar_periods.clone(),
+                normalized_topics: self.normalized_topics,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feeds(toml_data: &str) -> Result<Vec<Feed>, String> {
+        CrawlerConfig::new(toml_data)?.feeds()
+    }
+
+    #[test]
+    fn resolves_multiple_feeds() {
+        let feeds = feeds(
+            r#"
+            data_dir = "/carbonbot_data"
+
+            [[feeds]]
+            exchange = "binance"
+            market_type = "spot"
+            msg_type = "trade"
+            symbols = ["BTCUSDT", "ETHUSDT"]
+            bar_periods = ["1s", "1m"]
+
+            [[feeds]]
+            exchange = "bitmex"
+            market_type = "inverse_swap"
+            msg_type = "l2_event"
+            normalized_topics = true
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(feeds.len(), 2);
+        assert_eq!(feeds[0].exchange, "binance");
+        assert_eq!(feeds[0].market_type, MarketType::Spot);
+        assert_eq!(feeds[0].msg_type, MessageType::Trade);
+        assert_eq!(
+            feeds[0].symbols,
+            Some(vec!["BTCUSDT".to_string(), "ETHUSDT".to_string()])
+        );
+        assert_eq!(feeds[0].bar_periods, vec![1, 60]);
+        assert!(!feeds[0].normalized_topics);
+
+        assert_eq!(feeds[1].market_type, MarketType::InverseSwap);
+        assert_eq!(feeds[1].msg_type, MessageType::L2Event);
+        assert_eq!(feeds[1].symbols, None);
+        assert_eq!(feeds[1].period, "");
+        assert!(feeds[1].normalized_topics);
+    }
+
+    #[test]
+    fn expands_one_feed_per_period() {
+        let feeds = feeds(
+            r#"
+            [[feeds]]
+            exchange = "binance"
+            market_type = "spot"
+            msg_type = "candlestick"
+            symbols = ["BTCUSDT"]
+            periods = ["60", "300"]
+            "#,
+        )
+        .unwrap();
+
+        let periods: Vec<&str> = feeds.iter().map(|feed| feed.period.as_str()).collect();
+        assert_eq!(periods, vec!["60", "300"]);
+        assert!(feeds
+            .iter()
+            .all(|feed| feed.msg_type == MessageType::Candlestick));
+    }
+
+    fn feed(market_type: &str, msg_type: &str) -> FeedConfig {
+        FeedConfig {
+            exchange: "binance".to_string(),
+            market_type: market_type.to_string(),
+            msg_type: msg_type.to_string(),
+            symbols: vec!["BTCUSDT".to_string()],
+            periods: Vec::new(),
+            bar_periods: Vec::new(),
+            normalized_topics: false,
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_market_and_msg_types() {
+        assert_eq!(
+            feed("moon", "trade").resolve().unwrap_err(),
+            "Unknown market type: moon"
+        );
+        assert_eq!(
+            feed("spot", "gossip").resolve().unwrap_err(),
+            "Unknown msg type: gossip"
+        );
+    }
+
+    #[test]
+    fn rejects_bad_candlestick_periods() {
+        let mut candlestick = feed("spot", "candlestick");
+        assert_eq!(
+            candlestick.resolve().unwrap_err(),
+            "A candlestick feed needs a period"
+        );
+        for period in ["", "1m", "abc", "0"] {
+            candlestick.periods = vec!["60".to_string(), period.to_string()];
+            assert!(candlestick.resolve().is_err(), "{:?}", period);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_bar_periods() {
+        let mut trade = feed("spot", "trade");
+        for period in ["", "abc", "0s", "1w"] {
+            trade.bar_periods = vec!["1m".to_string(), period.to_string()];
+            assert_eq!(
+                trade.resolve().unwrap_err(),
+                format!("Invalid bar period: {}", period)
+            );
+        }
+    }
+}
@@ -0,0 +1,412 @@
+use crate::misc_crawlers::create_conversion_thread;
+use crate::shutdown::{shutdown, shutdown_requested};
+use crate::writers::Sinks;
+
+use crypto_crawler::{fetch_symbols_retry, MarketType, Message, MessageType};
+use crypto_market_topic::{Topic, TopicType};
+use crypto_ws_client::*;
+use futures::{future::BoxFuture, FutureExt};
+use log::*;
+use std::{
+    collections::HashSet,
+    sync::{mpsc::Sender, Arc},
+    time::Duration,
+};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+/// A change of the symbols of a market, found by [`discover_symbols`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SymbolChange {
+    Listed(Vec<String>),
+    Delisted(Vec<String>),
+}
+
+type Client = Arc<dyn WSClient + Send + Sync>;
+type Connect = fn(Sender<String>) -> BoxFuture<'static, Client>;
+
+// The websocket client of a market, `None` if discovery doesn't support it
+fn connector(exchange: &str, market_type: MarketType) -> Option<Connect> {
+    macro_rules! connect {
+        ($client:ident) => {
+            |tx| async move { Arc::new($client::new(tx, None).await) as Client }.boxed()
+        };
+    }
+
+    let connect: Connect = match (exchange, market_type) {
+        ("binance", MarketType::Spot) => connect!(BinanceSpotWSClient),
+        ("binance", MarketType::InverseFuture | MarketType::InverseSwap) => {
+            connect!(BinanceInverseWSClient)
+        }
+        ("binance", MarketType::LinearFuture | MarketType::LinearSwap) => {
+            connect!(BinanceLinearWSClient)
+        }
+        ("bitmex", _) => connect!(BitmexWSClient),
+        ("bybit", MarketType::InverseFuture | MarketType::InverseSwap) => {
+            connect!(BybitInverseWSClient)
+        }
+        ("bybit", MarketType::LinearSwap) => connect!(BybitLinearSwapWSClient),
+        ("deribit", _) => connect!(DeribitWSClient),
+        ("gate", MarketType::Spot) => connect!(GateSpotWSClient),
+        ("gate", MarketType::InverseSwap) => connect!(GateInverseSwapWSClient),
+        ("gate", MarketType::LinearSwap) => connect!(GateLinearSwapWSClient),
+        ("huobi", MarketType::Spot) => connect!(HuobiSpotWSClient),
+        ("huobi", MarketType::InverseFuture) => connect!(HuobiFutureWSClient),
+        ("huobi", MarketType::InverseSwap) => connect!(HuobiInverseSwapWSClient),
+        ("huobi", MarketType::LinearSwap) => connect!(HuobiLinearSwapWSClient),
+        ("huobi", MarketType::EuropeanOption) => connect!(HuobiOptionWSClient),
+        ("kraken", MarketType::Spot) => connect!(KrakenSpotWSClient),
+        ("kraken", MarketType::InverseFuture | MarketType::InverseSwap) => {
+            connect!(KrakenFuturesWSClient)
+        }
+        ("okx", _) => connect!(OkxWSClient),
+        _ => return None,
+    };
+    Some(connect)
+}
+
+async fn subscribe(
+    client: &Client,
+    msg_type: MessageType,
+    period: &str,
+    symbols: &[String],
+) -> Result<(), String> {
+    match msg_type {
+        MessageType::Trade => client.subscribe_trade(symbols).await,
+        MessageType::BBO => client.subscribe_bbo(symbols).await,
+        MessageType::L2Event => client.subscribe_orderbook(symbols).await,
+        MessageType::L2TopK => client.subscribe_orderbook_topk(symbols).await,
+        MessageType::L3Event => client.subscribe_l3_orderbook(symbols).await,
+        MessageType::Ticker => client.subscribe_ticker(symbols).await,
+        MessageType::Candlestick => {
+            let interval = period
+                .parse::<usize>()
+                .map_err(|_| format!("Invalid candlestick period: {}", period))?;
+            let symbol_interval_list: Vec<(String, usize)> = symbols
+                .iter()
+                .map(|symbol| (symbol.clone(), interval))
+                .collect();
+            client.subscribe_candlestick(&symbol_interval_list).await
+        }
+        _ => return Err(format!("{} can not be subscribed to per symbol", msg_type)),
+    }
+    Ok(())
+}
+
+/// Whether a feed can follow new listings and delistings, which takes a
+/// websocket subscription per symbol.
+pub fn can_discover(exchange: &str, market_type: MarketType, msg_type: MessageType) -> bool {
+    matches!(
+        msg_type,
+        MessageType::Trade
+            | MessageType::BBO
+            | MessageType::L2Event
+            | MessageType::L2TopK
+            | MessageType::L3Event
+            | MessageType::Ticker
+            | MessageType::Candlestick
+    ) && connector(exchange, market_type).is_some()
+}
+
+// Connect a client and subscribe it to `symbols`, the client runs until its
+// run future is dropped or it is closed
+async fn start(
+    connect: Connect,
+    msg_type: MessageType,
+    period: &str,
+    symbols: &[String],
+    tx: Sender<String>,
+) -> Result<(Client, BoxFuture<'static, ()>), String> {
+    let client = connect(tx).await;
+    if let Err(err) = subscribe(&client, msg_type, period, symbols).await {
+        client.close();
+        return Err(err);
+    }
+    let running = client.clone();
+    Ok((client, async move { running.run().await }.boxed()))
+}
+
+/// Crawl `symbols` with one websocket client that follows `changes`, until the
+/// client exits or a shutdown is requested, which closes the client.
+///
+/// New listings are subscribed on the running client. crypto-ws-client can't
+/// unsubscribe per message type, so for delistings a client subscribed to the
+/// listed symbols only takes over, and the old one is closed.
+pub async fn crawl_discovered(
+    exchange: String,
+    market_type: MarketType,
+    msg_type: MessageType,
+    period: String,
+    mut symbols: Vec<String>,
+    tx: Sender<Message>,
+    mut changes: UnboundedReceiver<SymbolChange>,
+) {
+    let connect = match connector(&exchange, market_type) {
+        Some(connect) => connect,
+        None => {
+            error!("{} {} can not follow new listings", exchange, market_type);
+            return;
+        }
+    };
+    let tx = create_conversion_thread(exchange.clone(), msg_type, market_type, tx);
+    let (mut client, mut run) = match start(connect, msg_type, &period, &symbols, tx.clone()).await
+    {
+        Ok(started) => started,
+        Err(err) => {
+            error!("{} {} {}: {}", exchange, market_type, msg_type, err);
+            return;
+        }
+    };
+
+    loop {
+        let change = tokio::select! {
+            _ = &mut run => break,
+            _ = shutdown() => None,
+            change = changes.recv() => change,
+        };
+        match change {
+            Some(SymbolChange::Listed(listed)) => {
+                if let Err(err) = subscribe(&client, msg_type, &period, &listed).await {
+                    error!("{} {} {}: {}", exchange, market_type, msg_type, err);
+                }
+                symbols.extend(listed);
+            }
+            Some(SymbolChange::Delisted(delisted)) => {
+                symbols.retain(|symbol| !delisted.contains(symbol));
+                let (new_client, new_run) =
+                    match start(connect, msg_type, &period, &symbols, tx.clone()).await {
+                        Ok(started) => started,
+                        Err(err) => {
+                            error!("{} {} {}: {}", exchange, market_type, msg_type, err);
+                            break;
+                        }
+                    };
+                client.close();
+                run.await;
+                client = new_client;
+                run = new_run;
+            }
+            None if shutdown_requested() => {
+                client.close();
+                run.await;
+                return;
+            }
+            // discovery stopped, keep crawling the current symbols
+            None => {
+                run.await;
+                break;
+            }
+        }
+    }
+    client.close();
+}
+
+/// Listings, relistings and delistings of a market, sorted.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct SymbolDiff {
+    listed: Vec<String>,
+    relisted: Vec<String>,
+    delisted: Vec<String>,
+}
+
+// The crawled symbols of a market, and those delisted since, to tell
+// relistings from new listings
+struct SymbolTracker {
+    subscribed: HashSet<String>,
+    delisted: HashSet<String>,
+}
+
+impl SymbolTracker {
+    fn new(symbols: Vec<String>) -> Self {
+        SymbolTracker {
+            subscribed: symbols.into_iter().collect(),
+            delisted: HashSet::new(),
+        }
+    }
+
+    // Diff the latest symbols with the crawled ones and take them over
+    fn update(&mut self, latest: &HashSet<String>) -> SymbolDiff {
+        // an empty list is more likely an API failure than a delisting of everything
+        if latest.is_empty() {
+            return SymbolDiff::default();
+        }
+
+        let (mut relisted, mut listed): (Vec<String>, Vec<String>) = latest
+            .difference(&self.subscribed)
+            .cloned()
+            .partition(|symbol| self.delisted.contains(symbol));
+        let mut delisted: Vec<String> = self.subscribed.difference(latest).cloned().collect();
+        listed.sort();
+        relisted.sort();
+        delisted.sort();
+
+        for symbol in listed.iter().chain(relisted.iter()) {
+            self.delisted.remove(symbol);
+            self.subscribed.insert(symbol.clone());
+        }
+        for symbol in delisted.iter() {
+            self.subscribed.remove(symbol);
+            self.delisted.insert(symbol.clone());
+        }
+
+        SymbolDiff {
+            listed,
+            relisted,
+            delisted,
+        }
+    }
+}
+
+/// Re-fetch the symbols of a market every `interval` and diff them with the
+/// crawled ones.
+///
+/// Every change is sent to `changes`, and to `sinks` as JSON on the
+/// `exchange_market_type_control_msg_type` topic. Returns once `changes` is
+/// closed, i.e. the crawler has exited.
+pub async fn discover_symbols(
+    exchange: String,
+    market_type: MarketType,
+    msg_type: MessageType,
+    symbols: Vec<String>,
+    interval: Duration,
+    changes: UnboundedSender<SymbolChange>,
+    sinks: Sinks,
+) {
+    let topic = Topic::new(
+        &exchange,
+        market_type,
+        TopicType::Control,
+        &msg_type.to_string(),
+    );
+    sinks.open(&topic);
+    let mut tracker = SymbolTracker::new(symbols);
+
+    loop {
+        tokio::time::sleep(interval).await;
+        if changes.is_closed() {
+            return;
+        }
+
+        let exchange_clone = exchange.clone();
+        let latest: HashSet<String> = match tokio::task::spawn_blocking(move || {
+            fetch_symbols_retry(&exchange_clone, market_type)
+        })
+        .await
+        {
+            Ok(symbols) => symbols.into_iter().collect(),
+            Err(err) => {
+                error!(
+                    "failed to fetch symbols of {} {}: {}",
+                    exchange, market_type, err
+                );
+                continue;
+            }
+        };
+        let SymbolDiff {
+            listed,
+            relisted,
+            delisted: newly_delisted,
+        } = tracker.update(&latest);
+
+        let mut new_symbols = listed.clone();
+        new_symbols.extend(relisted.iter().cloned());
+        if !new_symbols.is_empty() {
+            info!(
+                "{} {} {} new symbols: {:?}",
+                exchange, market_type, msg_type, new_symbols
+            );
+            if changes.send(SymbolChange::Listed(new_symbols)).is_err() {
+                return;
+            }
+        }
+        if !newly_delisted.is_empty() {
+            info!(
+                "{} {} {} delisted symbols: {:?}",
+                exchange, market_type, msg_type, newly_delisted
+            );
+            if changes
+                .send(SymbolChange::Delisted(newly_delisted.clone()))
+                .is_err()
+            {
+                return;
+            }
+        }
+
+        let now = chrono::Utc::now().timestamp_millis();
+        for (event, symbols) in [
+            ("listed", listed),
+            ("relisted", relisted),
+            ("delisted", newly_delisted),
+        ] {
+            if symbols.is_empty() {
+                continue;
+            }
+            let event = serde_json::json!({
+                "exchange": exchange,
+                "market_type": market_type.to_string(),
+                "msg_type": msg_type.to_string(),
+                "event": event,
+                "symbols": symbols,
+                "timestamp": now,
+            })
+            .to_string();
+            sinks.send(topic.clone(), event.into_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbols(symbols: &[&str]) -> Vec<String> {
+        symbols.iter().map(|symbol| symbol.to_string()).collect()
+    }
+
+    fn update(tracker: &mut SymbolTracker, latest: &[&str]) -> SymbolDiff {
+        tracker.update(&symbols(latest).into_iter().collect())
+    }
+
+    #[test]
+    fn finds_listings_and_delistings() {
+        let mut tracker = SymbolTracker::new(symbols(&["BTCUSDT", "ETHUSDT"]));
+        assert_eq!(
+            update(&mut tracker, &["BTCUSDT", "ETHUSDT"]),
+            SymbolDiff::default()
+        );
+        assert_eq!(
+            update(&mut tracker, &["BTCUSDT", "SOLUSDT", "ADAUSDT"]),
+            SymbolDiff {
+                listed: symbols(&["ADAUSDT", "SOLUSDT"]),
+                relisted: vec![],
+                delisted: symbols(&["ETHUSDT"]),
+            }
+        );
+    }
+
+    #[test]
+    fn tells_relistings_from_listings() {
+        let mut tracker = SymbolTracker::new(symbols(&["BTCUSDT", "ETHUSDT"]));
+        update(&mut tracker, &["BTCUSDT"]);
+        assert_eq!(
+            update(&mut tracker, &["BTCUSDT", "ETHUSDT", "SOLUSDT"]),
+            SymbolDiff {
+                listed: symbols(&["SOLUSDT"]),
+                relisted: symbols(&["ETHUSDT"]),
+                delisted: vec![],
+            }
+        );
+        // relisted once, a later listing is a change no more
+        assert_eq!(
+            update(&mut tracker, &["BTCUSDT", "ETHUSDT", "SOLUSDT"]),
+            SymbolDiff::default()
+        );
+    }
+
+    #[test]
+    fn ignores_empty_responses() {
+        let mut tracker = SymbolTracker::new(symbols(&["BTCUSDT"]));
+        assert_eq!(update(&mut tracker, &[]), SymbolDiff::default());
+        // still crawled, so not listed again
+        assert_eq!(update(&mut tracker, &["BTCUSDT"]), SymbolDiff::default());
+    }
+}
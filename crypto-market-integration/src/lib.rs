@@ -1,5 +1,22 @@
+pub(crate) mod config;
+pub(crate) mod discovery;
+pub(crate) mod metrics;
 pub(crate) mod misc_crawlers;
+pub(crate) mod shutdown;
+pub(crate) mod supervisor;
+pub(crate) mod watchdog;
 pub(crate) mod writers;
 
+pub use config::{
+    check_period, parse_period, parse_sinks, CrawlerConfig, Feed, FeedConfig, Outputs,
+};
+pub use discovery::{can_discover, crawl_discovered, discover_symbols, SymbolChange};
+pub use metrics::{render_metrics, serve_metrics};
 pub use misc_crawlers::crawl_other;
-pub use writers::create_writer_threads;
+pub use shutdown::{handle_signals, shutdown, shutdown_requested, shutdown_timeout};
+pub use supervisor::{run_supervisor, SupervisorConfig, WorkerConfig, WorkerState, WorkerStatus};
+pub use watchdog::{report_exit, start_watchdog, WatchdogConfig};
+pub use writers::{
+    create_writer_threads, encode_topics, CaptureReader, CaptureWriter, Instrument, MessageQueue,
+    ParseError, QueuePolicy, Sink, SinkKind, SinkMessage, Sinks,
+};
@@ -1,42 +1,151 @@
 use clap::clap_app;
 use crypto_crawler::*;
-use crypto_market_integration::{crawl_other, create_writer_threads};
+use crypto_market_integration::{
+    can_discover, check_period, crawl_discovered, crawl_other, create_writer_threads,
+    discover_symbols, handle_signals, parse_period, parse_sinks, report_exit, run_supervisor,
+    serve_metrics, shutdown, shutdown_timeout, start_watchdog, CrawlerConfig, Feed, Outputs, Sinks,
+    SupervisorConfig, WatchdogConfig,
+};
+use crypto_market_topic::Transport;
 use crypto_market_type::MarketType;
 use crypto_msg_type::MessageType;
 use log::*;
-use std::{str::FromStr, sync::Arc};
+use std::{str::FromStr, sync::Arc, time::Duration};
 
-pub async fn crawl(
-    exchange: &'static str,
-    market_type: MarketType,
-    msg_type: MessageType,
-    data_dir: Option<String>,
-    redis_url: Option<String>,
-    symbols: Option<&[String]>,
-    period: String,
-) {
+/// Crawl one feed until its crawler exits or a shutdown is requested, then
+/// flush its writers. Returns whether the feed was shut down cleanly.
+pub async fn crawl(feed: Feed, outputs: Outputs) -> bool {
     // if data_dir.is_none() && redis_url.is_none() {
     //     error!("Both DATA_DIR and REDIS_URL are not set");
     //     return;
     // }
+    let Feed {
+        exchange,
+        market_type,
+        msg_type,
+        symbols,
+        period,
+        bar_periods,
+        normalized_topics,
+    } = feed;
+
+    // only a market crawled as a whole follows new listings
+    let discover = symbols.is_none() && can_discover(&exchange, market_type, msg_type);
+    let symbols = match symbols {
+        Some(symbols) => symbols,
+        None if msg_type == MessageType::Other || msg_type == MessageType::OpenInterest => {
+            vec![]
+        }
+        None => {
+            let exchange = exchange.clone();
+            tokio::task::spawn_blocking(move || fetch_symbols_retry(&exchange, market_type))
+                .await
+                .unwrap()
+        }
+    };
     let (tx, rx) = std::sync::mpsc::channel::<Message>();
 
     let period = Arc::new(period);
 
-    let period_arc = period.clone();
-    tokio::task::spawn(async move {
-        let writer_threads = create_writer_threads(
-            rx,
-            data_dir,
-            redis_url,
-            exchange,
-            market_type,
-            msg_type,
-            period_arc,
-        );
-        futures::future::join_all(writer_threads.into_iter()).await;
-    });
+    let (sinks, writer_threads) = match create_writer_threads(
+        rx,
+        queue_capacity(),
+        outputs,
+        period.clone(),
+        bar_periods,
+        normalized_topics,
+    ) {
+        Ok(writers) => writers,
+        Err(err) => {
+            error!("{}", err);
+            return false;
+        }
+    };
+    let writers = tokio::task::spawn(futures::future::join_all(writer_threads.into_iter()));
+
+    let feed = format!("{} {} {} {}", exchange, market_type, msg_type, period);
+    let (mut crawler, discovery) = match symbol_refresh_interval() {
+        Some(interval) if discover => {
+            let (tx_changes, rx_changes) = tokio::sync::mpsc::unbounded_channel();
+            let discovery = tokio::task::spawn(discover_symbols(
+                exchange.clone(),
+                market_type,
+                msg_type,
+                symbols.clone(),
+                interval,
+                tx_changes,
+                sinks,
+            ));
+            let crawler = tokio::task::spawn(crawl_discovered(
+                exchange,
+                market_type,
+                msg_type,
+                period.to_string(),
+                symbols,
+                tx,
+                rx_changes,
+            ));
+            (crawler, Some(discovery))
+        }
+        _ => {
+            let crawler = tokio::task::spawn(async move {
+                crawl_symbols(
+                    &exchange,
+                    market_type,
+                    msg_type,
+                    &period,
+                    &symbols,
+                    tx,
+                    sinks,
+                )
+                .await;
+            });
+            (crawler, None)
+        }
+    };
+    let requested = tokio::select! {
+        _ = &mut crawler => {
+            report_exit(&feed).await;
+            false
+        }
+        _ = shutdown() => {
+            if discovery.is_some() {
+                // closes its websocket clients on its own
+                let _ = tokio::time::timeout(shutdown_timeout(), &mut crawler).await;
+            }
+            // drops the websocket clients of the crawler, the REST crawlers
+            // on blocking threads can't be aborted and fail their next send
+            crawler.abort();
+            true
+        }
+    };
+    if let Some(discovery) = discovery {
+        discovery.abort();
+    }
+
+    // the queue is drained of the messages already crawled, then closed
+    match tokio::time::timeout(shutdown_timeout(), writers).await {
+        Ok(_) => {
+            info!("{} flushed", feed);
+            requested
+        }
+        Err(_) => {
+            error!("{} timed out flushing its writers", feed);
+            false
+        }
+    }
+}
 
+async fn crawl_symbols(
+    exchange: &str,
+    market_type: MarketType,
+    msg_type: MessageType,
+    period: &str,
+    symbols: &[String],
+    tx: std::sync::mpsc::Sender<Message>,
+    sinks: Sinks,
+) {
+    let symbols: Option<&[String]> = Some(symbols);
     if msg_type == MessageType::Candlestick {
         let mut symbol_interval_list = Vec::new();
         if let Some(arry) = symbols {
@@ -50,11 +159,12 @@ pub async fn crawl(
         let symbol_interval_list = Some(symbol_interval_list);
         crawl_candlestick(exchange, market_type, symbol_interval_list, tx).await;
     } else if msg_type == MessageType::OpenInterest {
-        tokio::task::spawn_blocking(move || crawl_open_interest(exchange, market_type, tx))
+        let exchange = exchange.to_string();
+        tokio::task::spawn_blocking(move || crawl_open_interest(&exchange, market_type, tx))
             .await
             .unwrap();
     } else if msg_type == MessageType::Other {
-        crawl_other(exchange, market_type, tx).await;
+        crawl_other(exchange, market_type, tx, sinks).await;
     } else {
         match msg_type {
             MessageType::BBO => {
@@ -75,10 +185,13 @@ pub async fn crawl(
                 } else {
                     vec![]
                 };
+                let exchange = exchange.to_string();
                 tokio::task::spawn_blocking(move || {
                     let symbols_local = symbols;
-                    crawl_l2_snapshot(exchange, market_type, Some(&symbols_local), tx)
-                });
+                    crawl_l2_snapshot(&exchange, market_type, Some(&symbols_local), tx)
+                })
+                .await
+                .unwrap();
             }
             MessageType::L2TopK => {
                 crawl_l2_topk(exchange, market_type, symbols, tx).await;
@@ -89,10 +202,13 @@ pub async fn crawl(
                 } else {
                     vec![]
                 };
+                let exchange = exchange.to_string();
                 tokio::task::spawn_blocking(move || {
                     let symbols_local = symbols;
-                    crawl_l3_snapshot(exchange, market_type, Some(&symbols_local), tx)
-                });
+                    crawl_l3_snapshot(&exchange, market_type, Some(&symbols_local), tx)
+                })
+                .await
+                .unwrap();
             }
             MessageType::Ticker => {
                 crawl_ticker(exchange, market_type, symbols, tx).await;
@@ -105,27 +221,165 @@ pub async fn crawl(
     }
 }
 
+// SYMBOL_REFRESH_SECS, 0 by default, which disables symbol discovery
+fn symbol_refresh_interval() -> Option<Duration> {
+    let secs = std::env::var("SYMBOL_REFRESH_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(0);
+    if secs == 0 {
+        None
+    } else {
+        Some(Duration::from_secs(secs))
+    }
+}
+
+// QUEUE_CAPACITY, messages waiting for the writer of a feed, 10000 by default
+fn queue_capacity() -> usize {
+    std::env::var("QUEUE_CAPACITY")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|capacity| *capacity > 0)
+        .unwrap_or(10000)
+}
+
+/// Crawl every feed concurrently, each with its own writer pipeline. Returns
+/// whether every feed was shut down cleanly.
+pub async fn crawl_feeds(feeds: Vec<Feed>, outputs: Outputs) -> bool {
+    let mut tasks = Vec::new();
+    for feed in feeds {
+        let outputs = outputs.clone();
+        tasks.push(tokio::task::spawn(async move {
+            info!(
+                "crawling {} {} {} {}",
+                feed.exchange, feed.market_type, feed.msg_type, feed.period
+            );
+            crawl(feed, outputs).await
+        }));
+    }
+    futures::future::join_all(tasks.into_iter())
+        .await
+        .into_iter()
+        .all(|clean| clean.unwrap_or(false))
+}
+
+// --sinks, or the comma separated SINKS environment variable
+fn sink_names(matches: &clap::ArgMatches) -> Option<Vec<String>> {
+    match matches.values_of("SINKS") {
+        Some(v) => Some(v.map(|v| v.to_string()).collect()),
+        None => std::env::var("SINKS")
+            .ok()
+            .map(|v| v.split(',').map(|v| v.trim().to_string()).collect()),
+    }
+}
+
+fn env_var(name: &str) -> Option<String> {
+    if std::env::var(name).is_err() {
+        info!("The {} environment variable does not exist", name);
+        None
+    } else {
+        let url = std::env::var(name).unwrap();
+        Some(url)
+    }
+}
+
 #[tokio::main(flavor = "multi_thread")]
 async fn main() {
     env_logger::init();
+    handle_signals();
+    Transport::init_or_exit();
 
     let matches: clap::ArgMatches = clap_app!(quic =>
             (about: "use save file")
-            (@arg EXCHANGE:     +required "exchange")
-            (@arg MARKET_TYPE:  +required "market_type")
-            (@arg MSG_TYPE:     +required "msg_type")
+            (@arg EXCHANGE: "exchange")
+            (@arg MARKET_TYPE: "market_type")
+            (@arg MSG_TYPE: "msg_type")
             (@arg PERIOD: "period")
             (@arg COMMA_SEPERATED_SYMBOLS: -c --comma_seperated_symbols +use_delimiter "comma_seperated_symbols")
+            (@arg BAR_PERIODS: -b --bar_periods +takes_value +use_delimiter "comma_seperated periods of candlesticks built from trades, e.g. 1s,1m,1h")
+            (@arg NORMALIZED_TOPICS: -n --normalized_topics "also publish on normalized_ topics keyed by pairs like BTC/USDT")
+            (@arg CONFIG: -f --config +takes_value "config file listing the feeds to crawl")
+            (@arg CAPTURE_DIR: --capture_dir +takes_value "capture the raw messages to this directory, to be re-parsed later")
+            (@arg SINKS: -s --sinks +takes_value +use_delimiter "comma_seperated sinks of the parsed messages: zeromq, redis, file, stdout")
+            (@subcommand supervisor =>
+                (about: "launch the workers of a config and restart them when they exit")
+                (@arg CONFIG: +required "supervisor config file")
+            )
     )
     .get_matches();
 
     // let args = vec!["carbonbot".to_string(), "binance".to_string(), "spot".to_string(),
     //                               "l2_topk".to_string(),  "2".to_string(),  "BTCUSDT".to_string()];
 
-    let exchange = matches.value_of("EXCHANGE").unwrap().to_string();
-    let exchange: &'static str = Box::leak(exchange.clone().into_boxed_str());
+    if let Some(matches) = matches.subcommand_matches("supervisor") {
+        let config = match SupervisorConfig::from_file(matches.value_of("CONFIG").unwrap()) {
+            Ok(config) => config,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        run_supervisor(config).await;
+        return;
+    }
+
+    if let Some(config_file) = matches.value_of("CONFIG") {
+        let config = match CrawlerConfig::from_file(config_file) {
+            Ok(config) => config,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        let feeds = match config.feeds() {
+            Ok(feeds) => feeds,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        let redis_url = config.redis_url.or_else(|| env_var("REDIS_URL"));
+        let sinks = match parse_sinks(sink_names(&matches).or(config.sinks), redis_url.as_deref()) {
+            Ok(sinks) => sinks,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        let outputs = Outputs {
+            data_dir: config.data_dir.or_else(|| env_var("DATA_DIR")),
+            redis_url,
+            capture_dir: matches
+                .value_of("CAPTURE_DIR")
+                .map(|v| v.to_string())
+                .or(config.capture_dir)
+                .or_else(|| env_var("CAPTURE_DIR")),
+            sinks,
+            sink_dir: config.sink_dir.or_else(|| env_var("SINK_DIR")),
+        };
+        if let Some(metrics_addr) = config.metrics_addr.or_else(|| env_var("METRICS_ADDR")) {
+            tokio::task::spawn(serve_metrics(metrics_addr));
+        }
+        if let Some(watchdog) = config.watchdog.or_else(WatchdogConfig::from_env) {
+            start_watchdog(watchdog);
+        }
+
+        let clean = crawl_feeds(feeds, outputs).await;
+        std::process::exit(if clean { 0 } else { 1 });
+    }
+
+    let (exchange, market_type_str, msg_type_str) = match (
+        matches.value_of("EXCHANGE"),
+        matches.value_of("MARKET_TYPE"),
+        matches.value_of("MSG_TYPE"),
+    ) {
+        (Some(exchange), Some(market_type), Some(msg_type)) => (exchange, market_type, msg_type),
+        _ => {
+            println!("EXCHANGE, MARKET_TYPE and MSG_TYPE are required without --config");
+            return;
+        }
+    };
 
-    let market_type_str = matches.value_of("MARKET_TYPE").unwrap();
     let market_type = MarketType::from_str(market_type_str);
     if market_type.is_err() {
         println!("Unknown market type: {}", market_type_str);
@@ -133,7 +387,6 @@ async fn main() {
     }
     let market_type = market_type.unwrap();
 
-    let msg_type_str = matches.value_of("MSG_TYPE").unwrap();
     let msg_type = MessageType::from_str(msg_type_str);
     if msg_type.is_err() {
         println!("Unknown msg type: {}", msg_type_str);
@@ -141,31 +394,55 @@ async fn main() {
     }
     let msg_type = msg_type.unwrap();
 
-    let data_dir = if std::env::var("DATA_DIR").is_err() {
-        info!("The DATA_DIR environment variable does not exist");
-        None
-    } else {
-        let url = std::env::var("DATA_DIR").unwrap();
-        Some(url)
-    };
+    let redis_url = env_var("REDIS_URL");
 
-    let redis_url = if std::env::var("REDIS_URL").is_err() {
-        info!("The REDIS_URL environment variable does not exist");
-        None
-    } else {
-        let url = std::env::var("REDIS_URL").unwrap();
-        Some(url)
+    let sinks = match parse_sinks(sink_names(&matches), redis_url.as_deref()) {
+        Ok(sinks) => sinks,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
     };
+    let outputs = Outputs {
+        data_dir: env_var("DATA_DIR"),
+        redis_url,
+        capture_dir: matches
+            .value_of("CAPTURE_DIR")
+            .map(|v| v.to_string())
+            .or_else(|| env_var("CAPTURE_DIR")),
+        sinks,
+        sink_dir: env_var("SINK_DIR"),
+    };
+
+    if let Some(metrics_addr) = env_var("METRICS_ADDR") {
+        tokio::task::spawn(serve_metrics(metrics_addr));
+    }
+    if let Some(watchdog) = WatchdogConfig::from_env() {
+        start_watchdog(watchdog);
+    }
 
     let period = matches.value_of("PERIOD").unwrap_or("");
+    if let Err(err) = check_period(msg_type, period) {
+        println!("{}", err);
+        return;
+    }
 
-    let specified_symbols = if let Some(v) = matches.values_of("COMMA_SEPERATED_SYMBOLS") {
+    let specified_symbols = matches.values_of("COMMA_SEPERATED_SYMBOLS").map(|v| {
         v.collect::<Vec<&str>>()
             .iter()
             .map(|v| v.to_string())
             .collect()
-    } else {
-        fetch_symbols_retry(exchange, market_type)
+    });
+
+    let bar_periods = match matches.values_of("BAR_PERIODS") {
+        Some(v) => match v.map(parse_period).collect::<Option<Vec<u64>>>() {
+            Some(bar_periods) => bar_periods,
+            None => {
+                println!("Invalid bar periods");
+                return;
+            }
+        },
+        None => vec![],
     };
 
     // let mut specified_symbols = Vec::new();
@@ -175,24 +452,15 @@ async fn main() {
     //     panic!("The environment variable DATA_DIR and REDIS_URL are not set, at least one of them should be set");
     // }
 
-    let pid = std::process::id();
-    // write pid to file
-    {
-        let mut dir = std::env::temp_dir()
-            .join("carbonbot-pids")
-            .join(msg_type_str);
-        let _ = std::fs::create_dir_all(&dir);
-        dir.push(format!("{}.{}.{}", exchange, market_type_str, msg_type_str));
-        std::fs::write(dir.as_path(), pid.to_string()).expect("Unable to write pid to file");
-    }
-    crawl(
-        exchange,
+    let feed = Feed {
+        exchange: exchange.to_string(),
         market_type,
         msg_type,
-        data_dir,
-        redis_url,
-        Some(&specified_symbols),
-        period.to_string(),
-    )
-    .await;
+        symbols: specified_symbols,
+        period: period.to_string(),
+        bar_periods,
+        normalized_topics: matches.is_present("NORMALIZED_TOPICS"),
+    };
+    let clean = crawl(feed, outputs).await;
+    std::process::exit(if clean { 0 } else { 1 });
 }
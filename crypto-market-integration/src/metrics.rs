@@ -0,0 +1,355 @@
+use lazy_static::lazy_static;
+use log::*;
+use std::{collections::BTreeMap, fmt::Write, sync::Mutex};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// Counters of one topic.
+#[derive(Default)]
+struct TopicMetrics {
+    messages: u64,
+    bytes: u64,
+    parse_failures: u64,
+    // updates replaced by a newer one, or dropped, while the writer queue was behind
+    conflated: u64,
+    dropped: u64,
+    // milliseconds
+    last_message_at: i64,
+    last_latency: i64,
+    latency_sum: i64,
+    latency_count: u64,
+    // messages of the current rate window
+    window_start: i64,
+    window_messages: u64,
+    rate: f64,
+}
+
+impl TopicMetrics {
+    fn update_rate(&mut self, now: i64) {
+        let elapsed = now - self.window_start;
+        if elapsed >= 1000 {
+            self.rate = self.window_messages as f64 * 1000.0 / elapsed as f64;
+            self.window_start = now;
+            self.window_messages = 0;
+        }
+    }
+}
+
+lazy_static! {
+    static ref METRICS: Mutex<BTreeMap<String, TopicMetrics>> = Mutex::new(BTreeMap::new());
+}
+
+fn now_ms() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+fn with_topic<F: FnOnce(&mut TopicMetrics, i64)>(topic: &str, f: F) {
+    let now = now_ms();
+    let mut metrics = METRICS.lock().unwrap();
+    let entry = metrics
+        .entry(topic.to_string())
+        .or_insert_with(|| TopicMetrics {
+            window_start: now,
+            ..Default::default()
+        });
+    f(entry, now);
+}
+
+/// Record a message published on `topic`.
+///
+/// `latency` is the time from the exchange timestamp of the message to when it
+/// was received, for messages with a timestamp of when they were sent.
+pub(crate) fn record_message(topic: &str, bytes: usize, latency: Option<i64>) {
+    with_topic(topic, |metrics, now| {
+        metrics.messages += 1;
+        metrics.bytes += bytes as u64;
+        metrics.last_message_at = now;
+        metrics.window_messages += 1;
+        metrics.update_rate(now);
+        if let Some(latency) = latency {
+            metrics.last_latency = latency;
+            metrics.latency_sum += latency;
+            metrics.latency_count += 1;
+        }
+    });
+}
+
+/// Record a message of `topic` that failed to parse.
+pub(crate) fn record_parse_failure(topic: &str) {
+    with_topic(topic, |metrics, _| metrics.parse_failures += 1);
+}
+
+/// Record an update of `topic` replaced by a newer one in the writer queue.
+pub(crate) fn record_conflated(topic: &str) {
+    with_topic(topic, |metrics, _| metrics.conflated += 1);
+}
+
+/// Record an update of `topic` dropped because the writer queue was full.
+pub(crate) fn record_dropped(topic: &str) {
+    with_topic(topic, |metrics, _| metrics.dropped += 1);
+}
+
+/// Health of one topic, as seen by the watchdog.
+pub(crate) struct TopicHealth {
+    pub topic: String,
+    /// Milliseconds, 0 if nothing was published yet
+    pub last_message_at: i64,
+    pub parse_failures: u64,
+}
+
+pub(crate) fn topic_health() -> Vec<TopicHealth> {
+    let metrics = METRICS.lock().unwrap();
+    metrics
+        .iter()
+        .map(|(topic, topic_metrics)| TopicHealth {
+            topic: topic.clone(),
+            last_message_at: topic_metrics.last_message_at,
+            parse_failures: topic_metrics.parse_failures,
+        })
+        .collect()
+}
+
+// Escape a label value of the Prometheus text format
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Render all metrics in the Prometheus text format.
+pub fn render_metrics() -> String {
+    let now = now_ms();
+    let mut metrics = METRICS.lock().unwrap();
+    for topic_metrics in metrics.values_mut() {
+        topic_metrics.update_rate(now);
+    }
+
+    let mut out = String::new();
+    let mut family =
+        |name: &str, kind: &str, help: &str, value: &dyn Fn(&TopicMetrics) -> String| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for (topic, topic_metrics) in metrics.iter() {
+                let _ = writeln!(
+                    out,
+                    "{}{{topic=\"{}\"}} {}",
+                    name,
+                    escape_label(topic),
+                    value(topic_metrics)
+                );
+            }
+        };
+
+    family(
+        "crawler_messages_total",
+        "counter",
+        "Messages published",
+        &|m| m.messages.to_string(),
+    );
+    family(
+        "crawler_messages_per_second",
+        "gauge",
+        "Messages published per second over the last second",
+        &|m| format!("{:.3}", m.rate),
+    );
+    family("crawler_bytes_total", "counter", "Bytes published", &|m| {
+        m.bytes.to_string()
+    });
+    family(
+        "crawler_parse_failures_total",
+        "counter",
+        "Messages that failed to parse",
+        &|m| m.parse_failures.to_string(),
+    );
+    family(
+        "crawler_conflated_total",
+        "counter",
+        "Updates replaced by a newer one of the same symbol in the writer queue",
+        &|m| m.conflated.to_string(),
+    );
+    family(
+        "crawler_dropped_total",
+        "counter",
+        "Updates dropped because the writer queue was full",
+        &|m| m.dropped.to_string(),
+    );
+    family(
+        "crawler_last_message_age_seconds",
+        "gauge",
+        "Seconds since the last message was published",
+        &|m| {
+            if m.last_message_at == 0 {
+                "NaN".to_string()
+            } else {
+                format!("{:.3}", (now - m.last_message_at) as f64 / 1000.0)
+            }
+        },
+    );
+    family(
+        "crawler_latency_milliseconds",
+        "gauge",
+        "Gap between the exchange timestamp and received_at of the last message",
+        &|m| m.last_latency.to_string(),
+    );
+    family(
+        "crawler_latency_sum_milliseconds",
+        "counter",
+        "Sum of the gaps between exchange timestamps and received_at",
+        &|m| m.latency_sum.to_string(),
+    );
+    family(
+        "crawler_latency_samples_total",
+        "counter",
+        "Messages with an exchange timestamp",
+        &|m| m.latency_count.to_string(),
+    );
+
+    out
+}
+
+async fn handle_connection(mut stream: TcpStream) -> std::io::Result<()> {
+    let mut buf = [0u8; 1024];
+    let n = stream.read(&mut buf).await?;
+    let request = String::from_utf8_lossy(&buf[..n]);
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+
+    let response = if path == "/metrics" || path == "/" {
+        let body = render_metrics();
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Serve the metrics over HTTP on `addr`, e.g. `0.0.0.0:9100`.
+pub async fn serve_metrics(addr: String) {
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("failed to serve metrics on {}: {}", addr, err);
+            return;
+        }
+    };
+    info!("serving metrics on http://{}/metrics", addr);
+    serve(listener).await
+}
+
+async fn serve(listener: TcpListener) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::task::spawn(async move {
+                    if let Err(err) = handle_connection(stream).await {
+                        debug!("metrics connection failed: {}", err);
+                    }
+                });
+            }
+            Err(err) => error!("failed to accept a metrics connection: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // metrics are global, every test records topics of its own
+    fn line<'a>(rendered: &'a str, name: &str, topic: &str) -> Option<&'a str> {
+        let prefix = format!("{}{{topic=\"{}\"}} ", name, topic);
+        rendered
+            .lines()
+            .find_map(|line| line.strip_prefix(prefix.as_str()))
+    }
+
+    #[test]
+    fn renders_every_family_per_topic() {
+        let topic = "binance_spot_trade_RENDERUSDT";
+        record_message(topic, 100, Some(20));
+        record_message(topic, 50, None);
+        record_parse_failure(topic);
+        record_conflated(topic);
+        record_dropped(topic);
+
+        let rendered = render_metrics();
+        assert!(rendered.contains("# HELP crawler_messages_total Messages published\n"));
+        assert!(rendered.contains("# TYPE crawler_messages_total counter\n"));
+        assert!(rendered.contains("# TYPE crawler_last_message_age_seconds gauge\n"));
+        assert_eq!(line(&rendered, "crawler_messages_total", topic), Some("2"));
+        assert_eq!(line(&rendered, "crawler_bytes_total", topic), Some("150"));
+        assert_eq!(
+            line(&rendered, "crawler_parse_failures_total", topic),
+            Some("1")
+        );
+        assert_eq!(line(&rendered, "crawler_conflated_total", topic), Some("1"));
+        assert_eq!(line(&rendered, "crawler_dropped_total", topic), Some("1"));
+        assert_eq!(
+            line(&rendered, "crawler_latency_milliseconds", topic),
+            Some("20")
+        );
+        assert_eq!(
+            line(&rendered, "crawler_latency_samples_total", topic),
+            Some("1")
+        );
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        record_parse_failure("odd\"topic\\\n");
+        let rendered = render_metrics();
+        assert_eq!(
+            line(
+                &rendered,
+                "crawler_parse_failures_total",
+                "odd\\\"topic\\\\\\n"
+            ),
+            Some("1")
+        );
+        // never published
+        assert_eq!(
+            line(
+                &rendered,
+                "crawler_last_message_age_seconds",
+                "odd\\\"topic\\\\\\n"
+            ),
+            Some("NaN")
+        );
+    }
+
+    async fn get(addr: std::net::SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn serves_the_metrics_over_http() {
+        let topic = "binance_spot_trade_SERVEUSDT";
+        record_message(topic, 10, None);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::task::spawn(serve(listener));
+
+        let response = get(addr, "/metrics").await;
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains("Content-Type: text/plain; version=0.0.4"));
+        assert!(head.contains(&format!("Content-Length: {}", body.len())));
+        assert_eq!(line(body, "crawler_messages_total", topic), Some("1"));
+
+        assert!(get(addr, "/other")
+            .await
+            .starts_with("HTTP/1.1 404 Not Found"));
+    }
+}
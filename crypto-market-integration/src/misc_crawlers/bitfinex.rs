@@ -0,0 +1,48 @@
+use std::sync::mpsc::Sender;
+
+use super::utils::{create_conversion_thread, run_with_reconnect};
+use crate::writers::Sinks;
+use crypto_crawler::Message;
+use crypto_market_type::MarketType;
+use crypto_msg_type::MessageType;
+use crypto_ws_client::*;
+
+/// The subscription commands of the Other feed, liquidations of every market
+/// at once, so it only runs as the `unknown` market.
+fn other_subscription(market_type: MarketType) -> Vec<String> {
+    match market_type {
+        MarketType::Unknown => {
+            vec![r#"{"event":"subscribe","channel":"status","key":"liq:global"}"#.to_string()]
+        }
+        _ => panic!("Unknown market_type {}", market_type),
+    }
+}
+
+pub(super) async fn crawl_other(market_type: MarketType, tx: Sender<Message>, sinks: Sinks) {
+    let commands = other_subscription(market_type);
+    let tx = create_conversion_thread("bitfinex".to_string(), MessageType::Other, market_type, tx);
+
+    run_with_reconnect("bitfinex", market_type, tx, sinks, commands, |tx| {
+        BitfinexWSClient::new(tx, None)
+    })
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subscribes_to_global_liquidations() {
+        assert_eq!(
+            other_subscription(MarketType::Unknown),
+            vec![r#"{"event":"subscribe","channel":"status","key":"liq:global"}"#]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown market_type")]
+    fn rejects_other_markets() {
+        other_subscription(MarketType::Spot);
+    }
+}
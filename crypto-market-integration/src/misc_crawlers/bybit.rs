@@ -1,21 +1,22 @@
 use std::sync::mpsc::Sender;
 
-use super::utils::create_conversion_thread;
+use super::utils::{create_conversion_thread, run_with_reconnect};
+use crate::writers::Sinks;
 use crypto_crawler::Message;
 use crypto_market_type::MarketType;
 use crypto_msg_type::MessageType;
 use crypto_ws_client::*;
 
-pub(super) async fn crawl_other(market_type: MarketType, tx: Sender<Message>) {
+pub(super) async fn crawl_other(market_type: MarketType, tx: Sender<Message>, sinks: Sinks) {
     let tx = create_conversion_thread("bybit".to_string(), MessageType::Other, market_type, tx);
     let commands = vec![r#"{"op":"subscribe","args":["insurance","liquidation"]}"#.to_string()];
 
     match market_type {
         MarketType::InverseFuture | MarketType::InverseSwap => {
-            let ws_client = BybitInverseWSClient::new(tx, None).await;
-            ws_client.send(&commands).await;
-            ws_client.run().await;
-            ws_client.close();
+            run_with_reconnect("bybit", market_type, tx, sinks, commands, |tx| {
+                BybitInverseWSClient::new(tx, None)
+            })
+            .await;
         }
         _ => panic!("Unknown market_type {}", market_type),
     }
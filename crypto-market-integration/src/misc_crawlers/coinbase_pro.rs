@@ -1,12 +1,13 @@
 use std::sync::mpsc::Sender;
 
-use super::utils::create_conversion_thread;
+use super::utils::{create_conversion_thread, run_with_reconnect};
+use crate::writers::Sinks;
 use crypto_crawler::Message;
 use crypto_market_type::MarketType;
 use crypto_msg_type::MessageType;
 use crypto_ws_client::*;
 
-pub(super) async fn crawl_other(market_type: MarketType, tx: Sender<Message>) {
+pub(super) async fn crawl_other(market_type: MarketType, tx: Sender<Message>, sinks: Sinks) {
     let tx = create_conversion_thread(
         "coinbase_pro".to_string(),
         MessageType::Other,
@@ -16,8 +17,8 @@ pub(super) async fn crawl_other(market_type: MarketType, tx: Sender<Message>) {
     let commands: Vec<String> =
         vec![r#"{"type": "subscribe","channels":[{ "name": "status"}]}"#.to_string()];
 
-    let ws_client = CoinbaseProWSClient::new(tx, None).await;
-    ws_client.send(&commands).await;
-    ws_client.run().await;
-    ws_client.close();
+    run_with_reconnect("coinbase_pro", market_type, tx, sinks, commands, |tx| {
+        CoinbaseProWSClient::new(tx, None)
+    })
+    .await;
 }
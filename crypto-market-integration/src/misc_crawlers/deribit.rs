@@ -0,0 +1,65 @@
+use std::sync::mpsc::Sender;
+
+use super::utils::{create_conversion_thread, run_with_reconnect};
+use crate::writers::Sinks;
+use crypto_crawler::Message;
+use crypto_market_type::MarketType;
+use crypto_msg_type::MessageType;
+use crypto_ws_client::*;
+
+/// The subscription commands of the Other feed, platform wide channels, so
+/// it only runs as the `unknown` market.
+fn other_subscription(market_type: MarketType) -> Vec<String> {
+    match market_type {
+        MarketType::Unknown => {
+            // instrument.state reports instruments created, started, settled and closed
+            let channels = [
+                "announcements",
+                "platform_state",
+                "instrument.state.any.any",
+            ];
+            vec![format!(
+                r#"{{"jsonrpc":"2.0","id":9527,"method":"public/subscribe","params":{{"channels":{}}}}}"#,
+                serde_json::to_string(&channels).unwrap()
+            )]
+        }
+        _ => panic!("Unknown market_type {}", market_type),
+    }
+}
+
+pub(super) async fn crawl_other(market_type: MarketType, tx: Sender<Message>, sinks: Sinks) {
+    let commands = other_subscription(market_type);
+    let tx = create_conversion_thread("deribit".to_string(), MessageType::Other, market_type, tx);
+
+    run_with_reconnect("deribit", market_type, tx, sinks, commands, |tx| {
+        DeribitWSClient::new(tx, None)
+    })
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subscribes_to_platform_channels() {
+        let commands = other_subscription(MarketType::Unknown);
+        assert_eq!(commands.len(), 1);
+        let command: serde_json::Value = serde_json::from_str(&commands[0]).unwrap();
+        assert_eq!(command["method"], "public/subscribe");
+        assert_eq!(
+            command["params"]["channels"],
+            serde_json::json!([
+                "announcements",
+                "platform_state",
+                "instrument.state.any.any"
+            ])
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown market_type")]
+    fn rejects_other_markets() {
+        other_subscription(MarketType::InverseFuture);
+    }
+}
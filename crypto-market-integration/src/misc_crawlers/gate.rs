@@ -0,0 +1,54 @@
+use std::sync::mpsc::Sender;
+
+use super::utils::{create_conversion_thread, run_with_reconnect};
+use crate::writers::Sinks;
+use crypto_crawler::Message;
+use crypto_market_type::MarketType;
+use crypto_msg_type::MessageType;
+use crypto_ws_client::*;
+
+// Gate checks `time`, so the command is built for every connection instead of
+// once for all reconnects
+fn subscribe_command() -> String {
+    format!(
+        r#"{{"time":{},"channel":"futures.public_liquidates","event":"subscribe","payload":["!all"]}}"#,
+        chrono::Utc::now().timestamp()
+    )
+}
+
+pub(super) async fn crawl_other(market_type: MarketType, tx: Sender<Message>, sinks: Sinks) {
+    let tx = create_conversion_thread("gate".to_string(), MessageType::Other, market_type, tx);
+
+    match market_type {
+        MarketType::InverseSwap => {
+            run_with_reconnect("gate", market_type, tx, sinks, vec![], |tx| async move {
+                let ws_client = GateInverseSwapWSClient::new(tx, None).await;
+                ws_client.send(&[subscribe_command()]).await;
+                ws_client
+            })
+            .await;
+        }
+        MarketType::LinearSwap => {
+            run_with_reconnect("gate", market_type, tx, sinks, vec![], |tx| async move {
+                let ws_client = GateLinearSwapWSClient::new(tx, None).await;
+                ws_client.send(&[subscribe_command()]).await;
+                ws_client
+            })
+            .await;
+        }
+        _ => panic!("Unknown market_type {}", market_type),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subscribes_with_the_current_time() {
+        let command: serde_json::Value = serde_json::from_str(&subscribe_command()).unwrap();
+        let now = chrono::Utc::now().timestamp();
+        assert!((now - command["time"].as_i64().unwrap()).abs() <= 1);
+        assert_eq!(command["channel"], "futures.public_liquidates");
+    }
+}
@@ -1,46 +1,116 @@
 use std::sync::mpsc::Sender;
 
-use super::utils::create_conversion_thread;
-use crypto_crawler::Message;
+use super::utils::{create_conversion_thread, run_with_reconnect};
+use crate::writers::Sinks;
+use crypto_crawler::{fetch_symbols_retry, Message};
 use crypto_market_type::MarketType;
 use crypto_msg_type::MessageType;
 use crypto_ws_client::*;
 
-pub(super) async fn crawl_other(market_type: MarketType, tx: Sender<Message>) {
+fn sub_command(topic: &str) -> String {
+    format!(r#"{{"sub":"{}","id":"crypto-ws-client"}}"#, topic)
+}
+
+/// The subscription commands of the Other feed of a market, `symbols` are
+/// the derivatives to subscribe to. Every market is crawled on the default
+/// endpoint of its crypto-ws-client client.
+///
+/// Spot has `market.overview` for all symbols at once, derivatives have one
+/// `market.{symbol}.detail` channel per contract.
+fn other_subscription(market_type: MarketType, symbols: &[String]) -> Vec<String> {
+    match market_type {
+        MarketType::Spot => vec![sub_command("market.overview")],
+        MarketType::InverseFuture
+        | MarketType::InverseSwap
+        | MarketType::LinearSwap
+        | MarketType::EuropeanOption => symbols
+            .iter()
+            .map(|symbol| sub_command(&format!("market.{}.detail", symbol)))
+            .collect(),
+        _ => panic!("Unknown market_type {}", market_type),
+    }
+}
+
+pub(super) async fn crawl_other(market_type: MarketType, tx: Sender<Message>, sinks: Sinks) {
     let tx = create_conversion_thread("huobi".to_string(), MessageType::Other, market_type, tx);
-    let commands = vec![r#"{"sub":"market.overview","id":"crypto-ws-client"}"#.to_string()];
+    let symbols = if market_type == MarketType::Spot {
+        vec![]
+    } else {
+        tokio::task::spawn_blocking(move || fetch_symbols_retry("huobi", market_type))
+            .await
+            .unwrap()
+    };
+    let commands = other_subscription(market_type, &symbols);
 
     match market_type {
         MarketType::Spot => {
-            let ws_client = HuobiSpotWSClient::new(tx, None).await;
-            ws_client.send(&commands).await;
-            ws_client.run().await;
-            ws_client.close();
+            run_with_reconnect("huobi", market_type, tx, sinks, commands, |tx| {
+                HuobiSpotWSClient::new(tx, None)
+            })
+            .await;
         }
         MarketType::InverseFuture => {
-            let ws_client = HuobiSpotWSClient::new(tx, None).await;
-            ws_client.send(&commands).await;
-            ws_client.run().await;
-            ws_client.close();
+            run_with_reconnect("huobi", market_type, tx, sinks, commands, |tx| {
+                HuobiFutureWSClient::new(tx, None)
+            })
+            .await;
         }
         MarketType::LinearSwap => {
-            let ws_client = HuobiSpotWSClient::new(tx, None).await;
-            ws_client.send(&commands).await;
-            ws_client.run().await;
-            ws_client.close();
+            run_with_reconnect("huobi", market_type, tx, sinks, commands, |tx| {
+                HuobiLinearSwapWSClient::new(tx, None)
+            })
+            .await;
         }
         MarketType::InverseSwap => {
-            let ws_client = HuobiSpotWSClient::new(tx, None).await;
-            ws_client.send(&commands).await;
-            ws_client.run().await;
-            ws_client.close();
+            run_with_reconnect("huobi", market_type, tx, sinks, commands, |tx| {
+                HuobiInverseSwapWSClient::new(tx, None)
+            })
+            .await;
         }
         MarketType::EuropeanOption => {
-            let ws_client = HuobiSpotWSClient::new(tx, None).await;
-            ws_client.send(&commands).await;
-            ws_client.run().await;
-            ws_client.close();
+            run_with_reconnect("huobi", market_type, tx, sinks, commands, |tx| {
+                HuobiOptionWSClient::new(tx, None)
+            })
+            .await;
         }
         _ => panic!("Unknown market_type {}", market_type),
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spot_subscribes_to_the_overview() {
+        assert_eq!(
+            other_subscription(MarketType::Spot, &[]),
+            vec![r#"{"sub":"market.overview","id":"crypto-ws-client"}"#]
+        );
+    }
+
+    #[test]
+    fn derivatives_subscribe_to_every_contract() {
+        let symbols = vec!["BTC-USD".to_string(), "ETH-USD".to_string()];
+        for market_type in [
+            MarketType::InverseFuture,
+            MarketType::InverseSwap,
+            MarketType::LinearSwap,
+            MarketType::EuropeanOption,
+        ] {
+            assert_eq!(
+                other_subscription(market_type, &symbols),
+                vec![
+                    r#"{"sub":"market.BTC-USD.detail","id":"crypto-ws-client"}"#,
+                    r#"{"sub":"market.ETH-USD.detail","id":"crypto-ws-client"}"#,
+                ]
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown market_type")]
+    fn rejects_other_markets() {
+        other_subscription(MarketType::LinearFuture, &[]);
+    }
+}
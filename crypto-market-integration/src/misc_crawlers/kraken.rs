@@ -0,0 +1,102 @@
+use std::{sync::mpsc::Sender, time::Duration};
+
+use super::utils::{create_conversion_thread, ConnectionEvents};
+use crate::writers::Sinks;
+use crypto_crawler::Message;
+use crypto_market_type::MarketType;
+use crypto_msg_type::MessageType;
+use log::*;
+
+// crypto-ws-client handles the systemStatus event of the websocket itself and
+// never passes it on, so the status is polled over REST instead
+const SYSTEM_STATUS_URL: &str = "https://api.kraken.com/0/public/SystemStatus";
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The status of a SystemStatus response as the websocket pushes it, e.g.
+/// `{"event":"systemStatus","status":"online","timestamp":"2022-06-01T00:00:00Z"}`.
+fn system_status(body: &str) -> Result<serde_json::Value, String> {
+    let resp: serde_json::Value = serde_json::from_str(body).map_err(|err| err.to_string())?;
+    if let Some(errors) = resp["error"].as_array().filter(|errors| !errors.is_empty()) {
+        return Err(format!("{:?}", errors));
+    }
+    match resp["result"]["status"].as_str() {
+        Some(status) => Ok(serde_json::json!({
+            "event": "systemStatus",
+            "status": status,
+            "timestamp": resp["result"]["timestamp"],
+        })),
+        None => Err(format!("no status in {}", body)),
+    }
+}
+
+async fn fetch_system_status() -> Result<serde_json::Value, String> {
+    let body = reqwest::get(SYSTEM_STATUS_URL)
+        .await
+        .and_then(|resp| resp.error_for_status())
+        .map_err(|err| err.to_string())?
+        .text()
+        .await
+        .map_err(|err| err.to_string())?;
+    system_status(&body)
+}
+
+/// Poll the system status, sent on startup and whenever it changes, e.g. to
+/// `maintenance` or `cancel_only`.
+///
+/// A failed poll is sent to `sinks` as a disconnect and the next successful
+/// one as a reconnect, the way websocket feeds report their gaps.
+pub(super) async fn crawl_other(market_type: MarketType, tx: Sender<Message>, sinks: Sinks) {
+    if market_type != MarketType::Spot {
+        panic!("Unknown market_type {}", market_type);
+    }
+    let tx = create_conversion_thread("kraken".to_string(), MessageType::Other, market_type, tx);
+    let mut events = ConnectionEvents::new("kraken", market_type, sinks);
+
+    let mut last_status: Option<String> = None;
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        match fetch_system_status().await {
+            Ok(event) => {
+                events.reconnected();
+                let status = event["status"].as_str().map(|status| status.to_string());
+                if status != last_status {
+                    if tx.send(event.to_string()).is_err() {
+                        return;
+                    }
+                    last_status = status;
+                }
+            }
+            Err(err) => {
+                warn!("Failed to fetch the kraken system status: {}", err);
+                if last_status.is_some() {
+                    events.disconnected();
+                } else {
+                    events.not_connected();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_recorded_status() {
+        let body =
+            r#"{"error":[],"result":{"status":"online","timestamp":"2022-06-01T00:00:00Z"}}"#;
+        assert_eq!(
+            system_status(body).unwrap().to_string(),
+            r#"{"event":"systemStatus","status":"online","timestamp":"2022-06-01T00:00:00Z"}"#
+        );
+    }
+
+    #[test]
+    fn reports_errors() {
+        assert!(system_status(r#"{"error":["EService:Unavailable"]}"#).is_err());
+        assert!(system_status(r#"{"error":[],"result":{}}"#).is_err());
+        assert!(system_status("<html>").is_err());
+    }
+}
@@ -1,23 +1,43 @@
 use std::sync::mpsc::Sender;
 
+use crate::writers::Sinks;
 use crypto_crawler::Message;
 use crypto_market_type::MarketType;
 
 mod binance;
+mod bitfinex;
 mod bitmex;
 mod bybit;
 mod coinbase_pro;
+mod deribit;
+mod gate;
 mod huobi;
+mod kraken;
+mod okx;
 
 mod utils;
 
-pub async fn crawl_other(exchange: &str, market_type: MarketType, tx: Sender<Message>) {
+pub(crate) use utils::create_conversion_thread;
+
+/// Crawl the Other feed of a market, its disconnects and reconnects are sent
+/// to `sinks` on the control topic.
+pub async fn crawl_other(
+    exchange: &str,
+    market_type: MarketType,
+    tx: Sender<Message>,
+    sinks: Sinks,
+) {
     match exchange {
-        "binance" => binance::crawl_other(market_type, tx).await,
-        "bitmex" => bitmex::crawl_other(market_type, tx).await,
-        "bybit" => bybit::crawl_other(market_type, tx).await,
-        "coinbase_pro" => coinbase_pro::crawl_other(market_type, tx).await,
-        "huobi" => huobi::crawl_other(market_type, tx).await,
+        "binance" => binance::crawl_other(market_type, tx, sinks).await,
+        "bitfinex" => bitfinex::crawl_other(market_type, tx, sinks).await,
+        "bitmex" => bitmex::crawl_other(market_type, tx, sinks).await,
+        "bybit" => bybit::crawl_other(market_type, tx, sinks).await,
+        "coinbase_pro" => coinbase_pro::crawl_other(market_type, tx, sinks).await,
+        "deribit" => deribit::crawl_other(market_type, tx, sinks).await,
+        "gate" => gate::crawl_other(market_type, tx, sinks).await,
+        "huobi" => huobi::crawl_other(market_type, tx, sinks).await,
+        "kraken" => kraken::crawl_other(market_type, tx, sinks).await,
+        "okx" => okx::crawl_other(market_type, tx, sinks).await,
         _ => panic!("Unknown exchange {}", exchange),
     }
 }
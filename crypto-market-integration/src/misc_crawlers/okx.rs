@@ -0,0 +1,82 @@
+use std::sync::mpsc::Sender;
+
+use super::utils::{create_conversion_thread, run_with_reconnect};
+use crate::writers::Sinks;
+use crypto_crawler::Message;
+use crypto_market_type::MarketType;
+use crypto_msg_type::MessageType;
+use crypto_ws_client::*;
+
+/// The subscription commands of the Other feed of a market, system
+/// maintenance for the `unknown` market and liquidations for the others.
+fn other_subscription(market_type: MarketType) -> Vec<String> {
+    let command = match market_type {
+        // system maintenance, not bound to a market
+        MarketType::Unknown => r#"{"op":"subscribe","args":[{"channel":"status"}]}"#.to_string(),
+        _ => {
+            // liquidation-orders has no SPOT instType, spot pairs are only
+            // liquidated when traded on margin, which OKX reports as MARGIN
+            let inst_type = match market_type {
+                MarketType::Spot => "MARGIN",
+                MarketType::InverseSwap | MarketType::LinearSwap => "SWAP",
+                MarketType::InverseFuture | MarketType::LinearFuture => "FUTURES",
+                MarketType::EuropeanOption => "OPTION",
+                _ => panic!("Unknown market_type {}", market_type),
+            };
+            format!(
+                r#"{{"op":"subscribe","args":[{{"channel":"liquidation-orders","instType":"{}"}}]}}"#,
+                inst_type
+            )
+        }
+    };
+    vec![command]
+}
+
+pub(super) async fn crawl_other(market_type: MarketType, tx: Sender<Message>, sinks: Sinks) {
+    let commands = other_subscription(market_type);
+    let tx = create_conversion_thread("okx".to_string(), MessageType::Other, market_type, tx);
+
+    run_with_reconnect("okx", market_type, tx, sinks, commands, |tx| {
+        OkxWSClient::new(tx, None)
+    })
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_subscribes_to_the_status() {
+        assert_eq!(
+            other_subscription(MarketType::Unknown),
+            vec![r#"{"op":"subscribe","args":[{"channel":"status"}]}"#]
+        );
+    }
+
+    #[test]
+    fn markets_subscribe_to_their_liquidations() {
+        for (market_type, inst_type) in [
+            (MarketType::Spot, "MARGIN"),
+            (MarketType::InverseSwap, "SWAP"),
+            (MarketType::LinearSwap, "SWAP"),
+            (MarketType::InverseFuture, "FUTURES"),
+            (MarketType::LinearFuture, "FUTURES"),
+            (MarketType::EuropeanOption, "OPTION"),
+        ] {
+            assert_eq!(
+                other_subscription(market_type),
+                vec![format!(
+                    r#"{{"op":"subscribe","args":[{{"channel":"liquidation-orders","instType":"{}"}}]}}"#,
+                    inst_type
+                )]
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown market_type")]
+    fn rejects_other_markets() {
+        other_subscription(MarketType::QuantoSwap);
+    }
+}
@@ -1,11 +1,26 @@
-use std::sync::mpsc::Sender;
+use std::{
+    future::Future,
+    panic::AssertUnwindSafe,
+    sync::mpsc::Sender,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
+use crate::writers::{panic_message, Sinks};
 use crypto_crawler::Message;
+use crypto_market_topic::{Topic, TopicType};
 use crypto_market_type::MarketType;
 use crypto_msg_type::MessageType;
+use crypto_ws_client::WSClient;
+use futures::FutureExt;
+use log::*;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+// a connection that lasted this long was healthy, the backoff starts over
+const RESET_AFTER: Duration = Duration::from_secs(60);
 
 // create a thread to convert Sender<Arc<Message>> Sender<String>
-pub(super) fn create_conversion_thread(
+pub(crate) fn create_conversion_thread(
     exchange: String,
     msg_type: MessageType,
     market_type: MarketType,
@@ -20,3 +35,263 @@ pub(super) fn create_conversion_thread(
     });
     tx_raw
 }
+
+// Exponential backoff between reconnects, from `MIN_BACKOFF` doubled up to
+// `MAX_BACKOFF`
+struct Backoff {
+    delay: Duration,
+}
+
+impl Backoff {
+    fn new() -> Self {
+        Backoff { delay: MIN_BACKOFF }
+    }
+
+    // The delay before the next attempt, after a connection that lasted
+    // `connected_for`
+    fn next(&mut self, connected_for: Duration) -> Duration {
+        if connected_for >= RESET_AFTER {
+            self.delay = MIN_BACKOFF;
+        }
+        let delay = self.delay;
+        self.delay = (self.delay * 2).min(MAX_BACKOFF);
+        delay
+    }
+}
+
+// A fraction in [0, 1) that differs between processes and calls
+fn random_fraction() -> f64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .subsec_nanos();
+    nanos as f64 / 1e9
+}
+
+// between 0.5 and 1.5 times `delay` for a `fraction` in [0, 1), so crawlers
+// dropped together don't reconnect together
+fn jitter(delay: Duration, fraction: f64) -> Duration {
+    delay.mul_f64(0.5 + fraction)
+}
+
+/// Disconnects and reconnects of an Other feed, sent to every sink as JSON on
+/// the `exchange_market_type_control_other` topic.
+pub(super) struct ConnectionEvents {
+    exchange: String,
+    market_type: MarketType,
+    topic: Topic,
+    sinks: Sinks,
+    disconnected_at: Option<i64>,
+}
+
+impl ConnectionEvents {
+    pub(super) fn new(exchange: &str, market_type: MarketType, sinks: Sinks) -> Self {
+        let topic = Topic::new(
+            exchange,
+            market_type,
+            TopicType::Control,
+            &MessageType::Other.to_string(),
+        );
+        sinks.open(&topic);
+        ConnectionEvents {
+            exchange: exchange.to_string(),
+            market_type,
+            topic,
+            sinks,
+            disconnected_at: None,
+        }
+    }
+
+    fn send(&self, event: serde_json::Value) {
+        self.sinks
+            .send(self.topic.clone(), event.to_string().into_bytes());
+    }
+
+    /// Report the end of a connection, unless it already ended.
+    pub(super) fn disconnected(&mut self) {
+        if self.disconnected_at.is_some() {
+            return;
+        }
+        let now = chrono::Utc::now().timestamp_millis();
+        self.disconnected_at = Some(now);
+        self.send(serde_json::json!({
+            "exchange": self.exchange,
+            "market_type": self.market_type.to_string(),
+            "msg_type": MessageType::Other.to_string(),
+            "event": "disconnected",
+            "timestamp": now,
+        }));
+    }
+
+    /// Start the gap without reporting it, for a connection that never
+    /// came up. It is reported once reconnected.
+    pub(super) fn not_connected(&mut self) {
+        self.disconnected_at
+            .get_or_insert_with(|| chrono::Utc::now().timestamp_millis());
+    }
+
+    /// Report the end of the gap, if there is one.
+    pub(super) fn reconnected(&mut self) {
+        if let Some(disconnected_at) = self.disconnected_at.take() {
+            info!("{} {} other reconnected", self.exchange, self.market_type);
+            self.send(serde_json::json!({
+                "exchange": self.exchange,
+                "market_type": self.market_type.to_string(),
+                "msg_type": MessageType::Other.to_string(),
+                "event": "reconnected",
+                "disconnected_at": disconnected_at,
+                "timestamp": chrono::Utc::now().timestamp_millis(),
+            }));
+        }
+    }
+}
+
+/// Run a websocket client created by `connect` and subscribed with `commands`,
+/// and reconnect and resubscribe with exponential backoff whenever the
+/// connection drops or `connect` panics.
+///
+/// Every disconnect and reconnect is sent to `sinks`, see [`ConnectionEvents`],
+/// so consumers know about the gap.
+pub(super) async fn run_with_reconnect<C, F, Fut>(
+    exchange: &str,
+    market_type: MarketType,
+    tx: Sender<String>,
+    sinks: Sinks,
+    commands: Vec<String>,
+    connect: F,
+) where
+    C: WSClient,
+    F: Fn(Sender<String>) -> Fut,
+    Fut: Future<Output = C>,
+{
+    let mut events = ConnectionEvents::new(exchange, market_type, sinks);
+    let mut backoff = Backoff::new();
+
+    loop {
+        let connected_at = Instant::now();
+        // crypto-ws-client panics when it fails to connect
+        match AssertUnwindSafe(connect(tx.clone())).catch_unwind().await {
+            Ok(ws_client) => {
+                ws_client.send(&commands).await;
+                events.reconnected();
+
+                ws_client.run().await;
+                ws_client.close();
+                events.disconnected();
+            }
+            // still disconnected, the gap is reported once reconnected
+            Err(err) => {
+                error!(
+                    "{} {} other failed to connect: {}",
+                    exchange,
+                    market_type,
+                    panic_message(err.as_ref())
+                );
+                events.not_connected();
+            }
+        }
+
+        let wait = jitter(backoff.next(connected_at.elapsed()), random_fraction());
+        warn!(
+            "{} {} other disconnected, reconnecting in {:?}",
+            exchange, market_type, wait
+        );
+        tokio::time::sleep(wait).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writers::{Sink, SinkMessage};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct RecordingSink {
+        sent: Arc<Mutex<Vec<(String, serde_json::Value)>>>,
+    }
+
+    impl Sink for RecordingSink {
+        fn send(&self, msg: Arc<SinkMessage>) {
+            let event = serde_json::from_slice(&msg.data).unwrap();
+            self.sent
+                .lock()
+                .unwrap()
+                .push((msg.topic.to_string(), event));
+        }
+    }
+
+    #[test]
+    fn reports_each_gap_once() {
+        let sink = RecordingSink::default();
+        let mut sinks = Sinks::default();
+        sinks.push(sink.clone());
+        let mut events = ConnectionEvents::new("kraken", MarketType::Spot, sinks);
+
+        // connected from the start, nothing to report
+        events.reconnected();
+        events.disconnected();
+        events.disconnected();
+        events.reconnected();
+        events.reconnected();
+
+        let sent = sink.sent.lock().unwrap();
+        assert_eq!(sent.len(), 2);
+        assert!(sent
+            .iter()
+            .all(|(topic, _)| topic == "kraken_spot_control_other"));
+        assert_eq!(sent[0].1["event"], "disconnected");
+        assert_eq!(sent[1].1["event"], "reconnected");
+        assert_eq!(sent[1].1["disconnected_at"], sent[0].1["timestamp"]);
+    }
+
+    #[test]
+    fn reports_a_failed_first_connection_once_connected() {
+        let sink = RecordingSink::default();
+        let mut sinks = Sinks::default();
+        sinks.push(sink.clone());
+        let mut events = ConnectionEvents::new("bitmex", MarketType::Unknown, sinks);
+
+        events.not_connected();
+        events.not_connected();
+        assert!(sink.sent.lock().unwrap().is_empty());
+        events.reconnected();
+
+        let sent = sink.sent.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].1["event"], "reconnected");
+    }
+
+    const SHORT: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let mut backoff = Backoff::new();
+        let delays: Vec<u64> = (0..9).map(|_| backoff.next(SHORT).as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 16, 32, 60, 60, 60]);
+    }
+
+    #[test]
+    fn backoff_starts_over_after_a_healthy_connection() {
+        let mut backoff = Backoff::new();
+        for _ in 0..4 {
+            backoff.next(SHORT);
+        }
+        assert_eq!(backoff.next(RESET_AFTER), MIN_BACKOFF);
+        assert_eq!(backoff.next(SHORT), MIN_BACKOFF * 2);
+    }
+
+    #[test]
+    fn jitter_stays_within_half_and_one_and_a_half_times() {
+        let delay = Duration::from_secs(10);
+        assert_eq!(jitter(delay, 0.0), Duration::from_secs(5));
+        assert_eq!(jitter(delay, 0.5), delay);
+        assert!(jitter(delay, 0.999_999) < Duration::from_secs(15));
+        for _ in 0..100 {
+            let fraction = random_fraction();
+            assert!((0.0..1.0).contains(&fraction));
+            let wait = jitter(delay, fraction);
+            assert!(wait >= Duration::from_secs(5) && wait < Duration::from_secs(15));
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use lazy_static::lazy_static;
+use log::*;
+use std::time::Duration;
+use tokio::sync::watch;
+
+lazy_static! {
+    static ref SHUTDOWN: watch::Sender<bool> = watch::channel(false).0;
+}
+
+/// Request a shutdown on the first SIGTERM or SIGINT, a second one exits at
+/// once without flushing.
+pub fn handle_signals() {
+    #[cfg(not(windows))]
+    std::thread::spawn(|| {
+        use signal_hook::{
+            consts::signal::{SIGINT, SIGTERM},
+            iterator::Signals,
+        };
+
+        let mut signals = Signals::new(&[SIGTERM, SIGINT]).expect("cannot register signals");
+        for signal in signals.forever() {
+            if shutdown_requested() {
+                error!("Received signal {} again, exiting without flushing", signal);
+                std::process::exit(128 + signal);
+            }
+            warn!("Received signal {}, shutting down", signal);
+            let _ = SHUTDOWN.send(true);
+        }
+    });
+}
+
+pub fn shutdown_requested() -> bool {
+    *SHUTDOWN.borrow()
+}
+
+/// A receiver that turns `true` once a shutdown is requested.
+pub(crate) fn subscribe() -> watch::Receiver<bool> {
+    SHUTDOWN.subscribe()
+}
+
+/// Resolves once a shutdown is requested.
+pub async fn shutdown() {
+    let mut rx = subscribe();
+    while !*rx.borrow() {
+        if rx.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+/// SHUTDOWN_TIMEOUT_SECS, how long writers get to flush, 30 seconds by default
+pub fn shutdown_timeout() -> Duration {
+    let secs = std::env::var("SHUTDOWN_TIMEOUT_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(30);
+    Duration::from_secs(secs)
+}
@@ -0,0 +1,486 @@
+use crate::shutdown::{shutdown, shutdown_timeout};
+
+use log::*;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    process::{Child, Command},
+    sync::Notify,
+};
+
+fn default_control_socket() -> String {
+    "/tmp/crypto-market-supervisor.sock".to_string()
+}
+
+fn default_min_backoff_secs() -> u64 {
+    1
+}
+
+fn default_max_backoff_secs() -> u64 {
+    60
+}
+
+fn default_reset_after_secs() -> u64 {
+    60
+}
+
+/// One `[[workers]]` entry of the supervisor config.
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize, Clone)]
+pub struct WorkerConfig {
+    pub name: String,
+    /// The crawler itself if not set
+    pub command: Option<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Added to the environment inherited from the supervisor
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+}
+
+/// Supervisor config, loaded from a TOML file.
+///
+/// ```toml
+/// control_socket = "/tmp/crypto-market-supervisor.sock"
+/// max_backoff_secs = 60
+///
+/// [[workers]]
+/// name = "binance-spot-trade"
+/// args = ["binance", "spot", "trade"]
+/// env = { DATA_DIR = "/carbonbot_data" }
+///
+/// [[workers]]
+/// name = "recorder-binance-spot-trade"
+/// command = "crypto-market-recorder"
+/// args = ["binance", "spot", "trade", "BTCUSDT"]
+/// ```
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize, Clone)]
+pub struct SupervisorConfig {
+    /// Unix socket answering `status` and `restart NAME`
+    #[serde(default = "default_control_socket")]
+    pub control_socket: String,
+    /// Delay before the first restart, doubled after every crash
+    #[serde(default = "default_min_backoff_secs")]
+    pub min_backoff_secs: u64,
+    #[serde(default = "default_max_backoff_secs")]
+    pub max_backoff_secs: u64,
+    /// A worker running this long is healthy again, the delay is reset
+    #[serde(default = "default_reset_after_secs")]
+    pub reset_after_secs: u64,
+    #[serde(default)]
+    pub workers: Vec<WorkerConfig>,
+}
+
+impl SupervisorConfig {
+    pub fn new(toml_data: &str) -> Result<Self, String> {
+        let config: SupervisorConfig =
+            toml::from_str(toml_data).map_err(|e| format!("Invalid config: {}", e))?;
+        // a zero delay would restart a crashing worker in a busy loop
+        if config.min_backoff_secs < 1 {
+            return Err("min_backoff_secs must be at least 1".to_string());
+        }
+        if config.max_backoff_secs < config.min_backoff_secs {
+            return Err("max_backoff_secs must not be below min_backoff_secs".to_string());
+        }
+        let mut names = std::collections::HashSet::new();
+        for worker in config.workers.iter() {
+            if !names.insert(worker.name.as_str()) {
+                return Err(format!("Duplicate worker {}", worker.name));
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let content =
+            std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        SupervisorConfig::new(&content).map_err(|e| format!("{}, {}", path, e))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkerState {
+    Running,
+    /// Waiting to be restarted
+    Backoff,
+    Stopped,
+}
+
+/// What the control socket reports about a worker.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct WorkerStatus {
+    pub name: String,
+    pub state: WorkerState,
+    pub pid: Option<u32>,
+    pub restarts: u64,
+    /// How the last run ended, e.g. `exit status: 1`
+    pub last_exit: Option<String>,
+    /// Milliseconds
+    pub started_at: Option<i64>,
+    pub next_start_at: Option<i64>,
+}
+
+struct Worker {
+    status: Mutex<WorkerStatus>,
+    restart: Notify,
+}
+
+type Workers = Arc<BTreeMap<String, Arc<Worker>>>;
+
+fn now_ms() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+fn command(worker: &WorkerConfig) -> std::io::Result<Child> {
+    let program = match worker.command {
+        Some(ref command) => command.into(),
+        None => std::env::current_exe()?,
+    };
+    let mut command = Command::new(program);
+    command.args(&worker.args).envs(&worker.env).kill_on_drop(true);
+    let supervisor = std::process::id() as libc::pid_t;
+    unsafe {
+        command.pre_exec(move || {
+            // a process group of its own, so Ctrl-C reaches the worker only
+            // through the supervisor instead of twice, which would skip its flush
+            libc::setpgid(0, 0);
+            // SIGTERM when the supervisor dies, even of SIGKILL, so a worker is
+            // never left running without it. The signal follows the thread that
+            // spawned the worker, a runtime worker thread living as long as the
+            // supervisor
+            #[cfg(target_os = "linux")]
+            {
+                if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                // the supervisor died before the signal was set
+                if libc::getppid() != supervisor {
+                    return Err(std::io::Error::other("the supervisor has exited"));
+                }
+            }
+            Ok(())
+        });
+    }
+    command.spawn()
+}
+
+// SIGTERM first so the worker flushes, SIGKILL if it takes too long
+async fn stop(name: &str, child: &mut Child) {
+    if let Some(pid) = child.id() {
+        unsafe {
+            libc::kill(pid as libc::pid_t, libc::SIGTERM);
+        }
+    }
+
+    match tokio::time::timeout(shutdown_timeout(), child.wait()).await {
+        Ok(Ok(status)) => info!("{} stopped, {}", name, status),
+        Ok(Err(err)) => error!("failed to wait for {}: {}", name, err),
+        Err(_) => {
+            warn!("{} did not stop in time, killing it", name);
+            let _ = child.kill().await;
+        }
+    }
+}
+
+async fn supervise(
+    config: WorkerConfig,
+    worker: Arc<Worker>,
+    backoff: (Duration, Duration, Duration),
+) {
+    let (min_backoff, max_backoff, reset_after) = backoff;
+    let mut delay = min_backoff;
+
+    loop {
+        let started = Instant::now();
+        let exit = match command(&config) {
+            Ok(mut child) => {
+                info!("started {}, pid {:?}", config.name, child.id());
+                {
+                    let mut status = worker.status.lock().unwrap();
+                    status.state = WorkerState::Running;
+                    status.pid = child.id();
+                    status.started_at = Some(now_ms());
+                    status.next_start_at = None;
+                }
+
+                tokio::select! {
+                    exit = child.wait() => match exit {
+                        Ok(exit) => exit.to_string(),
+                        Err(err) => err.to_string(),
+                    },
+                    _ = worker.restart.notified() => {
+                        stop(&config.name, &mut child).await;
+                        let mut status = worker.status.lock().unwrap();
+                        status.restarts += 1;
+                        status.last_exit = Some("restarted".to_string());
+                        delay = min_backoff;
+                        continue;
+                    }
+                    _ = shutdown() => {
+                        stop(&config.name, &mut child).await;
+                        let mut status = worker.status.lock().unwrap();
+                        status.state = WorkerState::Stopped;
+                        status.pid = None;
+                        return;
+                    }
+                }
+            }
+            Err(err) => format!("failed to start: {}", err),
+        };
+
+        if started.elapsed() >= reset_after {
+            delay = min_backoff;
+        }
+        error!(
+            "{} exited ({}), restarting in {:?}",
+            config.name, exit, delay
+        );
+        {
+            let mut status = worker.status.lock().unwrap();
+            status.state = WorkerState::Backoff;
+            status.pid = None;
+            status.last_exit = Some(exit);
+            status.next_start_at = Some(now_ms() + delay.as_millis() as i64);
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = worker.restart.notified() => {}
+            _ = shutdown() => {
+                let mut status = worker.status.lock().unwrap();
+                status.state = WorkerState::Stopped;
+                status.next_start_at = None;
+                return;
+            }
+        }
+        worker.status.lock().unwrap().restarts += 1;
+        delay = (delay * 2).min(max_backoff);
+    }
+}
+
+async fn handle_control(stream: UnixStream, workers: Workers) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        let mut words = line.split_whitespace();
+        let response = match (words.next(), words.next()) {
+            (Some("status"), None) => {
+                let statuses: Vec<WorkerStatus> = workers
+                    .values()
+                    .map(|worker| worker.status.lock().unwrap().clone())
+                    .collect();
+                serde_json::to_string(&statuses).unwrap()
+            }
+            (Some("restart"), Some(name)) => match workers.get(name) {
+                Some(worker) => {
+                    worker.restart.notify_one();
+                    "ok".to_string()
+                }
+                None => format!("unknown worker {}", name),
+            },
+            _ => "commands: status, restart NAME".to_string(),
+        };
+        writer.write_all(response.as_bytes()).await?;
+        writer.write_all(b"\n").await?;
+    }
+    Ok(())
+}
+
+async fn serve_control(path: String, workers: Workers) {
+    // left behind by a supervisor that was killed
+    let _ = std::fs::remove_file(&path);
+    // the socket restarts workers, only the owner may connect. Created 0600
+    // rather than changed after binding, when others could already connect
+    let umask = unsafe { libc::umask(0o177) };
+    let bound = UnixListener::bind(&path);
+    unsafe {
+        libc::umask(umask);
+    }
+    let listener = match bound {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("failed to serve the control socket {}: {}", path, err);
+            return;
+        }
+    };
+    info!("serving the control socket {}", path);
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let workers = workers.clone();
+                tokio::task::spawn(async move {
+                    if let Err(err) = handle_control(stream, workers).await {
+                        debug!("control connection failed: {}", err);
+                    }
+                });
+            }
+            Err(err) => error!("failed to accept a control connection: {}", err),
+        }
+    }
+}
+
+// The state of every worker, none of them started yet
+fn new_workers(configs: &[WorkerConfig]) -> Workers {
+    Arc::new(
+        configs
+            .iter()
+            .map(|worker| {
+                let status = WorkerStatus {
+                    name: worker.name.clone(),
+                    state: WorkerState::Stopped,
+                    pid: None,
+                    restarts: 0,
+                    last_exit: None,
+                    started_at: None,
+                    next_start_at: None,
+                };
+                let worker_state = Worker {
+                    status: Mutex::new(status),
+                    restart: Notify::new(),
+                };
+                (worker.name.clone(), Arc::new(worker_state))
+            })
+            .collect(),
+    )
+}
+
+/// Launch every worker of `config` and restart it with exponential backoff
+/// whenever it exits, until a shutdown is requested. The workers are then
+/// stopped with SIGTERM so they can flush.
+pub async fn run_supervisor(config: SupervisorConfig) {
+    let backoff = (
+        Duration::from_secs(config.min_backoff_secs),
+        Duration::from_secs(config.max_backoff_secs),
+        Duration::from_secs(config.reset_after_secs),
+    );
+
+    let workers = new_workers(&config.workers);
+
+    let control = tokio::task::spawn(serve_control(
+        config.control_socket.clone(),
+        workers.clone(),
+    ));
+
+    let tasks = config.workers.into_iter().map(|worker| {
+        let state = workers[&worker.name].clone();
+        tokio::task::spawn(supervise(worker, state, backoff))
+    });
+    futures::future::join_all(tasks).await;
+
+    control.abort();
+    let _ = std::fs::remove_file(&config.control_socket);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_backoff_defaults() {
+        let config = SupervisorConfig::new("[[workers]]\nname = \"a\"").unwrap();
+        assert_eq!(config.min_backoff_secs, 1);
+        assert_eq!(config.max_backoff_secs, 60);
+        assert_eq!(config.workers.len(), 1);
+    }
+
+    #[test]
+    fn rejects_a_zero_backoff() {
+        assert!(SupervisorConfig::new("min_backoff_secs = 0").is_err());
+        assert!(SupervisorConfig::new("min_backoff_secs = 10\nmax_backoff_secs = 5").is_err());
+    }
+
+    #[test]
+    fn rejects_duplicate_workers() {
+        let toml = "[[workers]]\nname = \"a\"\n[[workers]]\nname = \"a\"";
+        assert!(SupervisorConfig::new(toml).is_err());
+    }
+
+    fn socket_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!(
+                "crypto-market-supervisor-{}-{}.sock",
+                name,
+                std::process::id()
+            ))
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    async fn connect(path: &str) -> UnixStream {
+        loop {
+            if let Ok(stream) = UnixStream::connect(path).await {
+                break stream;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn serves_the_control_socket_to_its_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = socket_path("owner");
+        let control = tokio::task::spawn(serve_control(path.clone(), Arc::new(BTreeMap::new())));
+        let mut stream = connect(&path).await;
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        stream.write_all(b"status\n").await.unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "[]");
+        control.abort();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn restarts_a_crashing_worker_with_backoff() {
+        let config = WorkerConfig {
+            name: "crashing".to_string(),
+            command: Some("false".to_string()),
+            args: Vec::new(),
+            env: HashMap::new(),
+        };
+        let workers = new_workers(std::slice::from_ref(&config));
+        let path = socket_path("backoff");
+        let control = tokio::task::spawn(serve_control(path.clone(), workers.clone()));
+        let backoff = (
+            Duration::from_millis(100),
+            Duration::from_millis(200),
+            Duration::from_secs(60),
+        );
+        let supervisor =
+            tokio::task::spawn(supervise(config, workers["crashing"].clone(), backoff));
+
+        let (reader, mut writer) = connect(&path).await.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let status = loop {
+            writer.write_all(b"status\n").await.unwrap();
+            let line = lines.next_line().await.unwrap().unwrap();
+            let statuses: serde_json::Value = serde_json::from_str(&line).unwrap();
+            let status = statuses[0].clone();
+            if status["restarts"].as_u64().unwrap() >= 2 && status["state"] == "backoff" {
+                break status;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        };
+        assert_eq!(status["name"], "crashing");
+        assert_eq!(status["last_exit"], "exit status: 1");
+        assert!(status["pid"].is_null());
+        let started_at = status["started_at"].as_i64().unwrap();
+        assert!(status["next_start_at"].as_i64().unwrap() > started_at);
+
+        supervisor.abort();
+        control.abort();
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,427 @@
+use crate::metrics::{topic_health, TopicHealth};
+
+use crypto_market_topic::Topic;
+use lazy_static::lazy_static;
+use log::*;
+use slack_hook::{PayloadBuilder, Slack};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    str::FromStr,
+    sync::Mutex,
+    time::Duration,
+};
+
+// How long an exit alert may delay the exit of the crawler
+const EXIT_ALERT_TIMEOUT: Duration = Duration::from_secs(10);
+// Symbols listed in an alert, the others are only counted
+const MAX_LISTED_SYMBOLS: usize = 10;
+
+fn default_stale_secs() -> u64 {
+    60
+}
+
+fn default_max_parse_failures() -> u64 {
+    100
+}
+
+fn default_interval_secs() -> u64 {
+    10
+}
+
+/// The `[watchdog]` section of the crawler config.
+///
+/// ```toml
+/// [watchdog]
+/// webhook_url = "https://hooks.slack.com/services/XXX/YYY/ZZZ"
+/// stale_secs = 60
+/// max_parse_failures = 100
+/// ```
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize, Clone)]
+pub struct WatchdogConfig {
+    /// Slack incoming webhook, any URL accepting the same POST works
+    pub webhook_url: String,
+    /// A topic is stale after this many seconds without messages
+    #[serde(default = "default_stale_secs")]
+    pub stale_secs: u64,
+    /// Alert when more messages than this fail to parse between two checks
+    #[serde(default = "default_max_parse_failures")]
+    pub max_parse_failures: u64,
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+}
+
+impl WatchdogConfig {
+    /// Read the config from `SLACK_WEBHOOK_URL`, `WATCHDOG_STALE_SECS` and
+    /// `WATCHDOG_MAX_PARSE_FAILURES`, `None` without a webhook URL.
+    pub fn from_env() -> Option<Self> {
+        let webhook_url = std::env::var("SLACK_WEBHOOK_URL").ok()?;
+        let env_u64 = |name: &str, default: u64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(default)
+        };
+        Some(WatchdogConfig {
+            webhook_url,
+            stale_secs: env_u64("WATCHDOG_STALE_SECS", default_stale_secs()),
+            max_parse_failures: env_u64(
+                "WATCHDOG_MAX_PARSE_FAILURES",
+                default_max_parse_failures(),
+            ),
+            interval_secs: default_interval_secs(),
+        })
+    }
+}
+
+lazy_static! {
+    // the webhook of the running watchdog, exits are reported through it
+    static ref WEBHOOK_URL: Mutex<Option<String>> = Mutex::new(None);
+}
+
+/// Report that the crawler of a feed returned, which means its websocket
+/// client exited `run()`.
+///
+/// The alert is sent before this returns, so it is not lost when the process
+/// exits right after.
+pub async fn report_exit(feed: &str) {
+    error!("{} exited", feed);
+    let webhook_url = WEBHOOK_URL.lock().unwrap().clone();
+    if let Some(webhook_url) = webhook_url {
+        let alert = send_alert(&webhook_url, format!(":x: {} exited", feed));
+        if tokio::time::timeout(EXIT_ALERT_TIMEOUT, alert)
+            .await
+            .is_err()
+        {
+            error!("timed out sending the exit alert of {}", feed);
+        }
+    }
+}
+
+async fn send_alert(webhook_url: &str, text: String) {
+    info!("{}", text);
+    let webhook_url = webhook_url.to_string();
+    let result = tokio::task::spawn_blocking(move || {
+        let slack = Slack::new(webhook_url.as_str())?;
+        let payload = PayloadBuilder::new()
+            .text(text.as_str())
+            .username("crypto-market-integration")
+            .build()?;
+        slack.send(&payload)
+    })
+    .await;
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => error!("failed to send slack alert: {}", err),
+        Err(err) => error!("slack alert panicked: {}", err),
+    }
+}
+
+// The feed of a topic and the symbol within it, alerts are sent per feed
+fn split_topic(topic: &str) -> (String, String) {
+    match Topic::from_str(topic) {
+        Ok(topic) => {
+            let feed = format!(
+                "{} {} {}",
+                topic.exchange, topic.market_type, topic.msg_type
+            );
+            let symbol = match topic.period {
+                Some(period) => format!("{} {}", topic.symbol, period),
+                None => topic.symbol,
+            };
+            (feed, symbol)
+        }
+        Err(_) => (topic.to_string(), String::new()),
+    }
+}
+
+fn list_symbols(symbols: &[String]) -> String {
+    let mut list = symbols
+        .iter()
+        .take(MAX_LISTED_SYMBOLS)
+        .cloned()
+        .collect::<Vec<String>>()
+        .join(", ");
+    if symbols.len() > MAX_LISTED_SYMBOLS {
+        list.push_str(&format!(" and {} more", symbols.len() - MAX_LISTED_SYMBOLS));
+    }
+    list
+}
+
+// feed -> symbols, sorted so alerts come in a stable order
+#[derive(Default)]
+struct ByFeed(BTreeMap<String, Vec<String>>);
+
+impl ByFeed {
+    fn add(&mut self, topic: &str) {
+        let (feed, symbol) = split_topic(topic);
+        self.0.entry(feed).or_default().push(symbol);
+    }
+}
+
+/// The health checks of the watchdog, a topic is only reported when it
+/// changes state.
+struct Checks {
+    config: WatchdogConfig,
+    stale: HashSet<String>,
+    failing: HashSet<String>,
+    parse_failures: HashMap<String, u64>,
+}
+
+impl Checks {
+    fn new(config: WatchdogConfig) -> Self {
+        Checks {
+            config,
+            stale: HashSet::new(),
+            failing: HashSet::new(),
+            parse_failures: HashMap::new(),
+        }
+    }
+
+    /// Check the health of every topic, returns one alert per feed and
+    /// kind of change.
+    fn check(&mut self, now: i64, healths: Vec<TopicHealth>) -> Vec<String> {
+        let stale_ms = (self.config.stale_secs * 1000) as i64;
+        let mut went_stale = ByFeed::default();
+        let mut receiving = ByFeed::default();
+        let mut went_failing = ByFeed::default();
+        let mut parsing = ByFeed::default();
+        let mut failures: BTreeMap<String, u64> = BTreeMap::new();
+
+        for health in healths {
+            if health.last_message_at > 0 {
+                if now - health.last_message_at > stale_ms {
+                    if self.stale.insert(health.topic.clone()) {
+                        went_stale.add(&health.topic);
+                    }
+                } else if self.stale.remove(&health.topic) {
+                    receiving.add(&health.topic);
+                }
+            }
+
+            let last = self
+                .parse_failures
+                .insert(health.topic.clone(), health.parse_failures)
+                .unwrap_or(0);
+            let new_failures = health.parse_failures - last;
+            if new_failures > self.config.max_parse_failures {
+                if self.failing.insert(health.topic.clone()) {
+                    went_failing.add(&health.topic);
+                    *failures.entry(split_topic(&health.topic).0).or_default() += new_failures;
+                }
+            } else if self.failing.remove(&health.topic) {
+                parsing.add(&health.topic);
+            }
+        }
+
+        let mut alerts = Vec::new();
+        for (feed, symbols) in went_stale.0 {
+            alerts.push(format!(
+                ":warning: {} has no messages for {}s: {}",
+                feed,
+                self.config.stale_secs,
+                list_symbols(&symbols)
+            ));
+        }
+        for (feed, symbols) in receiving.0 {
+            alerts.push(format!(
+                ":white_check_mark: {} is receiving messages again: {}",
+                feed,
+                list_symbols(&symbols)
+            ));
+        }
+        for (feed, symbols) in went_failing.0 {
+            alerts.push(format!(
+                ":warning: {} messages of {} failed to parse in {}s: {}",
+                failures[&feed],
+                feed,
+                self.config.interval_secs,
+                list_symbols(&symbols)
+            ));
+        }
+        for (feed, symbols) in parsing.0 {
+            alerts.push(format!(
+                ":white_check_mark: {} is parsing again: {}",
+                feed,
+                list_symbols(&symbols)
+            ));
+        }
+        alerts
+    }
+}
+
+async fn run_watchdog(config: WatchdogConfig) {
+    let webhook_url = config.webhook_url.clone();
+    let interval = Duration::from_secs(config.interval_secs);
+    let mut checks = Checks::new(config);
+
+    loop {
+        tokio::time::sleep(interval).await;
+        let now = chrono::Utc::now().timestamp_millis();
+        for alert in checks.check(now, topic_health()) {
+            send_alert(&webhook_url, alert).await;
+        }
+    }
+}
+
+/// Watch all topics published by this process and alert on Slack when one
+/// goes quiet, fails to parse too often, or when a crawler exits.
+///
+/// Alerts are sent once per feed with the symbols concerned, and a recovery
+/// notice follows once the topics are healthy again.
+pub fn start_watchdog(config: WatchdogConfig) {
+    *WEBHOOK_URL.lock().unwrap() = Some(config.webhook_url.clone());
+    tokio::task::spawn(run_watchdog(config));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        sync::Arc,
+    };
+
+    fn config() -> WatchdogConfig {
+        WatchdogConfig {
+            webhook_url: String::new(),
+            stale_secs: 60,
+            max_parse_failures: 100,
+            interval_secs: 10,
+        }
+    }
+
+    fn health(topic: &str, last_message_at: i64, parse_failures: u64) -> TopicHealth {
+        TopicHealth {
+            topic: topic.to_string(),
+            last_message_at,
+            parse_failures,
+        }
+    }
+
+    // A local webhook answering 200, returns its URL and the bodies it received
+    fn webhook_stub() -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let bodies = Arc::new(Mutex::new(Vec::new()));
+        let received = bodies.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    let line = line.to_ascii_lowercase();
+                    if let Some(value) = line.strip_prefix("content-length:") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                received
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8_lossy(&body).to_string());
+                stream
+                    .write_all(
+                        b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok",
+                    )
+                    .unwrap();
+            }
+        });
+        (url, bodies)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn exit_alert_is_sent_before_report_exit_returns() {
+        let (url, bodies) = webhook_stub();
+        *WEBHOOK_URL.lock().unwrap() = Some(url);
+
+        report_exit("binance spot trade").await;
+
+        let bodies = bodies.lock().unwrap();
+        assert_eq!(bodies.len(), 1);
+        assert!(bodies[0].contains(":x: binance spot trade exited"));
+    }
+
+    #[test]
+    fn stale_topics_are_aggregated_per_feed() {
+        let mut checks = Checks::new(config());
+        let now = 1_000_000;
+        let healths = || {
+            vec![
+                health("binance_spot_trade_BTCUSDT", now - 61_000, 0),
+                health("binance_spot_trade_ETHUSDT", now - 90_000, 0),
+                health("binance_spot_trade_XRPUSDT", now - 1_000, 0),
+                health("binance_spot_candlestick_BTCUSDT_60", now - 61_000, 0),
+            ]
+        };
+
+        let alerts = checks.check(now, healths());
+        assert_eq!(
+            alerts,
+            vec![
+                ":warning: binance spot candlestick has no messages for 60s: BTCUSDT 60",
+                ":warning: binance spot trade has no messages for 60s: BTCUSDT, ETHUSDT",
+            ]
+        );
+        // only changes are reported
+        assert!(checks.check(now, healths()).is_empty());
+
+        let alerts = checks.check(
+            now,
+            vec![
+                health("binance_spot_trade_BTCUSDT", now, 0),
+                health("binance_spot_trade_ETHUSDT", now, 0),
+            ],
+        );
+        assert_eq!(
+            alerts,
+            vec![":white_check_mark: binance spot trade is receiving messages again: BTCUSDT, ETHUSDT"]
+        );
+    }
+
+    #[test]
+    fn parse_failures_are_aggregated_per_feed() {
+        let mut checks = Checks::new(config());
+        let now = 1_000_000;
+        let alerts = checks.check(
+            now,
+            vec![
+                health("binance_spot_trade_BTCUSDT", now, 150),
+                health("binance_spot_trade_ETHUSDT", now, 200),
+                health("binance_spot_trade_XRPUSDT", now, 50),
+            ],
+        );
+        assert_eq!(
+            alerts,
+            vec![":warning: 350 messages of binance spot trade failed to parse in 10s: BTCUSDT, ETHUSDT"]
+        );
+
+        let alerts = checks.check(
+            now,
+            vec![
+                health("binance_spot_trade_BTCUSDT", now, 150),
+                health("binance_spot_trade_ETHUSDT", now, 210),
+            ],
+        );
+        assert_eq!(
+            alerts,
+            vec![":white_check_mark: binance spot trade is parsing again: BTCUSDT, ETHUSDT"]
+        );
+    }
+
+    #[test]
+    fn long_lists_are_cut() {
+        let symbols: Vec<String> = (0..12).map(|i| format!("S{}", i)).collect();
+        assert_eq!(
+            list_symbols(&symbols),
+            "S0, S1, S2, S3, S4, S5, S6, S7, S8, S9 and 2 more"
+        );
+    }
+}
@@ -0,0 +1,236 @@
+use super::Sinks;
+use crate::metrics::record_message;
+
+use crypto_crawler::MessageType;
+use crypto_market_topic::Topic;
+use crypto_msg_parser::{KlineMsg, TradeMsg};
+use log::*;
+use std::{collections::HashMap, time::Duration};
+use tokio::sync::mpsc::UnboundedReceiver;
+use wmjtyd_libstock::data::kline::encode_kline;
+
+// Bars without later trades are closed this long after their end
+const CLOSE_DELAY_MS: i64 = 1000;
+
+/// Builds OHLCV bars of several periods from trades.
+///
+/// A bar is published once, trades arriving after their bar was closed are
+/// dropped.
+pub(super) struct CandlestickAggregator {
+    periods: Vec<u64>,
+    // (symbol, period) -> open bar
+    bars: HashMap<(String, u64), KlineMsg>,
+    // (symbol, period) -> start of the last closed bar
+    closed_starts: HashMap<(String, u64), i64>,
+}
+
+impl CandlestickAggregator {
+    pub fn new(periods: Vec<u64>) -> Self {
+        CandlestickAggregator {
+            periods,
+            bars: HashMap::new(),
+            closed_starts: HashMap::new(),
+        }
+    }
+
+    fn close(&mut self, key: (String, u64), bar: &KlineMsg) {
+        self.closed_starts.insert(key, bar.timestamp);
+    }
+
+    /// Add a trade to the bars of its symbol, returns the bars it closed.
+    pub fn add(&mut self, trade: &TradeMsg) -> Vec<KlineMsg> {
+        let mut closed = Vec::new();
+        for period in self.periods.iter() {
+            let period_ms = (*period * 1000) as i64;
+            let start = trade.timestamp - trade.timestamp.rem_euclid(period_ms);
+            let key = (trade.symbol.clone(), *period);
+
+            if matches!(self.closed_starts.get(&key), Some(closed) if *closed >= start) {
+                debug!(
+                    "late trade of {} at {} dropped, its {}s bar is closed",
+                    trade.symbol, trade.timestamp, period
+                );
+                continue;
+            }
+
+            match self.bars.get(&key).map(|bar| bar.timestamp) {
+                Some(timestamp) if timestamp == start => {
+                    let bar = self.bars.get_mut(&key).unwrap();
+                    bar.high = bar.high.max(trade.price);
+                    bar.low = bar.low.min(trade.price);
+                    bar.close = trade.price;
+                    bar.volume += trade.quantity_base;
+                    bar.quote_volume = Some(bar.quote_volume.unwrap_or(0.0) + trade.quantity_quote);
+                }
+                // older than the first bar of the symbol
+                Some(timestamp) if timestamp > start => {}
+                _ => {
+                    if let Some(bar) = self
+                        .bars
+                        .insert(key.clone(), new_bar(trade, start, *period))
+                    {
+                        self.close(key, &bar);
+                        closed.push(bar);
+                    }
+                }
+            }
+        }
+        closed
+    }
+
+    /// Close the bars that ended before `now`.
+    pub fn close_expired(&mut self, now: i64) -> Vec<KlineMsg> {
+        let expired: Vec<(String, u64)> = self
+            .bars
+            .iter()
+            .filter(|((_, period), bar)| {
+                bar.timestamp + (*period * 1000) as i64 + CLOSE_DELAY_MS <= now
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        let mut closed = Vec::new();
+        for key in expired {
+            if let Some(bar) = self.bars.remove(&key) {
+                self.close(key, &bar);
+                closed.push(bar);
+            }
+        }
+        closed
+    }
+}
+
+fn new_bar(trade: &TradeMsg, start: i64, period: u64) -> KlineMsg {
+    KlineMsg {
+        exchange: trade.exchange.clone(),
+        market_type: trade.market_type,
+        msg_type: MessageType::Candlestick,
+        symbol: trade.symbol.clone(),
+        pair: trade.pair.clone(),
+        timestamp: start,
+        period: period.to_string(),
+        open: trade.price,
+        high: trade.price,
+        low: trade.price,
+        close: trade.price,
+        volume: trade.quantity_base,
+        quote_volume: Some(trade.quantity_quote),
+        json: String::new(),
+    }
+}
+
+/// Aggregate the trades parsed by the writer thread into bars, sent to the sinks on
+/// `exchange_market_type_candlestick_symbol_period` topics when they close.
+pub(super) async fn create_candlestick_thread(
+    mut rx: UnboundedReceiver<TradeMsg>,
+    periods: Vec<u64>,
+    sinks: Sinks,
+) {
+    let mut aggregator = CandlestickAggregator::new(periods);
+
+    loop {
+        let mut closed = Vec::new();
+        match tokio::time::timeout(Duration::from_secs(1), rx.recv()).await {
+            Ok(Some(trade)) => closed.extend(aggregator.add(&trade)),
+            Ok(None) => break,
+            Err(_) => {}
+        }
+
+        let now = chrono::Utc::now().timestamp_millis();
+        closed.extend(aggregator.close_expired(now));
+
+        for bar in closed {
+            let topic = Topic::new(
+                &bar.exchange,
+                bar.market_type,
+                MessageType::Candlestick,
+                &bar.symbol,
+            )
+            .with_period(&bar.period);
+            match encode_kline(&bar) {
+                Ok(data) => {
+                    record_message(&topic.to_string(), data.len(), None);
+                    sinks.send(topic, data);
+                }
+                Err(err) => error!("failed to encode {}: {}", topic, err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto_msg_parser::{MarketType, TradeSide};
+
+    fn trade(timestamp: i64, price: f64) -> TradeMsg {
+        TradeMsg {
+            exchange: "binance".to_string(),
+            market_type: MarketType::Spot,
+            symbol: "BTCUSDT".to_string(),
+            pair: "BTC/USDT".to_string(),
+            msg_type: MessageType::Trade,
+            timestamp,
+            side: TradeSide::Buy,
+            price,
+            quantity_base: 1.0,
+            quantity_quote: price,
+            quantity_contract: None,
+            trade_id: timestamp.to_string(),
+            json: String::new(),
+        }
+    }
+
+    #[test]
+    fn builds_bars() {
+        let mut aggregator = CandlestickAggregator::new(vec![60]);
+        assert!(aggregator.add(&trade(60_000, 10.0)).is_empty());
+        assert!(aggregator.add(&trade(61_000, 12.0)).is_empty());
+        assert!(aggregator.add(&trade(62_000, 9.0)).is_empty());
+
+        let closed = aggregator.add(&trade(120_000, 11.0));
+        assert_eq!(closed.len(), 1);
+        let bar = &closed[0];
+        assert_eq!(bar.timestamp, 60_000);
+        assert_eq!(bar.period, "60");
+        assert_eq!(
+            (bar.open, bar.high, bar.low, bar.close),
+            (10.0, 12.0, 9.0, 9.0)
+        );
+        assert_eq!(bar.volume, 3.0);
+        assert_eq!(bar.quote_volume, Some(31.0));
+    }
+
+    #[test]
+    fn closes_expired_bars() {
+        let mut aggregator = CandlestickAggregator::new(vec![1, 60]);
+        aggregator.add(&trade(60_000, 10.0));
+
+        let closed = aggregator.close_expired(60_000 + 1000 + CLOSE_DELAY_MS);
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].period, "1");
+        let closed = aggregator.close_expired(120_000 + CLOSE_DELAY_MS);
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].period, "60");
+    }
+
+    #[test]
+    fn drops_late_trades_of_closed_bars() {
+        let mut aggregator = CandlestickAggregator::new(vec![60]);
+        aggregator.add(&trade(60_000, 10.0));
+        assert_eq!(aggregator.close_expired(120_000 + CLOSE_DELAY_MS).len(), 1);
+
+        // would have reopened the bar and published it a second time
+        assert!(aggregator.add(&trade(119_000, 12.0)).is_empty());
+        assert!(aggregator.close_expired(i64::MAX / 2).is_empty());
+
+        aggregator.add(&trade(120_000, 11.0));
+        // late trade of the bar replaced by this one
+        aggregator.add(&trade(180_000, 13.0));
+        assert!(aggregator.add(&trade(150_000, 14.0)).is_empty());
+        let closed = aggregator.close_expired(i64::MAX / 2);
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].timestamp, 180_000);
+        assert_eq!(closed[0].close, 13.0);
+    }
+}
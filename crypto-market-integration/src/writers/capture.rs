@@ -0,0 +1,335 @@
+use crypto_crawler::{MarketType, Message, MessageType};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use log::*;
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::Receiver,
+        Arc,
+    },
+};
+
+const MAGIC: &[u8; 4] = b"CMC2";
+// captures written before records had a period
+const MAGIC_V1: &[u8; 4] = b"CMC1";
+// The gzip stream is flushed every so many messages, so a capture cut short
+// by a crash is readable up to the last flush
+const FLUSH_EVERY: u64 = 1000;
+
+// Capture threads started by this process, one per feed
+static NEXT_FEED: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes raw crawled messages to a gzip compressed capture file.
+///
+/// Every record is the exchange, market type, message type and period as
+/// length-prefixed strings, `received_at` as a little endian u64 and the
+/// length-prefixed JSON payload. The period is empty but for candlesticks.
+pub struct CaptureWriter {
+    encoder: GzEncoder<BufWriter<fs::File>>,
+    path: String,
+    count: u64,
+}
+
+impl CaptureWriter {
+    pub fn new(path: &str) -> io::Result<Self> {
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = fs::File::create(path)?;
+        let mut encoder = GzEncoder::new(BufWriter::new(file), Compression::default());
+        encoder.write_all(MAGIC)?;
+        Ok(CaptureWriter {
+            encoder,
+            path: path.to_string(),
+            count: 0,
+        })
+    }
+
+    pub fn write(&mut self, msg: &Message, period: &str) -> io::Result<()> {
+        write_str(&mut self.encoder, &msg.exchange)?;
+        write_str(&mut self.encoder, &msg.market_type.to_string())?;
+        write_str(&mut self.encoder, &msg.msg_type.to_string())?;
+        write_str(&mut self.encoder, period)?;
+        self.encoder.write_all(&msg.received_at.to_le_bytes())?;
+        self.encoder
+            .write_all(&(msg.json.len() as u32).to_le_bytes())?;
+        self.encoder.write_all(msg.json.as_bytes())?;
+
+        self.count += 1;
+        if self.count % FLUSH_EVERY == 0 {
+            self.encoder.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn close(self) {
+        if let Err(err) = self.encoder.finish().and_then(|mut file| file.flush()) {
+            error!("{}, {}", self.path, err);
+        }
+    }
+}
+
+fn write_str<W: Write>(writer: &mut W, s: &str) -> io::Result<()> {
+    writer.write_all(&[s.len() as u8])?;
+    writer.write_all(s.as_bytes())
+}
+
+fn read_exact_or_eof<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+fn read_str<R: Read>(reader: &mut R) -> io::Result<String> {
+    let mut len = [0u8; 1];
+    reader.read_exact(&mut len)?;
+    let mut buf = vec![0u8; len[0] as usize];
+    reader.read_exact(&mut buf)?;
+    String::from_utf8(buf).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn invalid_data(what: &str, value: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unknown {}: {}", what, value),
+    )
+}
+
+/// Reads back the messages of a capture file and their period, in the order
+/// they were crawled.
+///
+/// A file cut short ends at its last complete message. Captures of the first
+/// version have no period, it is empty.
+pub struct CaptureReader {
+    decoder: GzDecoder<BufReader<fs::File>>,
+    has_period: bool,
+}
+
+impl CaptureReader {
+    pub fn open(path: &str) -> io::Result<Self> {
+        let file = fs::File::open(path)?;
+        let mut decoder = GzDecoder::new(BufReader::new(file));
+        let mut magic = [0u8; 4];
+        decoder.read_exact(&mut magic)?;
+        let has_period = match &magic {
+            MAGIC => true,
+            MAGIC_V1 => false,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} is not a capture file", path),
+                ))
+            }
+        };
+        Ok(CaptureReader {
+            decoder,
+            has_period,
+        })
+    }
+
+    fn read_message(&mut self) -> io::Result<Option<(Message, String)>> {
+        let mut len = [0u8; 1];
+        if !read_exact_or_eof(&mut self.decoder, &mut len)? {
+            return Ok(None);
+        }
+        let mut exchange = vec![0u8; len[0] as usize];
+        self.decoder.read_exact(&mut exchange)?;
+        let exchange = String::from_utf8(exchange)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        let market_type = read_str(&mut self.decoder)?;
+        let market_type = MarketType::from_str(&market_type)
+            .map_err(|_| invalid_data("market type", &market_type))?;
+        let msg_type = read_str(&mut self.decoder)?;
+        let msg_type =
+            MessageType::from_str(&msg_type).map_err(|_| invalid_data("msg type", &msg_type))?;
+        let period = if self.has_period {
+            read_str(&mut self.decoder)?
+        } else {
+            String::new()
+        };
+
+        let mut received_at = [0u8; 8];
+        self.decoder.read_exact(&mut received_at)?;
+        let mut json_len = [0u8; 4];
+        self.decoder.read_exact(&mut json_len)?;
+        let mut json = vec![0u8; u32::from_le_bytes(json_len) as usize];
+        self.decoder.read_exact(&mut json)?;
+        let json = String::from_utf8(json)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        let msg = Message {
+            exchange,
+            market_type,
+            msg_type,
+            received_at: u64::from_le_bytes(received_at),
+            json,
+        };
+        Ok(Some((msg, period)))
+    }
+}
+
+impl Iterator for CaptureReader {
+    type Item = (Message, String);
+
+    fn next(&mut self) -> Option<(Message, String)> {
+        match self.read_message() {
+            Ok(msg) => msg,
+            Err(err) => {
+                if err.kind() != io::ErrorKind::UnexpectedEof {
+                    error!("failed to read the capture: {}", err);
+                } else {
+                    warn!("the capture ends with a truncated message");
+                }
+                None
+            }
+        }
+    }
+}
+
+// The file name of a capture, feeds of the same exchange, market type and
+// message type, e.g. candlesticks of several periods or several symbol
+// subsets, tell theirs apart by period and feed id
+fn capture_name(msg: &Message, period: &str, started_at: &str, feed: &str) -> String {
+    let mut name = format!("{}_{}_{}", msg.exchange, msg.market_type, msg.msg_type);
+    if !period.is_empty() {
+        name.push('_');
+        name.push_str(period);
+    }
+    format!("{}_{}_{}.capture.gz", name, started_at, feed)
+}
+
+/// Capture raw messages under `capture_dir`, one file per exchange, market
+/// type and message type of the feed, named after the period, the time the
+/// capture started and an id of the feed unique across processes.
+pub(super) async fn create_capture_thread(
+    rx: Receiver<Arc<Message>>,
+    capture_dir: String,
+    period: Arc<String>,
+) {
+    let feed = format!(
+        "{}-{}",
+        std::process::id(),
+        NEXT_FEED.fetch_add(1, Ordering::Relaxed)
+    );
+    tokio::task::spawn_blocking(move || {
+        let started_at = chrono::Utc::now().format("%Y%m%d%H%M%S").to_string();
+        let mut writers: HashMap<(String, MarketType, MessageType), Option<CaptureWriter>> =
+            HashMap::new();
+
+        for msg in rx {
+            let key = (msg.exchange.clone(), msg.market_type, msg.msg_type);
+            let writer = writers.entry(key).or_insert_with(|| {
+                let path =
+                    Path::new(&capture_dir).join(capture_name(&msg, &period, &started_at, &feed));
+                let path = path.to_string_lossy();
+                match CaptureWriter::new(&path) {
+                    Ok(writer) => Some(writer),
+                    Err(err) => {
+                        error!("failed to create the capture {}: {}", path, err);
+                        None
+                    }
+                }
+            });
+            if let Some(capture) = writer {
+                if let Err(err) = capture.write(&msg, &period) {
+                    error!("{}, {}", capture.path, err);
+                }
+            }
+        }
+
+        for writer in writers.into_values().flatten() {
+            writer.close();
+        }
+    })
+    .await
+    .expect("create_capture_thread failed");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(msg_type: MessageType, received_at: u64, json: &str) -> Message {
+        Message {
+            exchange: "binance".to_string(),
+            market_type: MarketType::Spot,
+            msg_type,
+            received_at,
+            json: json.to_string(),
+        }
+    }
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!(
+                "capture-{}-{}.capture.gz",
+                name,
+                std::process::id()
+            ))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    #[test]
+    fn reads_back_what_was_written() {
+        let path = temp_path("round-trip");
+        let written = vec![
+            (message(MessageType::Trade, 1, r#"{"p":"1.0"}"#), ""),
+            (message(MessageType::Candlestick, 2, r#"{"k":{}}"#), "60"),
+        ];
+        let mut writer = CaptureWriter::new(&path).unwrap();
+        for (msg, period) in written.iter() {
+            writer.write(msg, period).unwrap();
+        }
+        writer.close();
+
+        let read: Vec<(Message, String)> = CaptureReader::open(&path).unwrap().collect();
+        assert_eq!(read.len(), written.len());
+        for ((msg, period), (expected, expected_period)) in read.iter().zip(written.iter()) {
+            assert_eq!(msg.exchange, expected.exchange);
+            assert_eq!(msg.market_type, expected.market_type);
+            assert_eq!(msg.msg_type, expected.msg_type);
+            assert_eq!(msg.received_at, expected.received_at);
+            assert_eq!(msg.json, expected.json);
+            assert_eq!(period, expected_period);
+        }
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn names_captures_after_the_feed() {
+        let trade = message(MessageType::Trade, 1, "{}");
+        let candlestick = message(MessageType::Candlestick, 1, "{}");
+        assert_eq!(
+            capture_name(&trade, "", "20220801000000", "42-0"),
+            "binance_spot_trade_20220801000000_42-0.capture.gz"
+        );
+        assert_ne!(
+            capture_name(&candlestick, "60", "20220801000000", "42-0"),
+            capture_name(&candlestick, "300", "20220801000000", "42-1")
+        );
+        assert_eq!(
+            capture_name(&candlestick, "60", "20220801000000", "42-0"),
+            "binance_spot_candlestick_60_20220801000000_42-0.capture.gz"
+        );
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let path = temp_path("not-a-capture");
+        let mut encoder = GzEncoder::new(fs::File::create(&path).unwrap(), Compression::default());
+        encoder.write_all(b"JSON").unwrap();
+        encoder.finish().unwrap();
+
+        assert!(CaptureReader::open(&path).is_err());
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,119 @@
+use crypto_crawler::Message;
+use crypto_market_file::FileWriter;
+use crypto_market_topic::{Topic, TopicType};
+use log::*;
+use std::collections::HashMap;
+
+/// Messages that failed to be parsed or encoded, kept together with the reason.
+///
+/// Every dead letter is counted and logged, and archived under
+/// `data_dir/dead_letter` if a data directory is configured.
+pub(super) struct DeadLetters {
+    count: u64,
+    data_dir: Option<String>,
+    // `None` for files that failed to open
+    files: HashMap<Topic, Option<FileWriter>>,
+}
+
+impl DeadLetters {
+    pub fn new(data_dir: Option<String>) -> Self {
+        DeadLetters {
+            count: 0,
+            data_dir,
+            files: HashMap::new(),
+        }
+    }
+
+    /// Record a bad message, returns the dead-letter topic and the JSON line
+    /// to publish on it.
+    pub fn add(&mut self, msg: &Message, reason: &str) -> (Topic, String) {
+        self.count += 1;
+        error!(
+            "dead letter #{} from {} {} {}: {}",
+            self.count, msg.exchange, msg.market_type, msg.msg_type, reason
+        );
+
+        let topic = Topic::new(
+            &msg.exchange,
+            msg.market_type,
+            TopicType::DeadLetter,
+            &msg.msg_type.to_string(),
+        );
+        let line = serde_json::json!({
+            "reason": reason,
+            "message": msg,
+        })
+        .to_string();
+
+        if let Some(ref data_dir) = self.data_dir {
+            let writer = self.files.entry(topic.clone()).or_insert_with_key(|topic| {
+                let path = std::path::Path::new(data_dir)
+                    .join("dead_letter")
+                    .join(format!("{}.json", topic.file_name()));
+                match FileWriter::new(&path.to_string_lossy()) {
+                    Ok(writer) => Some(writer),
+                    Err(err) => {
+                        error!("failed to open {}: {}", path.display(), err);
+                        None
+                    }
+                }
+            });
+            if let Some(writer) = writer {
+                writer.write(&line);
+            }
+        }
+
+        (topic, line)
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn close(&mut self) {
+        for writer in self.files.values_mut().flatten() {
+            writer.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writers::parser::parse_message;
+    use crypto_crawler::{MarketType, MessageType};
+
+    #[test]
+    fn archives_unparseable_messages_with_their_error() {
+        let data_dir = std::env::temp_dir().join(format!("dead-letters-{}", std::process::id()));
+        let mut dead_letters = DeadLetters::new(Some(data_dir.to_string_lossy().to_string()));
+        let msg = Message::new(
+            "binance".to_string(),
+            MarketType::Spot,
+            MessageType::Trade,
+            r#"{"stream":"btcusdt@trade","data":{}}"#.to_string(),
+        );
+        let reason = match parse_message(&msg, false) {
+            Ok(_) => panic!("parsed an empty trade"),
+            Err(err) => err.to_string(),
+        };
+
+        let (topic, line) = dead_letters.add(&msg, &reason);
+        assert_eq!(topic.to_string(), "binance_spot_dead_letter_trade");
+        assert_eq!(dead_letters.count(), 1);
+        dead_letters.add(&msg, &reason);
+        assert_eq!(dead_letters.count(), 2);
+        dead_letters.close();
+
+        let path = data_dir
+            .join("dead_letter")
+            .join(format!("{}.json", topic.file_name()));
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines, vec![line.as_str(), line.as_str()]);
+        let letter: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(letter["reason"], reason);
+        assert_eq!(letter["message"]["json"], msg.json);
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+}
@@ -0,0 +1,218 @@
+use crypto_contract_value::get_contract_value;
+use crypto_crawler::MarketType;
+use crypto_pair::normalize_pair;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Instruments are republished this often, for subscribers that joined late
+const REPUBLISH_INTERVAL: Duration = Duration::from_secs(60);
+
+const MONTHS: [&str; 12] = [
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+
+// The expiry of a dated contract as YYMMDD, e.g. `210625` of `BTCUSD_210625`,
+// `BTC-USD-210625`, `BTC_USD_20210625`, `BTC210625` or `BTC-25JUN21`
+fn expiry(symbol: &str) -> Option<String> {
+    let all_digits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    for part in symbol.split(|c: char| c == '_' || c == '-') {
+        if (part.len() == 6 || part.len() == 8) && all_digits(part) {
+            return Some(part[part.len() - 6..].to_string());
+        }
+        // DDMMMYY
+        let n = part.len();
+        if (n == 6 || n == 7) && part.is_ascii() {
+            let (day, month, year) = (&part[..n - 5], &part[n - 5..n - 2], &part[n - 2..]);
+            if let Some(month) = MONTHS.iter().position(|m| *m == month) {
+                if all_digits(day) && all_digits(year) {
+                    return Some(format!("{}{:02}{:0>2}", year, month + 1, day));
+                }
+            }
+        }
+    }
+    let digits = symbol.len() - symbol.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    if digits == 6 {
+        return Some(symbol[symbol.len() - 6..].to_string());
+    }
+    None
+}
+
+/// The key of an instrument in the normalized topics.
+///
+/// Spot and perpetual markets are keyed by the pair. Dated futures of the same
+/// pair are told apart by their expiry, or the exchange symbol if it has none,
+/// e.g. `BTC/USD-210625`, and options by the exchange symbol, which carries the
+/// strike too.
+pub(super) fn normalized_key(market_type: MarketType, symbol: &str, pair: &str) -> String {
+    match market_type {
+        MarketType::Spot
+        | MarketType::LinearSwap
+        | MarketType::InverseSwap
+        | MarketType::QuantoSwap
+        | MarketType::Unknown => pair.to_string(),
+        MarketType::LinearFuture | MarketType::InverseFuture | MarketType::QuantoFuture => {
+            format!(
+                "{}-{}",
+                pair,
+                expiry(symbol).unwrap_or_else(|| symbol.to_string())
+            )
+        }
+        _ => format!("{}-{}", pair, symbol),
+    }
+}
+
+/// Reference data of one instrument, to match it across exchanges.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Instrument {
+    pub exchange: String,
+    pub market_type: MarketType,
+    /// Exchange-specific symbol, e.g. `XBTUSD`
+    pub symbol: String,
+    /// Normalized pair, e.g. `BTC/USD`
+    pub pair: String,
+    /// Key of the normalized topics, see [`normalized_key`]
+    pub key: String,
+    pub base: String,
+    pub quote: String,
+    /// Currency the contract settles in, `None` for spot
+    pub settle: Option<String>,
+    /// Base (inverse) or quote (linear) amount of one contract
+    pub contract_value: Option<f64>,
+}
+
+impl Instrument {
+    fn new(exchange: &str, market_type: MarketType, symbol: &str, pair: &str) -> Option<Self> {
+        let (base, quote) = pair.split_once('/')?;
+        let settle = match market_type {
+            MarketType::Spot | MarketType::Unknown => None,
+            MarketType::InverseFuture | MarketType::InverseSwap => Some(base.to_string()),
+            _ => Some(quote.to_string()),
+        };
+        Some(Instrument {
+            exchange: exchange.to_string(),
+            market_type,
+            symbol: symbol.to_string(),
+            pair: pair.to_string(),
+            key: normalized_key(market_type, symbol, pair),
+            base: base.to_string(),
+            quote: quote.to_string(),
+            settle,
+            contract_value: get_contract_value(exchange, market_type, pair),
+        })
+    }
+}
+
+/// Instruments seen by a writer, keyed by exchange symbol.
+pub(super) struct Instruments {
+    instruments: HashMap<(String, MarketType, String), Option<Instrument>>,
+    published_at: Instant,
+}
+
+impl Instruments {
+    pub fn new() -> Self {
+        Instruments {
+            instruments: HashMap::new(),
+            published_at: Instant::now(),
+        }
+    }
+
+    /// Every instrument once `REPUBLISH_INTERVAL` has passed since the last
+    /// time, nothing otherwise.
+    pub fn republish(&mut self, now: Instant) -> Vec<&Instrument> {
+        if now.duration_since(self.published_at) < REPUBLISH_INTERVAL {
+            return Vec::new();
+        }
+        self.published_at = now;
+        self.instruments.values().flatten().collect()
+    }
+
+    /// Look up an instrument, the bool is true the first time it is seen.
+    ///
+    /// `pair` is the pair from the parsed message if any, otherwise it is
+    /// normalized from the symbol.
+    pub fn get(
+        &mut self,
+        exchange: &str,
+        market_type: MarketType,
+        symbol: &str,
+        pair: Option<&str>,
+    ) -> (Option<&Instrument>, bool) {
+        let key = (exchange.to_string(), market_type, symbol.to_string());
+        let mut is_new = false;
+        let instrument = self.instruments.entry(key).or_insert_with(|| {
+            is_new = true;
+            let pair = match pair {
+                Some(pair) if !pair.is_empty() => Some(pair.to_string()),
+                _ => normalize_pair(symbol, exchange),
+            };
+            pair.and_then(|pair| Instrument::new(exchange, market_type, symbol, &pair))
+        });
+        (instrument.as_ref(), is_new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spot_and_swaps_are_keyed_by_pair() {
+        assert_eq!(
+            normalized_key(MarketType::Spot, "BTCUSDT", "BTC/USDT"),
+            "BTC/USDT"
+        );
+        assert_eq!(
+            normalized_key(MarketType::InverseSwap, "BTCUSD_PERP", "BTC/USD"),
+            "BTC/USD"
+        );
+    }
+
+    #[test]
+    fn futures_are_keyed_by_expiry() {
+        for symbol in [
+            "BTCUSD_210625",
+            "BTC-USD-210625",
+            "BTC_USD_20210625",
+            "BTC210625",
+            "BTC-25JUN21",
+        ] {
+            assert_eq!(
+                normalized_key(MarketType::InverseFuture, symbol, "BTC/USD"),
+                "BTC/USD-210625",
+                "{}",
+                symbol
+            );
+        }
+        assert_eq!(
+            normalized_key(MarketType::InverseFuture, "BTC-5JUL21", "BTC/USD"),
+            "BTC/USD-210705"
+        );
+        // no expiry in the symbol
+        assert_eq!(
+            normalized_key(MarketType::InverseFuture, "XBTZ21", "BTC/USD"),
+            "BTC/USD-XBTZ21"
+        );
+    }
+
+    #[test]
+    fn options_are_keyed_by_symbol() {
+        assert_eq!(
+            normalized_key(MarketType::EuropeanOption, "ETH-26MAR21-1600-C", "ETH/USD"),
+            "ETH/USD-ETH-26MAR21-1600-C"
+        );
+    }
+
+    #[test]
+    fn republishes_after_the_interval() {
+        let mut instruments = Instruments::new();
+        instruments.get("binance", MarketType::Spot, "BTCUSDT", Some("BTC/USDT"));
+        let now = Instant::now();
+        assert!(instruments.republish(now).is_empty());
+
+        let later = now + REPUBLISH_INTERVAL;
+        assert_eq!(instruments.republish(later).len(), 1);
+        assert!(instruments.republish(later).is_empty());
+    }
+}
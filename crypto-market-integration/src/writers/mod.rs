@@ -1,209 +1,407 @@
+mod candlestick;
+mod capture;
+mod dead_letter;
+mod instruments;
+mod parser;
+mod queue;
+mod redis_writer;
+mod sequence;
+mod sink;
+
+use crate::config::Outputs;
+use crate::shutdown;
+use crate::metrics::{record_dropped, record_message, record_parse_failure};
 use crypto_crawler::*;
-use crypto_msg_parser::{parse_bbo, parse_candlestick, parse_l2, parse_l2_topk, parse_trade, parse_funding_rate};
+use crypto_market_file::FileWriter;
+use crypto_market_topic::{Publisher, Topic, TopicType};
+use crypto_msg_parser::TradeMsg;
 use futures::{future::BoxFuture, FutureExt};
 use log::*;
-use tokio::io::AsyncWriteExt;
-use wmjtyd_libstock::message::zeromq::Pub;
-use wmjtyd_libstock::message::zeromq::Zeromq;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
 
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    panic::{catch_unwind, AssertUnwindSafe},
     sync::{
         mpsc::{Receiver, Sender},
-        Arc,
-    }
+        Arc, Mutex,
+    },
+    time::Instant,
 };
 
+pub use capture::{CaptureReader, CaptureWriter};
+pub use instruments::Instrument;
+pub use parser::ParseError;
+pub use queue::{MessageQueue, QueuePolicy};
+pub use sink::{Sink, SinkKind, SinkMessage, Sinks};
+pub(crate) use parser::extract_raw_symbol;
+
+use candlestick::create_candlestick_thread;
+use capture::create_capture_thread;
+use dead_letter::DeadLetters;
+use instruments::Instruments;
+use parser::{parse_message, EncodedMessage};
+use queue::bounded;
+use sequence::{fetch_l2_snapshot, Sequence, SequenceTracker};
+use sink::create_sinks;
 
-use wmjtyd_libstock::data::{
-    bbo::encode_bbo,
-    kline::encode_kline,
-    orderbook::encode_orderbook,
-    trade::encode_trade,
-    funding_rate::encode_funding_rate,
-};
 
-pub trait Writer {
-    fn write(&mut self, s: &str);
-    fn close(&mut self);
+// Open the publisher of a topic unless it is already. Topics whose publisher
+// fails to open are logged once and their messages dropped.
+pub(crate) async fn open_publisher(
+    writers: &mut HashMap<Topic, Option<Publisher>>,
+    topic: &Topic,
+) {
+    if !writers.contains_key(topic) {
+        let publisher = match Publisher::for_topic(topic).await {
+            Ok(publisher) => Some(publisher),
+            Err(err) => {
+                error!("failed to open the publisher of {}: {}", topic, err);
+                None
+            }
+        };
+        writers.insert(topic.clone(), publisher);
+    }
 }
 
+// Send a message to the publisher of its topic, opened on first use.
+pub(crate) async fn publish(
+    writers: &mut HashMap<Topic, Option<Publisher>>,
+    topic: &Topic,
+    data: &[u8],
+) {
+    debug!("{}", topic);
+    open_publisher(writers, topic).await;
+    if let Some(Some(publisher)) = writers.get_mut(topic) {
+        if let Err(err) = publisher.send(data).await {
+            error!("failed to publish {}: {}", topic, err);
+        }
+    }
+}
 
-// Quickly create a message queue
-async fn create(name: &String) -> impl tokio::io::AsyncWriteExt  {
-    let file_name = name.replacen("/", "-", 3);
+// The topic of a crawled message of `symbol`
+fn topic_of(msg: &Message, symbol: &str, period: &str) -> Topic {
+    Topic::new(&msg.exchange, msg.market_type, msg.msg_type, symbol).with_period(period)
+}
 
-    let ipc_exchange_market_type_msg_type =
-        format!("ipc:///tmp/{}.ipc", file_name);
-    
-    let socket = match Zeromq::<Pub>::new(&ipc_exchange_market_type_msg_type).await {
-        Ok(v) =>  v,
-        Err(msg) => panic!("init publish error: {}; msg: {:?}", ipc_exchange_market_type_msg_type, msg)
-    };
-    socket
+pub(crate) fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s
+    } else {
+        "unknown panic"
+    }
+}
+
+// The topic of a parsed message, raw payloads have a topic type of their own
+fn encoded_topic(msg: &Message, encoded: &EncodedMessage, period: &str) -> Topic {
+    let topic = topic_of(msg, &encoded.symbol, period);
+    if encoded.raw {
+        Topic {
+            msg_type: TopicType::Raw(msg.msg_type),
+            ..topic
+        }
+    } else {
+        topic
+    }
+}
+
+/// Parse and encode a crawled message the way the writer thread does, returns
+/// the topic and the payload of every symbol.
+pub fn encode_topics(msg: &Message, period: &str) -> Result<Vec<(Topic, Vec<u8>)>, ParseError> {
+    Ok(parse_message(msg, false)?
+        .into_iter()
+        .map(|encoded| (encoded_topic(msg, &encoded, period), encoded.data))
+        .collect())
+}
+
+fn instrument_topic(instrument: &Instrument) -> Topic {
+    Topic::new(
+        &instrument.exchange,
+        instrument.market_type,
+        TopicType::Instrument,
+        "ALL",
+    )
 }
 
 async fn create_writer_thread(
-    rx: Receiver<Message>,
-    tx_redis: Option<Sender<Arc<Message>>>,
-    exchange: &'static str,
-    market_type: MarketType,
-    msg_type: MessageType,
+    queue: MessageQueue,
+    sinks: Sinks,
+    tx_archives: Vec<Sender<Arc<Message>>>,
+    tx_candlestick: Option<UnboundedSender<TradeMsg>>,
+    data_dir: Option<String>,
     period: Arc<String>,
+    normalized_topics: bool,
 ) {
-    tokio::task::spawn(async move {
-        let mut writers= HashMap::new();
+    // a blocking thread of its own, so it runs on any runtime
+    tokio::task::spawn_blocking(move || {
+        let mut instruments = Instruments::new();
+        let mut dead_letters = DeadLetters::new(data_dir);
+        let mut sequences = SequenceTracker::new();
+        // symbols waiting for a snapshot after a sequence gap
+        let resyncing: Arc<Mutex<HashSet<String>>> = Arc::default();
 
-        for msg in rx {
+        while let Some(msg) = queue.pop() {
             debug!("msg ->> yes");
-            let msg = Arc::new(msg);
-            let msg_r = msg.clone();
-            let mut data_vec = Vec::new();
 
             // Convert the message to &[u8]
-            match msg_type {
-                MessageType::BBO => {
-                    let received_at = msg_r.received_at;
-                    let bbo_msg = tokio::task::spawn_blocking(move || {
-                        parse_bbo(
-                            exchange,
-                            MarketType::Spot,
-                            &msg_r.json,
-                            Some(received_at as i64),
-                        )
-                        .unwrap()
-                    })
-                    .await
-                    .unwrap();
-
-
-                    let symbol = bbo_msg.symbol.to_owned();
-                    let byte_data = encode_bbo(&bbo_msg).unwrap();
-                    data_vec.push((symbol, byte_data));
+            let parsed =
+                match catch_unwind(AssertUnwindSafe(|| parse_message(&msg, normalized_topics))) {
+                    Ok(parsed) => parsed.map_err(|err| err.to_string()),
+                    Err(err) => Err(format!("parser panicked: {}", panic_message(err.as_ref()))),
+                };
+            let data_vec = match parsed {
+                Ok(data_vec) => data_vec,
+                Err(reason) => {
+                    let symbol = extract_raw_symbol(&msg.exchange, msg.market_type, &msg.json);
+                    let topic = topic_of(&msg, &symbol, &period);
+                    record_parse_failure(&topic.to_string());
+
+                    let (topic, line) = dead_letters.add(&msg, &reason);
+                    record_message(&topic.to_string(), line.len(), None);
+                    sinks.send(topic, line.into_bytes());
+                    continue;
                 }
-                MessageType::Trade => {
-                    let trade_msg = tokio::task::spawn_blocking(move || {
-                        parse_trade(exchange, MarketType::Spot, &msg_r.json).unwrap()
-                    })
-                    .await
-                    .unwrap();
-
-
-                    for trdate in trade_msg {
-                        let symbol = trdate.symbol.to_owned();
-                        let byte_data = encode_trade(&trdate).unwrap();
-                        data_vec.push((symbol, byte_data));
+            };
+
+            for mut encoded in data_vec {
+                // build candlesticks from the parsed trades
+                if let (Some(tx_candlestick), Some(trade)) =
+                    (&tx_candlestick, encoded.trade.take())
+                {
+                    if tx_candlestick.send(trade).is_err() {
+                        error!("The candlestick thread has exited");
                     }
                 }
-                MessageType::L2Event => {
-                    let orderbook_msg = tokio::task::spawn_blocking(move || {
-                        parse_l2(exchange, MarketType::Spot, &msg_r.json, None).unwrap()
-                    })
-                    .await
-                    .unwrap();
-
-                    for orderbook in orderbook_msg {
-                        let symbol = orderbook.symbol.to_owned();
-                        let byte_data = encode_orderbook(&orderbook).unwrap();
-                        data_vec.push((symbol, byte_data));
+
+                if msg.msg_type == MessageType::L2Event {
+                    let gap = match sequences.check(
+                        &encoded.symbol,
+                        encoded.seq_id,
+                        encoded.prev_seq_id,
+                    ) {
+                        Sequence::InOrder => None,
+                        Sequence::Stale(gap) => {
+                            warn!(
+                                "{} {} {} stale update dropped: {:?}",
+                                msg.exchange, msg.market_type, encoded.symbol, gap
+                            );
+                            record_dropped(&encoded_topic(&msg, &encoded, &period).to_string());
+                            continue;
+                        }
+                        Sequence::Gap(gap) => Some(gap),
+                    };
+                    if let Some(gap) = gap {
+                        warn!(
+                            "{} {} {} sequence gap: {:?}",
+                            msg.exchange, msg.market_type, encoded.symbol, gap
+                        );
+                        let topic = Topic::new(
+                            &msg.exchange,
+                            msg.market_type,
+                            TopicType::L2Gap,
+                            &encoded.symbol,
+                        );
+                        let event = serde_json::json!({
+                            "exchange": msg.exchange,
+                            "market_type": msg.market_type.to_string(),
+                            "symbol": encoded.symbol,
+                            "last_seq_id": gap.last_seq_id,
+                            "seq_id": gap.seq_id,
+                            "prev_seq_id": gap.prev_seq_id,
+                            "received_at": msg.received_at,
+                        })
+                        .to_string();
+                        sinks.send(topic, event.into_bytes());
+
+                        if resyncing.lock().unwrap().insert(encoded.symbol.clone()) {
+                            let exchange = msg.exchange.clone();
+                            let market_type = msg.market_type;
+                            let symbol = encoded.symbol.clone();
+                            let queue = queue.clone();
+                            let tx_archives = tx_archives.clone();
+                            let resyncing = resyncing.clone();
+                            // the snapshot is archived and goes through the
+                            // queue like crawled messages, which wakes the
+                            // writer up
+                            tokio::task::spawn_blocking(move || {
+                                match fetch_l2_snapshot(&exchange, market_type, &symbol) {
+                                    Ok(snapshot) => {
+                                        let snapshot = Arc::new(snapshot);
+                                        for tx_archive in tx_archives.iter() {
+                                            let _ = tx_archive.send(snapshot.clone());
+                                        }
+                                        queue.push(snapshot);
+                                    }
+                                    Err(err) => warn!(
+                                        "Failed to fetch a snapshot of {} to resync: {}",
+                                        symbol, err
+                                    ),
+                                }
+                                resyncing.lock().unwrap().remove(&symbol);
+                            });
+                        }
                     }
                 }
-                MessageType::L2TopK => {
-                    let received_at = msg.received_at as i64;
-                    let orderbook_msg = tokio::task::spawn_blocking(move || {
-                        parse_l2_topk(exchange, MarketType::Spot, &msg_r.json, Some(received_at))
-                            .unwrap()
-                    })
-                    .await
-                    .unwrap();
-
-                    for orderbook in orderbook_msg {
-                        let symbol = orderbook.symbol.to_owned();
-                        let byte_data = encode_orderbook(&orderbook).unwrap();
-                        data_vec.push((symbol, byte_data));
+
+                let topic = encoded_topic(&msg, &encoded, &period);
+                record_message(
+                    &topic.to_string(),
+                    encoded.data.len(),
+                    encoded
+                        .timestamp
+                        .map(|timestamp| msg.received_at as i64 - timestamp),
+                );
+
+                // the same data under the normalized key
+                if let Some((key, data)) = encoded.normalized.take() {
+                    let (instrument, is_new) = instruments.get(
+                        &msg.exchange,
+                        msg.market_type,
+                        &encoded.symbol,
+                        encoded.pair.as_deref(),
+                    );
+                    if let (Some(instrument), true) = (instrument, is_new) {
+                        sinks.send(
+                            instrument_topic(instrument),
+                            serde_json::to_vec(instrument).unwrap(),
+                        );
                     }
+                    sinks.send(topic.clone().normalized(&key), data);
                 }
-                MessageType::Candlestick => {
-                    let kline_msg = tokio::task::spawn_blocking(move || {
-                        parse_candlestick(exchange, market_type, &msg_r.json, msg_type).unwrap()
-                    })
-                    .await
-                    .unwrap();
-
-                    let symbol = kline_msg.symbol.to_owned();
-                    let byte_data = encode_kline(&kline_msg).unwrap();
-                    data_vec.push((symbol, byte_data));
-                }
-                MessageType::FundingRate => {
-                    let funding_rate_msg = tokio::task::spawn_blocking(move || {
-                        println!("{}", msg_r.json);
-                        parse_funding_rate(exchange, market_type, &msg_r.json, Some(1232))
-                    }).await.unwrap().unwrap();
 
-                    for mut funding_rate in funding_rate_msg {
+                sinks.send(topic, encoded.data);
+            }
 
-                        if  None == funding_rate.estimated_rate  {
-                            funding_rate.estimated_rate = Some(0.0);
-                        }
+            for instrument in instruments.republish(Instant::now()) {
+                sinks.send(
+                    instrument_topic(instrument),
+                    serde_json::to_vec(instrument).unwrap(),
+                );
+            }
+        }
 
-                        let symbol = funding_rate.symbol.to_owned();
-                        let byte_data = encode_funding_rate(&funding_rate).unwrap();
-                        data_vec.push((symbol, byte_data));
-                    }
+        if dead_letters.count() > 0 {
+            warn!("{} messages were sent to the dead letter topic", dead_letters.count());
+        }
+        dead_letters.close();
+    })
+    .await
+    .expect("create_writer_thread failed");
+}
 
-                }
-                _ => panic!("Not implemented"),
-            };
+/// Archive raw messages as JSON lines, one file per topic under `data_dir`.
+///
+/// Files are reopened on SIGHUP, so they can be rotated by logrotate.
+async fn create_file_writer_thread(
+    rx: Receiver<Arc<Message>>,
+    data_dir: String,
+    period: Arc<String>,
+) {
+    tokio::task::spawn_blocking(move || {
+        // `None` for files that failed to open, their messages are dropped
+        let mut writers: HashMap<Topic, Option<FileWriter>> = HashMap::new();
 
-            // Send a message to the corresponding message queue
-            for (symbol, data_byte) in data_vec {
-                let key = if period.len() != 0 {
-                    format!(
-                        "{}_{}_{}_{}_{}",
-                        msg.exchange, msg.market_type, msg.msg_type, symbol, period
-                    )
-                } else {
-                    format!(
-                        "{}_{}_{}_{}",
-                        msg.exchange, msg.market_type, msg.msg_type, symbol
-                    )
-                };
-                debug!("{}", key);
-                let writer_mq = if writers.contains_key(&key) {
-                    writers.get_mut(&key).unwrap()
-                } else {
-                    println!("{key}");
-                    let socket = create(&key).await;
-                    writers.insert(key.to_owned(), socket);
-                    writers.get_mut(&key).unwrap()
-                };
-                writer_mq.write(&data_byte).await.unwrap();
+        for msg in rx {
+            let symbol = extract_raw_symbol(&msg.exchange, msg.market_type, &msg.json);
+            let topic = topic_of(&msg, &symbol, &period);
+            let writer = writers.entry(topic).or_insert_with_key(|topic| {
+                let path = std::path::Path::new(&data_dir)
+                    .join(msg.msg_type.to_string())
+                    .join(&msg.exchange)
+                    .join(msg.market_type.to_string())
+                    .join(format!("{}.json", topic.file_name()));
+                match FileWriter::new(&path.to_string_lossy()) {
+                    Ok(writer) => Some(writer),
+                    Err(err) => {
+                        error!("failed to open {}: {}", path.display(), err);
+                        None
+                    }
+                }
+            });
+            if let Some(writer) = writer {
+                writer.write(&serde_json::to_string(msg.as_ref()).unwrap());
             }
+        }
 
-            // copy to redis
-            if let Some(ref tx_redis) = tx_redis {
-                tx_redis.send(msg).unwrap();
-            }
+        for writer in writers.values_mut().flatten() {
+            writer.close();
         }
     })
     .await
-    .expect("create_nanomsg_writer_thread failed");
+    .expect("create_file_writer_thread failed");
 }
 
+/// Create the writer pipeline of a feed, every parsed message fans out to
+/// the sinks of `outputs`.
+///
+/// Crawled messages wait in a queue of `queue_capacity` messages, see
+/// [`MessageQueue`] for what happens when the writer falls behind, and every
+/// sink buffers as many. The returned sinks take the events of the crawlers,
+/// such as reconnects, and must be dropped with them. Once the crawlers exit
+/// or a shutdown is requested the queue is drained, then every sink and file
+/// is flushed and closed, and the threads return.
 pub fn create_writer_threads(
     rx: Receiver<Message>,
-    _data_dir: Option<String>,
-    _redis_url: Option<String>,
-    exchange: &'static str,
-    market_type: MarketType,
-    msg_type: MessageType,
+    queue_capacity: usize,
+    outputs: Outputs,
     period: Arc<String>,
-) -> Vec<BoxFuture<'static, ()>> {
-    let mut threads = Vec::new();
+    bar_periods: Vec<u64>,
+    normalized_topics: bool,
+) -> Result<(Sinks, Vec<BoxFuture<'static, ()>>), String> {
+    let Outputs {
+        data_dir,
+        redis_url,
+        capture_dir,
+        sinks,
+        sink_dir,
+    } = outputs;
+    let (sinks, mut threads) = create_sinks(&sinks, redis_url, sink_dir, queue_capacity)?;
+
+    let mut tx_archives = Vec::new();
+    if let Some(ref data_dir) = data_dir {
+        let (tx_file, rx_file) = std::sync::mpsc::channel::<Arc<Message>>();
+        threads.push(
+            create_file_writer_thread(rx_file, data_dir.clone(), period.clone()).boxed(),
+        );
+        tx_archives.push(tx_file);
+    }
+    if let Some(capture_dir) = capture_dir {
+        let (tx_capture, rx_capture) = std::sync::mpsc::channel::<Arc<Message>>();
+        threads.push(create_capture_thread(rx_capture, capture_dir, period.clone()).boxed());
+        tx_archives.push(tx_capture);
+    }
 
+    let tx_candlestick = if !bar_periods.is_empty() {
+        let (tx_candlestick, rx_candlestick) = unbounded_channel::<TradeMsg>();
+        threads.push(
+            create_candlestick_thread(rx_candlestick, bar_periods, sinks.clone()).boxed(),
+        );
+        Some(tx_candlestick)
+    } else {
+        None
+    };
+
+    let queue = bounded(
+        rx,
+        queue_capacity,
+        period.clone(),
+        shutdown::subscribe(),
+        tx_archives.clone(),
+    );
     threads.push(
-        create_writer_thread(rx, None, exchange, market_type, msg_type, period).boxed(),
+        create_writer_thread(
+            queue,
+            sinks.clone(),
+            tx_archives,
+            tx_candlestick,
+            data_dir,
+            period,
+            normalized_topics,
+        )
+        .boxed(),
     );
 
-    threads
+    Ok((sinks, threads))
 }
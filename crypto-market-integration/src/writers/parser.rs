@@ -0,0 +1,492 @@
+use crypto_crawler::{MarketType, Message, MessageType};
+use crypto_msg_parser::{
+    extract_symbol, parse_bbo, parse_candlestick, parse_funding_rate, parse_l2, parse_l2_snapshot,
+    parse_l2_topk, parse_ticker, parse_trade, TradeMsg,
+};
+
+use super::instruments::normalized_key;
+
+use wmjtyd_libstock::data::{
+    bbo::encode_bbo, funding_rate::encode_funding_rate, kline::encode_kline,
+    orderbook::encode_orderbook, trade::encode_trade,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum ParseError {
+    #[error("failed to parse {0} message: {1}")]
+    ParseFailed(MessageType, String),
+
+    #[error("failed to encode {0} message: {1}")]
+    EncodeFailed(MessageType, String),
+
+    #[error("unsupported message type: {0}")]
+    Unsupported(MessageType),
+}
+
+pub type ParseResult<T> = Result<T, ParseError>;
+
+/// The encoded message of one symbol.
+pub struct EncodedMessage {
+    pub symbol: String,
+    pub data: Vec<u8>,
+    /// Sequence numbers of order book updates, if the exchange provides them
+    pub seq_id: Option<u64>,
+    pub prev_seq_id: Option<u64>,
+    /// Exchange timestamp in milliseconds of when the message was sent, the
+    /// latency is measured from it. `None` for raw messages and candlesticks
+    pub timestamp: Option<i64>,
+    /// Normalized pair, e.g. `BTC/USDT`, `None` for raw messages
+    pub pair: Option<String>,
+    /// The exchange JSON, published on a `raw_` topic
+    pub raw: bool,
+    /// The parsed trade, which candlesticks are built from
+    pub trade: Option<TradeMsg>,
+    /// The normalized key and the message encoded with it as the symbol, see
+    /// `parse_message`
+    pub normalized: Option<(String, Vec<u8>)>,
+}
+
+impl EncodedMessage {
+    fn new(symbol: String, data: Vec<u8>) -> Self {
+        EncodedMessage {
+            symbol,
+            data,
+            seq_id: None,
+            prev_seq_id: None,
+            timestamp: None,
+            pair: None,
+            raw: false,
+            trade: None,
+            normalized: None,
+        }
+    }
+
+    fn with_timestamp(mut self, timestamp: i64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    fn with_pair(mut self, pair: String) -> Self {
+        self.pair = Some(pair);
+        self
+    }
+
+    fn with_normalized(mut self, normalized: Option<(String, Vec<u8>)>) -> Self {
+        self.normalized = normalized;
+        self
+    }
+}
+
+// Encode a copy of a parsed message with the normalized key as its symbol,
+// `None` unless `$normalized`
+macro_rules! encode_normalized {
+    ($normalized:expr, $msg_type:expr, $msg:expr, $encode:expr) => {
+        if $normalized {
+            let mut msg = $msg.clone();
+            let key = normalized_key(msg.market_type, &msg.symbol, &msg.pair);
+            msg.symbol = key.clone();
+            let data =
+                $encode(&msg).map_err(|e| ParseError::EncodeFailed($msg_type, e.to_string()))?;
+            Some((key, data))
+        } else {
+            None
+        }
+    };
+}
+
+/// Extract the symbol of a raw message, `ALL` if there is none or the
+/// exchange payload is not recognized.
+pub fn extract_raw_symbol(exchange: &str, market_type: MarketType, json: &str) -> String {
+    match extract_symbol(exchange, market_type, json) {
+        Ok(symbol) => symbol,
+        Err(_) => "ALL".to_string(),
+    }
+}
+
+// Messages crypto-msg-parser can't parse are published as the exchange JSON,
+// keyed by the symbol extracted from the payload.
+fn encode_raw(exchange: &str, market_type: MarketType, json: &str) -> EncodedMessage {
+    let symbol = extract_raw_symbol(exchange, market_type, json);
+    EncodedMessage {
+        raw: true,
+        ..EncodedMessage::new(symbol, json.as_bytes().to_vec())
+    }
+}
+
+/// Parse a crawled message and encode it per symbol.
+///
+/// The exchange, market type and message type are taken from the message
+/// itself, so one writer can serve feeds of any market. With `normalized`,
+/// parsed messages are also encoded keyed by their normalized key, raw
+/// messages are not.
+pub fn parse_message(msg: &Message, normalized: bool) -> ParseResult<Vec<EncodedMessage>> {
+    let exchange = msg.exchange.as_str();
+    let market_type = msg.market_type;
+    let msg_type = msg.msg_type;
+    let received_at = msg.received_at as i64;
+
+    let mut data_vec = Vec::new();
+
+    match msg_type {
+        MessageType::BBO => {
+            let bbo_msg = parse_bbo(exchange, market_type, &msg.json, Some(received_at))
+                .map_err(|e| ParseError::ParseFailed(msg_type, e.to_string()))?;
+
+            let byte_data = encode_bbo(&bbo_msg)
+                .map_err(|e| ParseError::EncodeFailed(msg_type, e.to_string()))?;
+            let normalized = encode_normalized!(normalized, msg_type, bbo_msg, encode_bbo);
+            data_vec.push(
+                EncodedMessage::new(bbo_msg.symbol, byte_data)
+                    .with_timestamp(bbo_msg.timestamp)
+                    .with_pair(bbo_msg.pair)
+                    .with_normalized(normalized),
+            );
+        }
+        MessageType::Trade => {
+            let trade_msg = parse_trade(exchange, market_type, &msg.json)
+                .map_err(|e| ParseError::ParseFailed(msg_type, e.to_string()))?;
+
+            for trade in trade_msg {
+                let byte_data = encode_trade(&trade)
+                    .map_err(|e| ParseError::EncodeFailed(msg_type, e.to_string()))?;
+                let normalized = encode_normalized!(normalized, msg_type, trade, encode_trade);
+                data_vec.push(EncodedMessage {
+                    trade: Some(trade.clone()),
+                    ..EncodedMessage::new(trade.symbol, byte_data)
+                        .with_timestamp(trade.timestamp)
+                        .with_pair(trade.pair)
+                        .with_normalized(normalized)
+                });
+            }
+        }
+        MessageType::L2Event | MessageType::L2TopK | MessageType::L2Snapshot => {
+            let orderbook_msg = match msg_type {
+                MessageType::L2Event => {
+                    parse_l2(exchange, market_type, &msg.json, Some(received_at))
+                }
+                MessageType::L2TopK => {
+                    parse_l2_topk(exchange, market_type, &msg.json, Some(received_at))
+                }
+                _ => parse_l2_snapshot(exchange, market_type, &msg.json, Some(received_at)),
+            }
+            .map_err(|e| ParseError::ParseFailed(msg_type, e.to_string()))?;
+
+            for orderbook in orderbook_msg {
+                let byte_data = encode_orderbook(&orderbook)
+                    .map_err(|e| ParseError::EncodeFailed(msg_type, e.to_string()))?;
+                let normalized =
+                    encode_normalized!(normalized, msg_type, orderbook, encode_orderbook);
+                data_vec.push(EncodedMessage {
+                    symbol: orderbook.symbol,
+                    data: byte_data,
+                    seq_id: orderbook.seq_id,
+                    prev_seq_id: orderbook.prev_seq_id,
+                    timestamp: Some(orderbook.timestamp),
+                    pair: Some(orderbook.pair),
+                    raw: false,
+                    trade: None,
+                    normalized,
+                });
+            }
+        }
+        MessageType::Candlestick => {
+            let kline_msg = parse_candlestick(exchange, market_type, &msg.json, msg_type)
+                .map_err(|e| ParseError::ParseFailed(msg_type, e.to_string()))?;
+
+            let byte_data = encode_kline(&kline_msg)
+                .map_err(|e| ParseError::EncodeFailed(msg_type, e.to_string()))?;
+            let normalized = encode_normalized!(normalized, msg_type, kline_msg, encode_kline);
+            // the timestamp is the start of the bar, not when it was sent
+            data_vec.push(
+                EncodedMessage::new(kline_msg.symbol, byte_data)
+                    .with_pair(kline_msg.pair)
+                    .with_normalized(normalized),
+            );
+        }
+        MessageType::FundingRate => {
+            let funding_rate_msg =
+                parse_funding_rate(exchange, market_type, &msg.json, Some(received_at))
+                    .map_err(|e| ParseError::ParseFailed(msg_type, e.to_string()))?;
+
+            for mut funding_rate in funding_rate_msg {
+                if funding_rate.estimated_rate.is_none() {
+                    funding_rate.estimated_rate = Some(0.0);
+                }
+
+                let byte_data = encode_funding_rate(&funding_rate)
+                    .map_err(|e| ParseError::EncodeFailed(msg_type, e.to_string()))?;
+                let normalized =
+                    encode_normalized!(normalized, msg_type, funding_rate, encode_funding_rate);
+                data_vec.push(
+                    EncodedMessage::new(funding_rate.symbol, byte_data)
+                        .with_timestamp(funding_rate.timestamp)
+                        .with_pair(funding_rate.pair)
+                        .with_normalized(normalized),
+                );
+            }
+        }
+        MessageType::Ticker => {
+            let ticker_msg = parse_ticker(exchange, market_type, &msg.json, Some(received_at))
+                .map_err(|e| ParseError::ParseFailed(msg_type, e.to_string()))?;
+
+            // libstock has no ticker encoding, the parsed ticker is sent as JSON
+            for ticker in ticker_msg {
+                let byte_data = serde_json::to_vec(&ticker)
+                    .map_err(|e| ParseError::EncodeFailed(msg_type, e.to_string()))?;
+                let normalized =
+                    encode_normalized!(normalized, msg_type, ticker, serde_json::to_vec);
+                data_vec.push(
+                    EncodedMessage::new(ticker.symbol, byte_data)
+                        .with_timestamp(ticker.timestamp)
+                        .with_pair(ticker.pair)
+                        .with_normalized(normalized),
+                );
+            }
+        }
+        // crypto-msg-parser has no parser for these
+        MessageType::L3Event
+        | MessageType::L3Snapshot
+        | MessageType::OpenInterest
+        | MessageType::Other => {
+            data_vec.push(encode_raw(exchange, market_type, &msg.json));
+        }
+        _ => return Err(ParseError::Unsupported(msg_type)),
+    };
+
+    Ok(data_vec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wmjtyd_libstock::data::{
+        bbo::decode_bbo, orderbook::decode_orderbook, trade::decode_trade,
+    };
+
+    // Parse a captured trade message, and check that the encoded trade
+    // decodes back to the same symbol, market type and price.
+    fn check_trade(exchange: &str, market_type: MarketType, json: &str, symbol: &str, price: f64) {
+        let msg = Message::new(
+            exchange.to_string(),
+            market_type,
+            MessageType::Trade,
+            json.to_string(),
+        );
+        let encoded = parse_message(&msg, false).unwrap();
+        assert_eq!(encoded.len(), 1);
+        assert_eq!(encoded[0].symbol, symbol);
+        assert!(!encoded[0].raw);
+
+        let trade = decode_trade(&encoded[0].data).unwrap();
+        assert_eq!(trade.exchange, exchange);
+        assert_eq!(trade.market_type, market_type);
+        assert_eq!(trade.symbol, symbol);
+        assert!((trade.price - price).abs() < 1e-6);
+    }
+
+    #[test]
+    fn spot_trade() {
+        check_trade(
+            "binance",
+            MarketType::Spot,
+            r#"{"stream":"btcusdt@trade","data":{"e":"trade","E":1616176861895,"s":"BTCUSDT","t":732781517,"p":"58228.67000000","q":"0.00006100","b":5416205357,"a":5416205377,"T":1616176861895,"m":true,"M":true}}"#,
+            "BTCUSDT",
+            58228.67,
+        );
+    }
+
+    #[test]
+    fn linear_swap_trade() {
+        check_trade(
+            "binance",
+            MarketType::LinearSwap,
+            r#"{"stream":"btcusdt@aggTrade","data":{"e":"aggTrade","E":1616201883458,"a":389551486,"s":"BTCUSDT","p":"58665.00","q":"0.043","f":621622993,"l":621622993,"T":1616201883304,"m":true}}"#,
+            "BTCUSDT",
+            58665.0,
+        );
+    }
+
+    #[test]
+    fn inverse_swap_trade() {
+        check_trade(
+            "binance",
+            MarketType::InverseSwap,
+            r#"{"stream":"btcusd_perp@aggTrade","data":{"e":"aggTrade","E":1616201036113,"a":21021852,"s":"BTCUSD_PERP","p":"58882.9","q":"10","f":41129035,"l":41129035,"T":1616201035958,"m":true}}"#,
+            "BTCUSD_PERP",
+            58882.9,
+        );
+    }
+
+    #[test]
+    fn inverse_future_trade() {
+        check_trade(
+            "binance",
+            MarketType::InverseFuture,
+            r#"{"stream":"btcusd_210625@aggTrade","data":{"e":"aggTrade","E":1616201036113,"a":4287749,"s":"BTCUSD_210625","p":"62784.1","q":"1","f":7397421,"l":7397421,"T":1616201035958,"m":false}}"#,
+            "BTCUSD_210625",
+            62784.1,
+        );
+    }
+
+    #[test]
+    fn european_option_trade() {
+        check_trade(
+            "deribit",
+            MarketType::EuropeanOption,
+            r#"{"jsonrpc":"2.0","method":"subscription","params":{"channel":"trades.option.any.raw","data":[{"trade_seq":1,"trade_id":"ETH-49985135","timestamp":1616321732986,"tick_direction":0,"price":0.0505,"mark_price":0.05018826,"iv":71.6,"instrument_name":"ETH-26MAR21-1600-C","index_price":1765.42,"direction":"sell","amount":1.0}]}}"#,
+            "ETH-26MAR21-1600-C",
+            0.0505,
+        );
+    }
+
+    fn parse_one(market_type: MarketType, msg_type: MessageType, json: &str) -> EncodedMessage {
+        let msg = Message::new(
+            "binance".to_string(),
+            market_type,
+            msg_type,
+            json.to_string(),
+        );
+        let mut encoded = parse_message(&msg, false).unwrap();
+        assert_eq!(encoded.len(), 1);
+        let encoded = encoded.remove(0);
+        assert!(!encoded.raw);
+        encoded
+    }
+
+    // Parse a captured BBO message, and check the best bid and ask it
+    // decodes back to.
+    fn check_bbo(market_type: MarketType, json: &str, symbol: &str, bid: f64, ask: f64) {
+        let encoded = parse_one(market_type, MessageType::BBO, json);
+        assert_eq!(encoded.symbol, symbol);
+
+        let bbo = decode_bbo(&encoded.data).unwrap();
+        assert_eq!(bbo.market_type, market_type);
+        assert_eq!(bbo.symbol, symbol);
+        assert!((bbo.bid_price - bid).abs() < 1e-6);
+        assert!((bbo.ask_price - ask).abs() < 1e-6);
+    }
+
+    // Parse a captured order book message, and check its sequence numbers
+    // and the best bid it decodes back to.
+    fn check_orderbook(
+        market_type: MarketType,
+        msg_type: MessageType,
+        json: &str,
+        symbol: &str,
+        seq_ids: (u64, u64),
+        bid: f64,
+    ) {
+        let encoded = parse_one(market_type, msg_type, json);
+        assert_eq!(encoded.symbol, symbol);
+        assert_eq!(encoded.seq_id, Some(seq_ids.0));
+        assert_eq!(encoded.prev_seq_id, Some(seq_ids.1));
+
+        let orderbook = decode_orderbook(&encoded.data).unwrap();
+        assert_eq!(orderbook.market_type, market_type);
+        assert_eq!(orderbook.symbol, symbol);
+        assert!((orderbook.bids[0].price - bid).abs() < 1e-6);
+    }
+
+    #[test]
+    fn linear_swap_bbo() {
+        check_bbo(
+            MarketType::LinearSwap,
+            r#"{"stream":"btcusdt@bookTicker","data":{"e":"bookTicker","u":1575745563447,"s":"BTCUSDT","b":"46735.60","B":"0.059","a":"46735.70","A":"2.497","T":1649064010418,"E":1649064010424}}"#,
+            "BTCUSDT",
+            46735.6,
+            46735.7,
+        );
+    }
+
+    #[test]
+    fn inverse_future_bbo() {
+        check_bbo(
+            MarketType::InverseFuture,
+            r#"{"stream":"btcusd_220624@bookTicker","data":{"e":"bookTicker","u":424393052364,"s":"BTCUSD_220624","ps":"BTCUSD","b":"47425.6","B":"24","a":"47425.7","A":"60","T":1649064010362,"E":1649064010366}}"#,
+            "BTCUSD_220624",
+            47425.6,
+            47425.7,
+        );
+    }
+
+    #[test]
+    fn linear_swap_l2_event() {
+        check_orderbook(
+            MarketType::LinearSwap,
+            MessageType::L2Event,
+            r#"{"stream":"btcusdt@depth@100ms","data":{"e":"depthUpdate","E":1622370862564,"T":1622370862553,"s":"BTCUSDT","U":683580024227,"u":683580024229,"pu":683580024224,"b":[["35530.88","0.024"]],"a":[["35550.00","0.000"]]}}"#,
+            "BTCUSDT",
+            (683580024229, 683580024224),
+            35530.88,
+        );
+    }
+
+    #[test]
+    fn inverse_future_l2_event() {
+        check_orderbook(
+            MarketType::InverseFuture,
+            MessageType::L2Event,
+            r#"{"stream":"btcusd_210625@depth@100ms","data":{"e":"depthUpdate","E":1622370862564,"T":1622370862553,"s":"BTCUSD_210625","ps":"BTCUSD","U":127559587191,"u":127559587193,"pu":127559587178,"b":[["35434.1","40"]],"a":[["35434.2","1125"]]}}"#,
+            "BTCUSD_210625",
+            (127559587193, 127559587178),
+            35434.1,
+        );
+    }
+
+    #[test]
+    fn linear_swap_l2_topk() {
+        check_orderbook(
+            MarketType::LinearSwap,
+            MessageType::L2TopK,
+            r#"{"stream":"btcusdt@depth5","data":{"e":"depthUpdate","E":1648699220545,"T":1648699220536,"s":"BTCUSDT","U":1208402733372,"u":1208402734545,"pu":1208402733334,"b":[["47322.60","4.126"],["47322.50","0.030"],["47322.40","0.008"],["47322.30","0.001"],["47322.20","0.022"]],"a":[["47322.70","1.560"],["47322.80","0.003"],["47322.90","0.002"],["47323.00","0.318"],["47323.10","0.159"]]}}"#,
+            "BTCUSDT",
+            (1208402734545, 1208402733334),
+            47322.6,
+        );
+    }
+
+    #[test]
+    fn inverse_future_l2_topk() {
+        check_orderbook(
+            MarketType::InverseFuture,
+            MessageType::L2TopK,
+            r#"{"stream":"btcusd_220624@depth5","data":{"e":"depthUpdate","E":1648699220545,"T":1648699220536,"s":"BTCUSD_220624","ps":"BTCUSD","U":424189421617,"u":424189422155,"pu":424189421558,"b":[["47686.1","186"],["47686.0","8"],["47685.9","1"],["47685.5","1"],["47685.2","40"]],"a":[["47686.2","1"],["47686.8","5"],["47686.9","1"],["47687.6","1"],["47687.7","100"]]}}"#,
+            "BTCUSD_220624",
+            (424189422155, 424189421558),
+            47686.1,
+        );
+    }
+
+    #[test]
+    fn normalized_copy_is_keyed_by_the_normalized_key() {
+        let msg = Message::new(
+            "binance".to_string(),
+            MarketType::InverseFuture,
+            MessageType::Trade,
+            r#"{"stream":"btcusd_210625@aggTrade","data":{"e":"aggTrade","E":1616201036113,"a":4287749,"s":"BTCUSD_210625","p":"62784.1","q":"1","f":7397421,"l":7397421,"T":1616201035958,"m":false}}"#.to_string(),
+        );
+        assert!(parse_message(&msg, false).unwrap()[0].normalized.is_none());
+
+        let encoded = parse_message(&msg, true).unwrap();
+        let (key, data) = encoded[0].normalized.as_ref().unwrap();
+        assert_eq!(key, "BTC/USD-210625");
+        let trade = decode_trade(data).unwrap();
+        assert_eq!(trade.symbol, "BTC/USD-210625");
+        assert!((trade.price - 62784.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn unparsed_message_is_raw() {
+        let json = r#"{"stream":"!forceOrder@arr","data":{"e":"forceOrder","E":1616201036113,"o":{"s":"BTCUSDT","S":"SELL","o":"LIMIT","f":"IOC","q":"0.014","p":"58000.00","ap":"58100.00","X":"FILLED","l":"0.014","z":"0.014","T":1616201036110}}}"#;
+        let msg = Message::new(
+            "binance".to_string(),
+            MarketType::LinearSwap,
+            MessageType::Other,
+            json.to_string(),
+        );
+        let encoded = parse_message(&msg, false).unwrap();
+        assert_eq!(encoded.len(), 1);
+        assert!(encoded[0].raw);
+        assert_eq!(encoded[0].data, json.as_bytes());
+    }
+}
@@ -0,0 +1,267 @@
+use super::{extract_raw_symbol, topic_of};
+use crate::metrics::{record_conflated, record_dropped};
+
+use crypto_crawler::{Message, MessageType};
+use log::*;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        mpsc::{Receiver, RecvTimeoutError, Sender},
+        Arc, Condvar, Mutex,
+    },
+    time::Duration,
+};
+use tokio::sync::watch;
+
+// How often the pump of an idle channel checks for a stop
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How a message type behaves when the queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueuePolicy {
+    /// Every message is kept, the producer waits for room
+    Lossless,
+    /// Only the latest pending message of a symbol is kept
+    Conflate,
+}
+
+impl QueuePolicy {
+    pub fn of(msg_type: MessageType) -> Self {
+        match msg_type {
+            MessageType::BBO | MessageType::Ticker | MessageType::L2TopK => QueuePolicy::Conflate,
+            _ => QueuePolicy::Lossless,
+        }
+    }
+}
+
+enum Slot {
+    Message(Arc<Message>),
+    // The latest pending message of a symbol, kept in `State::latest`
+    Latest(String),
+}
+
+#[derive(Default)]
+struct State {
+    slots: VecDeque<Slot>,
+    latest: HashMap<String, Arc<Message>>,
+    closed: bool,
+}
+
+/// A bounded queue between the crawler and the writer thread.
+///
+/// Lossless messages, trades and L2 events included, wait for room, so a
+/// writer falling behind holds up the pump and the crawler channel backs up.
+/// A conflated message, BBO, ticker or top-K, replaces the pending message of
+/// its symbol, or is dropped if the queue is full and none is pending.
+/// Messages whose symbol can't be extracted are never conflated.
+#[derive(Clone)]
+pub struct MessageQueue {
+    state: Arc<(Mutex<State>, Condvar)>,
+    capacity: usize,
+    period: Arc<String>,
+}
+
+impl MessageQueue {
+    pub fn new(capacity: usize, period: Arc<String>) -> Self {
+        MessageQueue {
+            state: Arc::new((Mutex::new(State::default()), Condvar::new())),
+            capacity,
+            period,
+        }
+    }
+
+    pub fn push(&self, msg: Arc<Message>) {
+        let (lock, cvar) = &*self.state;
+        let mut state = lock.lock().unwrap();
+
+        let symbol = match QueuePolicy::of(msg.msg_type) {
+            QueuePolicy::Conflate => Some(extract_raw_symbol(
+                &msg.exchange,
+                msg.market_type,
+                &msg.json,
+            ))
+            .filter(|symbol| symbol != "ALL"),
+            QueuePolicy::Lossless => None,
+        };
+
+        match symbol {
+            Some(symbol) => {
+                let topic = topic_of(&msg, &symbol, &self.period).to_string();
+                if let Some(pending) = state.latest.get_mut(&symbol) {
+                    *pending = msg;
+                    record_conflated(&topic);
+                } else if state.slots.len() >= self.capacity {
+                    record_dropped(&topic);
+                } else {
+                    state.latest.insert(symbol.clone(), msg);
+                    state.slots.push_back(Slot::Latest(symbol));
+                }
+            }
+            None => {
+                while state.slots.len() >= self.capacity && !state.closed {
+                    state = cvar.wait(state).unwrap();
+                }
+                state.slots.push_back(Slot::Message(msg));
+            }
+        }
+        cvar.notify_all();
+    }
+
+    /// The next message, `None` once the queue is closed and drained.
+    pub fn pop(&self) -> Option<Arc<Message>> {
+        let (lock, cvar) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        loop {
+            if let Some(slot) = state.slots.pop_front() {
+                cvar.notify_all();
+                return match slot {
+                    Slot::Message(msg) => Some(msg),
+                    Slot::Latest(symbol) => state.latest.remove(&symbol),
+                };
+            }
+            if state.closed {
+                return None;
+            }
+            state = cvar.wait(state).unwrap();
+        }
+    }
+
+    pub fn close(&self) {
+        let (lock, cvar) = &*self.state;
+        lock.lock().unwrap().closed = true;
+        cvar.notify_all();
+    }
+}
+
+/// Move the messages of a crawler channel into a bounded queue, the queue is
+/// closed when the crawlers exit or `stop` turns `true`.
+///
+/// Every message is sent to `tx_archives` first, so the archive and capture
+/// keep what the queue conflates. crypto-crawler only takes an unbounded
+/// `Sender`, so while the queue is full of lossless messages the pump waits
+/// and they back up in the channel.
+///
+/// On `stop` the messages already in the channel are queued and the channel
+/// is dropped, so crawlers that can't be aborted, such as the REST crawlers
+/// on blocking threads, fail their next send instead of holding the queue
+/// open.
+pub(super) fn bounded(
+    rx: Receiver<Message>,
+    capacity: usize,
+    period: Arc<String>,
+    stop: watch::Receiver<bool>,
+    tx_archives: Vec<Sender<Arc<Message>>>,
+) -> MessageQueue {
+    let queue = MessageQueue::new(capacity, period);
+    let producer = queue.clone();
+    std::thread::spawn(move || {
+        let forward = |msg: Message| {
+            let msg = Arc::new(msg);
+            for tx_archive in tx_archives.iter() {
+                if tx_archive.send(msg.clone()).is_err() {
+                    error!("The archive thread has exited");
+                }
+            }
+            producer.push(msg);
+        };
+        while !*stop.borrow() {
+            match rx.recv_timeout(STOP_POLL_INTERVAL) {
+                Ok(msg) => forward(msg),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        for msg in rx.try_iter() {
+            forward(msg);
+        }
+        drop(rx);
+        producer.close();
+    });
+    queue
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto_crawler::MarketType;
+
+    fn message(msg_type: MessageType, json: &str) -> Message {
+        Message::new(
+            "binance".to_string(),
+            MarketType::Spot,
+            msg_type,
+            json.to_string(),
+        )
+    }
+
+    fn drain(queue: &MessageQueue) -> Vec<String> {
+        std::iter::from_fn(|| queue.pop())
+            .map(|msg| msg.json.clone())
+            .collect()
+    }
+
+    #[test]
+    fn waits_for_room_once_full() {
+        let queue = MessageQueue::new(2, Arc::new(String::new()));
+        for json in ["1", "2"] {
+            queue.push(Arc::new(message(MessageType::Trade, json)));
+        }
+        let producer = queue.clone();
+        let pushed = std::thread::spawn(move || {
+            producer.push(Arc::new(message(MessageType::Trade, "3")));
+            producer.close();
+        });
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!pushed.is_finished());
+
+        // nothing is dropped, the third trade is pushed once there is room
+        assert_eq!(drain(&queue), vec!["1", "2", "3"]);
+        pushed.join().unwrap();
+    }
+
+    #[test]
+    fn tees_raw_messages_before_queueing() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let (tx_archive, rx_archive) = std::sync::mpsc::channel();
+        let (_stop_tx, stop) = watch::channel(false);
+        let queue = bounded(rx, 10, Arc::new(String::new()), stop, vec![tx_archive]);
+        for json in ["1", "2"] {
+            tx.send(message(MessageType::Trade, json)).unwrap();
+        }
+        drop(tx);
+
+        assert_eq!(drain(&queue), vec!["1", "2"]);
+        let archived: Vec<String> = rx_archive.iter().map(|msg| msg.json.clone()).collect();
+        assert_eq!(archived, vec!["1", "2"]);
+    }
+
+    #[test]
+    fn drains_the_channel_into_the_queue() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let (_stop_tx, stop) = watch::channel(false);
+        let queue = bounded(rx, 10, Arc::new(String::new()), stop, Vec::new());
+        for json in ["1", "2"] {
+            tx.send(message(MessageType::Trade, json)).unwrap();
+        }
+        drop(tx);
+
+        // closed by the pump thread once the crawler channel is
+        assert_eq!(drain(&queue), vec!["1", "2"]);
+    }
+
+    #[test]
+    fn stops_while_a_crawler_still_holds_the_channel() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let (stop_tx, stop) = watch::channel(false);
+        let queue = bounded(rx, 10, Arc::new(String::new()), stop, Vec::new());
+        for json in ["1", "2"] {
+            tx.send(message(MessageType::L2Snapshot, json)).unwrap();
+        }
+        stop_tx.send(true).unwrap();
+
+        // sent before the stop, so still written
+        assert_eq!(drain(&queue), vec!["1", "2"]);
+        // the queue is closed once the channel is dropped
+        assert!(tx.send(message(MessageType::L2Snapshot, "3")).is_err());
+    }
+}
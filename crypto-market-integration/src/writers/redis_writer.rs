@@ -0,0 +1,383 @@
+use super::sink::SinkMessage;
+
+use crypto_crawler::MessageType;
+use crypto_market_topic::{Topic, TopicType};
+use log::*;
+use redis::{ErrorKind, RedisError};
+use std::{
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc::Receiver;
+
+const DEFAULT_STREAM_MAXLEN: usize = 10000;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+fn connect_redis(redis_url: &str) -> Result<redis::Connection, RedisError> {
+    if redis_url.is_empty() {
+        return Err(RedisError::from((
+            ErrorKind::InvalidClientConfig,
+            "redis_url is empty",
+        )));
+    }
+    redis::Client::open(redis_url).and_then(|client| client.get_connection())
+}
+
+/// A Redis connection that is reopened with exponential backoff, messages
+/// are dropped while it is down so the other sinks keep going.
+struct RedisConnection {
+    redis_url: String,
+    conn: Option<redis::Connection>,
+    backoff: Duration,
+    retry_at: Instant,
+    dropped: u64,
+}
+
+impl RedisConnection {
+    fn new(redis_url: String) -> Self {
+        RedisConnection {
+            redis_url,
+            conn: None,
+            backoff: MIN_BACKOFF,
+            retry_at: Instant::now(),
+            dropped: 0,
+        }
+    }
+
+    fn get(&mut self) -> Option<&mut redis::Connection> {
+        if self.conn.is_none() && Instant::now() >= self.retry_at {
+            match connect_redis(&self.redis_url) {
+                Ok(conn) => {
+                    if self.dropped > 0 {
+                        warn!(
+                            "Reconnected to {}, {} messages were dropped",
+                            self.redis_url, self.dropped
+                        );
+                    }
+                    self.conn = Some(conn);
+                    self.backoff = MIN_BACKOFF;
+                    self.dropped = 0;
+                }
+                Err(err) => {
+                    error!(
+                        "Failed to connect to {}: {}, retrying in {:?}",
+                        self.redis_url, err, self.backoff
+                    );
+                    self.retry_at = Instant::now() + self.backoff;
+                    self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+        if self.conn.is_none() {
+            self.dropped += 1;
+        }
+        self.conn.as_mut()
+    }
+
+    // The connection may be broken, it is reopened on the next message
+    fn reset(&mut self) {
+        self.conn = None;
+    }
+}
+
+/// Where a topic is stored besides its channel, by the format of its payload.
+#[derive(Debug, PartialEq, Eq)]
+enum Route {
+    /// libstock encoded messages, appended to `stream:topic`
+    Encoded(String),
+    /// JSON events and raw messages, appended to `json:topic`
+    Json(String),
+    /// Reference data, the latest of every symbol in the `instrument:exchange_market_type` hash
+    Instrument(String),
+}
+
+fn route(topic: &Topic) -> Route {
+    let name = topic.to_string();
+    match topic.msg_type {
+        TopicType::Msg(MessageType::Ticker)
+        | TopicType::Raw(_)
+        | TopicType::L2Gap
+        | TopicType::DeadLetter
+        | TopicType::Control => Route::Json(format!("json:{}", name)),
+        TopicType::Instrument => Route::Instrument(format!(
+            "instrument:{}_{}",
+            topic.exchange, topic.market_type
+        )),
+        TopicType::Msg(_) | TopicType::L2Book | TopicType::L2BookTopK => {
+            Route::Encoded(format!("stream:{}", name))
+        }
+    }
+}
+
+// REDIS_STREAM_MSG_TYPES selects which message types are appended to streams,
+// e.g. `trade,bbo`. All topics are streamed if it is not set.
+fn stream_msg_types() -> Option<Vec<MessageType>> {
+    let msg_types = std::env::var("REDIS_STREAM_MSG_TYPES").ok()?;
+    Some(
+        msg_types
+            .split(',')
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .filter_map(|x| match MessageType::from_str(x) {
+                Ok(msg_type) => Some(msg_type),
+                Err(_) => {
+                    warn!("Unknown msg type in REDIS_STREAM_MSG_TYPES: {}", x);
+                    None
+                }
+            })
+            .collect(),
+    )
+}
+
+fn stream_maxlen() -> usize {
+    std::env::var("REDIS_STREAM_MAXLEN")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(DEFAULT_STREAM_MAXLEN)
+}
+
+/// Publish every message on the `topic` channel, and append encoded messages
+/// to the capped `stream:topic` stream and JSON ones to `json:topic`.
+/// Instruments are kept by symbol in the `instrument:exchange_market_type`
+/// hash instead.
+pub(super) async fn create_redis_writer_thread(
+    mut rx: Receiver<Arc<SinkMessage>>,
+    redis_url: String,
+) {
+    tokio::task::spawn_blocking(move || {
+        let stream_msg_types = stream_msg_types();
+        let maxlen = stream_maxlen();
+
+        let mut redis_conn = RedisConnection::new(redis_url);
+
+        while let Some(entry) = rx.blocking_recv() {
+            let stream = match (&stream_msg_types, entry.topic.msg_type) {
+                (Some(msg_types), TopicType::Msg(msg_type)) => msg_types.contains(&msg_type),
+                (Some(_), _) => false,
+                (None, _) => true,
+            };
+            let topic = entry.topic.to_string();
+
+            let mut pipe = redis::pipe();
+            pipe.cmd("PUBLISH").arg(&topic).arg(&entry.data).ignore();
+            match route(&entry.topic) {
+                Route::Encoded(key) | Route::Json(key) if stream => {
+                    pipe.cmd("XADD")
+                        .arg(key)
+                        .arg("MAXLEN")
+                        .arg("~")
+                        .arg(maxlen)
+                        .arg("*")
+                        .arg("data")
+                        .arg(&entry.data)
+                        .ignore();
+                }
+                Route::Encoded(_) | Route::Json(_) => {}
+                Route::Instrument(key) => {
+                    match serde_json::from_slice::<serde_json::Value>(&entry.data) {
+                        Ok(instrument) => match instrument["symbol"].as_str() {
+                            Some(symbol) => {
+                                pipe.cmd("HSET")
+                                    .arg(key)
+                                    .arg(symbol)
+                                    .arg(&entry.data)
+                                    .ignore();
+                            }
+                            None => warn!("{}, an instrument without a symbol", topic),
+                        },
+                        Err(err) => warn!("{}, {}", topic, err),
+                    }
+                }
+            }
+
+            if let Some(conn) = redis_conn.get() {
+                if let Err(err) = pipe.query::<()>(conn) {
+                    error!("{}, {}", topic, err);
+                    redis_conn.reset();
+                }
+            }
+        }
+    })
+    .await
+    .expect("create_redis_writer_thread failed");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto_crawler::MarketType;
+    use std::process::{Child, Command, Stdio};
+    use tokio::sync::mpsc::{channel, Sender};
+
+    // A throwaway redis-server without persistence, `None` if it is not installed
+    struct RedisServer(Child);
+
+    impl RedisServer {
+        fn start(port: u16) -> Option<Self> {
+            let child = Command::new("redis-server")
+                .args([
+                    "--port",
+                    &port.to_string(),
+                    "--save",
+                    "",
+                    "--appendonly",
+                    "no",
+                ])
+                .stdout(Stdio::null())
+                .spawn()
+                .ok()?;
+            let server = RedisServer(child);
+            for _ in 0..50 {
+                if connect_redis(&redis_url(port)).is_ok() {
+                    return Some(server);
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            None
+        }
+    }
+
+    impl Drop for RedisServer {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    fn redis_url(port: u16) -> String {
+        format!("redis://127.0.0.1:{}", port)
+    }
+
+    fn topic() -> Topic {
+        Topic::new("binance", MarketType::Spot, MessageType::Trade, "BTCUSDT")
+    }
+
+    fn send(tx: &Sender<Arc<SinkMessage>>, data: &[u8]) {
+        let msg = SinkMessage {
+            topic: topic(),
+            data: data.to_vec(),
+        };
+        tx.try_send(Arc::new(msg)).unwrap();
+    }
+
+    // The payloads of the stream of `topic()`
+    fn stream(port: u16) -> Vec<Vec<u8>> {
+        let mut conn = connect_redis(&redis_url(port)).unwrap();
+        let entries: Vec<(String, Vec<Vec<u8>>)> = redis::cmd("XRANGE")
+            .arg(format!("stream:{}", topic()))
+            .arg("-")
+            .arg("+")
+            .query(&mut conn)
+            .unwrap();
+        entries
+            .into_iter()
+            .map(|(_, fields)| fields[1].clone())
+            .collect()
+    }
+
+    #[test]
+    fn routes_by_topic_type() {
+        assert_eq!(
+            route(&topic()),
+            Route::Encoded("stream:binance_spot_trade_BTCUSDT".to_string())
+        );
+        let ticker = Topic::new("binance", MarketType::Spot, MessageType::Ticker, "BTCUSDT");
+        assert_eq!(
+            route(&ticker),
+            Route::Json("json:binance_spot_ticker_BTCUSDT".to_string())
+        );
+        let dead_letter = Topic::new("binance", MarketType::Spot, TopicType::DeadLetter, "trade");
+        assert_eq!(
+            route(&dead_letter),
+            Route::Json("json:binance_spot_dead_letter_trade".to_string())
+        );
+        let instrument = Topic::new("binance", MarketType::Spot, TopicType::Instrument, "ALL");
+        assert_eq!(
+            route(&instrument),
+            Route::Instrument("instrument:binance_spot".to_string())
+        );
+    }
+
+    #[test]
+    fn empty_url_is_an_error() {
+        assert!(connect_redis("").is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn publishes_to_streams() {
+        let port = 16379;
+        let _server = match RedisServer::start(port) {
+            Some(server) => server,
+            None => return eprintln!("redis-server is not installed, skipped"),
+        };
+
+        let (tx, rx) = channel(16);
+        send(&tx, b"first");
+        send(&tx, b"second");
+        drop(tx);
+        create_redis_writer_thread(rx, redis_url(port)).await;
+
+        assert_eq!(stream(port), vec![b"first".to_vec(), b"second".to_vec()]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reconnects_after_a_restart() {
+        let port = 16380;
+        let server = match RedisServer::start(port) {
+            Some(server) => server,
+            None => return eprintln!("redis-server is not installed, skipped"),
+        };
+
+        let (tx, rx) = channel(16);
+        let writer = tokio::task::spawn(create_redis_writer_thread(rx, redis_url(port)));
+        send(&tx, b"before");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        drop(server);
+        // fails and drops the connection
+        send(&tx, b"lost");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let _server = RedisServer::start(port).unwrap();
+        send(&tx, b"after");
+        drop(tx);
+        writer.await.unwrap();
+
+        assert_eq!(stream(port), vec![b"after".to_vec()]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stores_instruments_by_symbol() {
+        let port = 16381;
+        let _server = match RedisServer::start(port) {
+            Some(server) => server,
+            None => return eprintln!("redis-server is not installed, skipped"),
+        };
+
+        let (tx, rx) = channel(16);
+        let instrument = br#"{"exchange":"binance","market_type":"spot","symbol":"BTCUSDT"}"#;
+        let msg = SinkMessage {
+            topic: Topic::new("binance", MarketType::Spot, TopicType::Instrument, "ALL"),
+            data: instrument.to_vec(),
+        };
+        tx.try_send(Arc::new(msg)).unwrap();
+        drop(tx);
+        create_redis_writer_thread(rx, redis_url(port)).await;
+
+        let mut conn = connect_redis(&redis_url(port)).unwrap();
+        let stored: Vec<u8> = redis::cmd("HGET")
+            .arg("instrument:binance_spot")
+            .arg("BTCUSDT")
+            .query(&mut conn)
+            .unwrap();
+        assert_eq!(stored, instrument.to_vec());
+        let streams: Vec<String> = redis::cmd("KEYS")
+            .arg("*stream:*")
+            .query(&mut conn)
+            .unwrap();
+        assert!(streams.is_empty());
+    }
+}
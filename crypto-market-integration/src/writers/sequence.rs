@@ -0,0 +1,165 @@
+use crypto_crawler::{MarketType, Message, MessageType};
+use std::collections::HashMap;
+
+/// A dropped or out-of-order order book update.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Gap {
+    pub last_seq_id: u64,
+    pub seq_id: u64,
+    pub prev_seq_id: Option<u64>,
+}
+
+/// Where an update falls in the sequence of its symbol.
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Sequence {
+    /// The next update, or the first one or an update without a sequence number
+    InOrder,
+    /// Not newer than the last update, to be dropped
+    Stale(Gap),
+    /// Updates are missing before this one
+    Gap(Gap),
+}
+
+/// Tracks the last sequence number of every symbol of an L2 feed.
+pub(super) struct SequenceTracker {
+    last_seq_ids: HashMap<String, u64>,
+}
+
+impl SequenceTracker {
+    pub fn new() -> Self {
+        SequenceTracker {
+            last_seq_ids: HashMap::new(),
+        }
+    }
+
+    /// Check an update against the previous one of its symbol.
+    ///
+    /// When the exchange provides `prev_seq_id` it must match the last
+    /// `seq_id`, otherwise updates only have to be increasing.
+    pub fn check(
+        &mut self,
+        symbol: &str,
+        seq_id: Option<u64>,
+        prev_seq_id: Option<u64>,
+    ) -> Sequence {
+        let seq_id = match seq_id {
+            Some(seq_id) => seq_id,
+            None => return Sequence::InOrder,
+        };
+        let last_seq_id = match self.last_seq_ids.get(symbol) {
+            Some(last_seq_id) => *last_seq_id,
+            None => {
+                self.last_seq_ids.insert(symbol.to_string(), seq_id);
+                return Sequence::InOrder;
+            }
+        };
+
+        let gap = Gap {
+            last_seq_id,
+            seq_id,
+            prev_seq_id,
+        };
+        if seq_id <= last_seq_id {
+            // keep the newest sequence number
+            return Sequence::Stale(gap);
+        }
+        self.last_seq_ids.insert(symbol.to_string(), seq_id);
+
+        match prev_seq_id {
+            Some(prev_seq_id) if prev_seq_id != last_seq_id => Sequence::Gap(gap),
+            _ => Sequence::InOrder,
+        }
+    }
+}
+
+/// Fetch one REST snapshot of `symbol`, as `crawl_l2_snapshot` would send it.
+pub(super) fn fetch_l2_snapshot(
+    exchange: &str,
+    market_type: MarketType,
+    symbol: &str,
+) -> Result<Message, String> {
+    crypto_rest_client::fetch_l2_snapshot(exchange, market_type, symbol, Some(3))
+        .map(|json| {
+            Message::new(
+                exchange.to_string(),
+                market_type,
+                MessageType::L2Snapshot,
+                json,
+            )
+        })
+        .map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn updates_in_order() {
+        let mut tracker = SequenceTracker::new();
+        assert!(matches!(
+            tracker.check("BTCUSDT", Some(10), None),
+            Sequence::InOrder
+        ));
+        assert!(matches!(
+            tracker.check("BTCUSDT", Some(11), Some(10)),
+            Sequence::InOrder
+        ));
+        // without prev_seq_id updates only have to be increasing
+        assert!(matches!(
+            tracker.check("BTCUSDT", Some(15), None),
+            Sequence::InOrder
+        ));
+        assert!(matches!(
+            tracker.check("BTCUSDT", None, None),
+            Sequence::InOrder
+        ));
+        // symbols are tracked separately
+        assert!(matches!(
+            tracker.check("ETHUSDT", Some(1), None),
+            Sequence::InOrder
+        ));
+    }
+
+    #[test]
+    fn stale_updates() {
+        let mut tracker = SequenceTracker::new();
+        tracker.check("BTCUSDT", Some(10), None);
+        assert!(matches!(
+            tracker.check("BTCUSDT", Some(10), Some(9)),
+            Sequence::Stale(Gap {
+                last_seq_id: 10,
+                seq_id: 10,
+                ..
+            })
+        ));
+        assert!(matches!(
+            tracker.check("BTCUSDT", Some(8), None),
+            Sequence::Stale(_)
+        ));
+        // the newest sequence number is kept
+        assert!(matches!(
+            tracker.check("BTCUSDT", Some(11), Some(10)),
+            Sequence::InOrder
+        ));
+    }
+
+    #[test]
+    fn gaps() {
+        let mut tracker = SequenceTracker::new();
+        tracker.check("BTCUSDT", Some(10), None);
+        assert!(matches!(
+            tracker.check("BTCUSDT", Some(13), Some(12)),
+            Sequence::Gap(Gap {
+                last_seq_id: 10,
+                seq_id: 13,
+                prev_seq_id: Some(12)
+            })
+        ));
+        // tracking resumes after the gap
+        assert!(matches!(
+            tracker.check("BTCUSDT", Some(14), Some(13)),
+            Sequence::InOrder
+        ));
+    }
+}
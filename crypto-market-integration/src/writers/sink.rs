@@ -0,0 +1,368 @@
+use super::{open_publisher, publish, redis_writer::create_redis_writer_thread};
+use crate::metrics::record_dropped;
+
+use crypto_market_file::FileWriter;
+use crypto_market_topic::Topic;
+use futures::{future::BoxFuture, FutureExt};
+use log::*;
+use std::{
+    collections::HashMap,
+    fmt,
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc::{channel, error::TrySendError, Receiver, Sender};
+
+// Files of the file sink are flushed this often, even without new messages
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// An encoded message on its way to the sinks.
+pub struct SinkMessage {
+    pub topic: Topic,
+    pub data: Vec<u8>,
+}
+
+/// An output of the parsed messages.
+///
+/// `send` must not block the writer thread, a sink buffers messages and
+/// handles its errors on its own, usually in a thread of its own fed by a
+/// bounded channel. Messages that find the channel full are dropped.
+pub trait Sink: Send + Sync {
+    fn send(&self, msg: Arc<SinkMessage>);
+
+    /// Prepare the output of a topic ahead of its first message, for topics
+    /// with rare messages that would be lost to a ZeroMQ slow joiner.
+    fn open(&self, _topic: &Topic) {}
+}
+
+/// The kinds of sinks, selected by name in the config or on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SinkKind {
+    /// Publish on the ZeroMQ socket of the topic
+    Zeromq,
+    /// Publish on the Redis channel of the topic and append to its stream
+    Redis,
+    /// Append length-prefixed payloads to one file per topic
+    File,
+    /// Print the topic and payload of every message, for debugging
+    Stdout,
+}
+
+impl fmt::Display for SinkKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SinkKind::Zeromq => write!(f, "zeromq"),
+            SinkKind::Redis => write!(f, "redis"),
+            SinkKind::File => write!(f, "file"),
+            SinkKind::Stdout => write!(f, "stdout"),
+        }
+    }
+}
+
+impl FromStr for SinkKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.trim() {
+            "zeromq" => Ok(SinkKind::Zeromq),
+            "redis" => Ok(SinkKind::Redis),
+            "file" => Ok(SinkKind::File),
+            "stdout" => Ok(SinkKind::Stdout),
+            _ => Err(format!("unknown sink: {}", s)),
+        }
+    }
+}
+
+/// Every sink of a writer pipeline, a message sent to it fans out to all.
+#[derive(Clone, Default)]
+pub struct Sinks {
+    sinks: Vec<Arc<dyn Sink>>,
+}
+
+impl Sinks {
+    pub fn push<S: Sink + 'static>(&mut self, sink: S) {
+        self.sinks.push(Arc::new(sink));
+    }
+
+    pub fn send(&self, topic: Topic, data: Vec<u8>) {
+        let msg = Arc::new(SinkMessage { topic, data });
+        for sink in self.sinks.iter() {
+            sink.send(msg.clone());
+        }
+    }
+
+    /// Open `topic` on every sink ahead of its first message, so that
+    /// subscribers have joined by the time a rare event, such as a reconnect
+    /// or a listing, is sent.
+    pub fn open(&self, topic: &Topic) {
+        for sink in self.sinks.iter() {
+            sink.open(topic);
+        }
+    }
+}
+
+struct ChannelSink {
+    name: SinkKind,
+    tx: Sender<Arc<SinkMessage>>,
+}
+
+impl Sink for ChannelSink {
+    fn send(&self, msg: Arc<SinkMessage>) {
+        match self.tx.try_send(msg) {
+            Ok(()) => {}
+            Err(TrySendError::Full(msg)) => {
+                debug!("The {} sink is full, {} dropped", self.name, msg.topic);
+                record_dropped(&msg.topic.to_string());
+            }
+            Err(TrySendError::Closed(_)) => error!("The {} sink has exited", self.name),
+        }
+    }
+}
+
+// What the thread of the ZeroMQ sink is asked to do
+enum ZeromqCommand {
+    Open(Topic),
+    Send(Arc<SinkMessage>),
+}
+
+/// Publishes on the ZeroMQ socket of every topic, opened on first use or by
+/// `open`.
+struct ZeromqSink {
+    tx: Sender<ZeromqCommand>,
+}
+
+impl Sink for ZeromqSink {
+    fn send(&self, msg: Arc<SinkMessage>) {
+        match self.tx.try_send(ZeromqCommand::Send(msg)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(ZeromqCommand::Send(msg))) => {
+                debug!("The zeromq sink is full, {} dropped", msg.topic);
+                record_dropped(&msg.topic.to_string());
+            }
+            Err(TrySendError::Full(_)) => {}
+            Err(TrySendError::Closed(_)) => error!("The zeromq sink has exited"),
+        }
+    }
+
+    fn open(&self, topic: &Topic) {
+        if let Err(err) = self.tx.try_send(ZeromqCommand::Open(topic.clone())) {
+            warn!("Failed to open the publisher of {} ahead: {}", topic, err);
+        }
+    }
+}
+
+async fn create_zeromq_sink_thread(mut rx: Receiver<ZeromqCommand>) {
+    let mut writers = HashMap::new();
+    while let Some(command) = rx.recv().await {
+        match command {
+            ZeromqCommand::Open(topic) => open_publisher(&mut writers, &topic).await,
+            ZeromqCommand::Send(msg) => publish(&mut writers, &msg.topic, &msg.data).await,
+        }
+    }
+}
+
+// Payloads are written as a little endian u32 length followed by the bytes.
+// Files are flushed every `FLUSH_INTERVAL` and reopened on SIGHUP, so they can
+// be rotated by logrotate.
+async fn create_file_sink_thread(mut rx: Receiver<Arc<SinkMessage>>, sink_dir: String) {
+    let runtime = tokio::runtime::Handle::current();
+    tokio::task::spawn_blocking(move || {
+        // `None` for files that failed to open, their messages are dropped
+        let mut files: HashMap<Topic, Option<FileWriter>> = HashMap::new();
+        let mut flushed_at = Instant::now();
+
+        loop {
+            match runtime.block_on(tokio::time::timeout(FLUSH_INTERVAL, rx.recv())) {
+                Ok(Some(msg)) => {
+                    let file = files.entry(msg.topic.clone()).or_insert_with_key(|topic| {
+                        let path = Path::new(&sink_dir).join(format!("{}.bin", topic.file_name()));
+                        match FileWriter::new(&path.to_string_lossy()) {
+                            Ok(file) => Some(file),
+                            Err(err) => {
+                                error!("failed to open {}: {}", path.display(), err);
+                                None
+                            }
+                        }
+                    });
+                    if let Some(file) = file {
+                        file.write_bytes(&(msg.data.len() as u32).to_le_bytes());
+                        file.write_bytes(&msg.data);
+                    }
+                }
+                Ok(None) => break,
+                // no message for a while, the files are flushed below
+                Err(_) => {}
+            }
+
+            if flushed_at.elapsed() >= FLUSH_INTERVAL {
+                for file in files.values_mut().flatten() {
+                    file.flush();
+                }
+                flushed_at = Instant::now();
+            }
+        }
+
+        for file in files.values_mut().flatten() {
+            file.close();
+        }
+    })
+    .await
+    .expect("create_file_sink_thread failed");
+}
+
+struct StdoutSink;
+
+impl Sink for StdoutSink {
+    fn send(&self, msg: Arc<SinkMessage>) {
+        // raw messages are JSON, encoded ones are printed as hex
+        match std::str::from_utf8(&msg.data) {
+            Ok(text) => println!("{} {}", msg.topic, text),
+            Err(_) => {
+                let hex: String = msg.data.iter().map(|b| format!("{:02x}", b)).collect();
+                println!("{} {}", msg.topic, hex);
+            }
+        }
+    }
+}
+
+/// Create the sinks of a writer pipeline, and the threads behind them, each
+/// buffering up to `capacity` messages.
+pub fn create_sinks(
+    kinds: &[SinkKind],
+    redis_url: Option<String>,
+    sink_dir: Option<String>,
+    capacity: usize,
+) -> Result<(Sinks, Vec<BoxFuture<'static, ()>>), String> {
+    let mut sinks = Sinks::default();
+    let mut threads = Vec::new();
+
+    for kind in kinds {
+        let (tx, rx) = channel::<Arc<SinkMessage>>(capacity);
+        let thread = match (kind, &redis_url, &sink_dir) {
+            (SinkKind::Zeromq, _, _) => {
+                let (tx, rx) = channel::<ZeromqCommand>(capacity);
+                threads.push(create_zeromq_sink_thread(rx).boxed());
+                sinks.push(ZeromqSink { tx });
+                continue;
+            }
+            (SinkKind::Redis, Some(redis_url), _) if redis_url.is_empty() => {
+                return Err("the redis sink needs REDIS_URL".to_string())
+            }
+            (SinkKind::Redis, Some(redis_url), _) => {
+                create_redis_writer_thread(rx, redis_url.clone()).boxed()
+            }
+            (SinkKind::Redis, None, _) => return Err("the redis sink needs REDIS_URL".to_string()),
+            (SinkKind::File, _, Some(sink_dir)) => {
+                create_file_sink_thread(rx, sink_dir.clone()).boxed()
+            }
+            (SinkKind::File, _, None) => return Err("the file sink needs SINK_DIR".to_string()),
+            (SinkKind::Stdout, _, _) => {
+                sinks.push(StdoutSink);
+                continue;
+            }
+        };
+        threads.push(thread);
+        sinks.push(ChannelSink { name: *kind, tx });
+    }
+
+    Ok((sinks, threads))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto_market_topic::{MarketType, MessageType};
+    use std::sync::Mutex;
+
+    #[derive(Clone, Default)]
+    struct RecordingSink {
+        sent: Arc<Mutex<Vec<(String, Vec<u8>)>>>,
+        opened: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Sink for RecordingSink {
+        fn send(&self, msg: Arc<SinkMessage>) {
+            let sent = (msg.topic.to_string(), msg.data.clone());
+            self.sent.lock().unwrap().push(sent);
+        }
+
+        fn open(&self, topic: &Topic) {
+            self.opened.lock().unwrap().push(topic.to_string());
+        }
+    }
+
+    fn topic(symbol: &str) -> Topic {
+        Topic::new("binance", MarketType::Spot, MessageType::Trade, symbol)
+    }
+
+    #[test]
+    fn routes_every_message_to_every_sink() {
+        let (first, second) = (RecordingSink::default(), RecordingSink::default());
+        let mut sinks = Sinks::default();
+        sinks.push(first.clone());
+        sinks.push(second.clone());
+
+        sinks.open(&topic("BTCUSDT"));
+        sinks.send(topic("BTCUSDT"), b"1".to_vec());
+        sinks.send(topic("ETHUSDT"), b"2".to_vec());
+
+        for sink in [first, second] {
+            assert_eq!(
+                *sink.sent.lock().unwrap(),
+                vec![
+                    ("binance_spot_trade_BTCUSDT".to_string(), b"1".to_vec()),
+                    ("binance_spot_trade_ETHUSDT".to_string(), b"2".to_vec()),
+                ]
+            );
+            assert_eq!(
+                *sink.opened.lock().unwrap(),
+                vec!["binance_spot_trade_BTCUSDT".to_string()]
+            );
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn file_sink_writes_length_prefixed_payloads() {
+        let sink_dir = std::env::temp_dir().join(format!("file-sink-{}", std::process::id()));
+        let (sinks, threads) = create_sinks(
+            &[SinkKind::File],
+            None,
+            Some(sink_dir.to_string_lossy().to_string()),
+            10,
+        )
+        .unwrap();
+        let payloads = vec![b"first".to_vec(), Vec::new(), vec![0u8; 300]];
+        for payload in payloads.iter() {
+            sinks.send(topic("BTCUSDT"), payload.clone());
+        }
+        // the file sink closes its files once its channel is
+        drop(sinks);
+        futures::future::join_all(threads).await;
+
+        let path = sink_dir.join(format!("{}.bin", topic("BTCUSDT").file_name()));
+        let content = std::fs::read(&path).unwrap();
+        let mut read = Vec::new();
+        let mut rest = content.as_slice();
+        while !rest.is_empty() {
+            let (len, tail) = rest.split_at(4);
+            let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+            read.push(tail[..len].to_vec());
+            rest = &tail[len..];
+        }
+        assert_eq!(read, payloads);
+        let _ = std::fs::remove_dir_all(&sink_dir);
+    }
+
+    #[test]
+    fn rejects_unknown_sinks() {
+        assert_eq!(" file ".parse::<SinkKind>(), Ok(SinkKind::File));
+        assert_eq!(
+            "kafka".parse::<SinkKind>(),
+            Err("unknown sink: kafka".to_string())
+        );
+        assert!(create_sinks(&[SinkKind::File], None, None, 10).is_err());
+        assert!(create_sinks(&[SinkKind::Redis], None, None, 10).is_err());
+    }
+}
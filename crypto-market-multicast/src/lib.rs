@@ -1,12 +1,13 @@
 extern crate net2;
 
 use net2::UdpBuilder;
+use crypto_market_topic::{Subscriber, Topic};
 use std::net::Ipv4Addr;
 use std::str::FromStr;
-use tokio::io::AsyncReadExt;
-use wmjtyd_libstock::message::zeromq::{Sub, Zeromq};
 
-pub async fn server(ip: &str, port: &str, ipcs: Vec<&str>) {
+/// Forward `topics`, and every topic matching `prefixes` through the broker,
+/// to the multicast group with a single subscriber.
+pub async fn server(ip: &str, port: &str, topics: Vec<Topic>, prefixes: Vec<String>) {
     // bind ip addr
     let socket = UdpBuilder::new_v4()
         .expect("cannot create UDP socket")
@@ -17,33 +18,31 @@ pub async fn server(ip: &str, port: &str, ipcs: Vec<&str>) {
 
     let addr = format!("{}:{}", ip, port);
 
-    let mut thread = Vec::new();
-
     let (tx, rx) = std::sync::mpsc::channel();
 
-    for ipc in ipcs {
-        let ipc = ipc.to_owned();
-        let tx = tx.clone();
-        thread.push(tokio::task::spawn(async move {
-            //  message queue
-            let mut mq = Zeromq::<Sub>::new(&ipc)
-                .await
-                .expect(format!("sub error; ipc: {}", ipc).as_str());
-            mq.subscribe("").await.expect("sub error;");
-
-            let mut data = [0u8; 8192];
+    tokio::task::spawn(async move {
+        //  message queue
+        let mut mq = if prefixes.is_empty() {
+            Subscriber::for_topics(&topics).await
+        } else {
+            let prefixes: Vec<String> = prefixes
+                .into_iter()
+                .chain(topics.iter().map(|topic| topic.to_string()))
+                .collect();
+            Subscriber::for_prefixes(&prefixes).await
+        }
+        .expect("sub error");
 
-            // Message pairs are used to obtain data and forward it to multicast
-            while let Ok(data_size) = mq.read(&mut data).await {
-                if 0 == data_size {
-                    break;
-                }
-                if tx.send(data[..data_size].to_vec()).is_err() {
-                    break;
-                }
+        // Message pairs are used to obtain data and forward it to multicast
+        while let Ok(data) = mq.recv().await {
+            if data.is_empty() {
+                break;
             }
-        }));
-    }
+            if tx.send(data).is_err() {
+                break;
+            }
+        }
+    });
 
     while let Ok(data) = rx.recv() {
         let size = socket
@@ -1,13 +1,16 @@
 use clap::clap_app;
+use crypto_market_topic::{Topic, Transport};
 
 #[tokio::main]
 async fn main() {
+    Transport::init_or_exit();
     let matches: clap::ArgMatches = clap_app!(quic =>
             (about: "Publish data in the form of multicast")
             (@subcommand server =>
                 (@arg IP: +required "comma_seperated_symbols")
                 (@arg PORT: +required "comma_seperated_symbols")
-                (@arg IPC: +required +use_delimiter "comma_seperated_symbols")
+                (@arg TOPICS: +use_delimiter "comma_seperated topics, e.g. binance_spot_trade_BTCUSDT")
+                (@arg PREFIXES: -p --prefixes +takes_value +use_delimiter "comma_seperated topic prefixes forwarded through the broker")
             )
             (@subcommand client =>
                 (@arg IP: +required "comma_seperated_symbols")
@@ -22,9 +25,20 @@ async fn main() {
             let ip = m.value_of("IP").unwrap();
             let port = m.value_of("PORT").unwrap();
 
-            let ipc = m.values_of("IPC").unwrap().collect::<Vec<&str>>();
+            let topics = m
+                .values_of("TOPICS")
+                .into_iter()
+                .flatten()
+                .map(|v| v.parse::<Topic>().expect("topic input error"))
+                .collect::<Vec<Topic>>();
+            let prefixes = m
+                .values_of("PREFIXES")
+                .into_iter()
+                .flatten()
+                .map(|v| v.to_string())
+                .collect::<Vec<String>>();
 
-            crypto_market_multicast::server(ip, port, ipc).await;
+            crypto_market_multicast::server(ip, port, topics, prefixes).await;
         }
         ("client", m) => {
             let m = m.expect("command error");
@@ -0,0 +1,309 @@
+use crypto_msg_parser::{MarketType, MessageType, Order, OrderBookMsg};
+use rust_decimal::{prelude::FromPrimitive, Decimal};
+use std::collections::{BTreeMap, VecDeque};
+
+// Deltas older than this are not replayed on top of a new snapshot
+const DELTA_WINDOW_MS: i64 = 10_000;
+const MAX_BUFFERED_DELTAS: usize = 10_000;
+
+fn update(side: &mut BTreeMap<Decimal, Order>, orders: &[Order]) {
+    for order in orders {
+        let price = match Decimal::from_f64(order.price) {
+            Some(price) => price,
+            None => continue,
+        };
+        if order.quantity_base == 0.0 {
+            side.remove(&price);
+        } else {
+            side.insert(price, order.clone());
+        }
+    }
+}
+
+/// A full L2 order book of one symbol, built from snapshots and deltas.
+///
+/// Deltas are only applied once a snapshot has been received, those that
+/// arrived shortly before a snapshot but are newer than it are replayed on
+/// top of it. Newer means a higher `seq_id` if the exchange has sequence
+/// numbers, a later timestamp otherwise.
+pub struct OrderBook {
+    exchange: String,
+    market_type: MarketType,
+    symbol: String,
+    pair: String,
+    timestamp: i64,
+    seq_id: Option<u64>,
+    asks: BTreeMap<Decimal, Order>,
+    bids: BTreeMap<Decimal, Order>,
+    synced: bool,
+    deltas: VecDeque<OrderBookMsg>,
+}
+
+impl OrderBook {
+    pub fn new(exchange: &str, market_type: MarketType, symbol: &str) -> Self {
+        OrderBook {
+            exchange: exchange.to_string(),
+            market_type,
+            symbol: symbol.to_string(),
+            pair: String::new(),
+            timestamp: 0,
+            seq_id: None,
+            asks: BTreeMap::new(),
+            bids: BTreeMap::new(),
+            synced: false,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    pub fn is_synced(&self) -> bool {
+        self.synced
+    }
+
+    pub fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+
+    /// Stop publishing the book until the next snapshot, after updates were lost.
+    pub fn invalidate(&mut self) {
+        self.synced = false;
+    }
+
+    /// Replace the whole book.
+    pub fn apply_snapshot(&mut self, snapshot: OrderBookMsg) {
+        if !snapshot.pair.is_empty() {
+            self.pair = snapshot.pair.clone();
+        }
+        self.asks.clear();
+        self.bids.clear();
+        update(&mut self.asks, &snapshot.asks);
+        update(&mut self.bids, &snapshot.bids);
+        self.timestamp = snapshot.timestamp;
+        self.seq_id = snapshot.seq_id;
+        self.synced = true;
+
+        // with sequence numbers `apply` skips the deltas already in the snapshot
+        let deltas = std::mem::take(&mut self.deltas);
+        for delta in deltas.iter() {
+            let sequenced = snapshot.seq_id.is_some() && delta.seq_id.is_some();
+            if self.synced && (sequenced || delta.timestamp > snapshot.timestamp) {
+                self.apply(delta);
+            }
+        }
+        self.deltas = deltas;
+    }
+
+    /// Apply an incremental update, a delta flagged as snapshot replaces the book.
+    pub fn apply_delta(&mut self, delta: OrderBookMsg) {
+        if delta.snapshot {
+            self.apply_snapshot(delta);
+            return;
+        }
+
+        if self.synced {
+            self.apply(&delta);
+        }
+
+        while let Some(front) = self.deltas.front() {
+            if self.deltas.len() >= MAX_BUFFERED_DELTAS
+                || front.timestamp < delta.timestamp - DELTA_WINDOW_MS
+            {
+                self.deltas.pop_front();
+            } else {
+                break;
+            }
+        }
+        self.deltas.push_back(delta);
+    }
+
+    // A delta already in the book is skipped, one that does not follow the
+    // book stops it until the next snapshot
+    fn apply(&mut self, delta: &OrderBookMsg) {
+        if let (Some(seq_id), Some(delta_seq_id)) = (self.seq_id, delta.seq_id) {
+            if delta_seq_id <= seq_id {
+                return;
+            }
+            if matches!(delta.prev_seq_id, Some(prev_seq_id) if prev_seq_id > seq_id) {
+                self.synced = false;
+                return;
+            }
+        }
+
+        update(&mut self.asks, &delta.asks);
+        update(&mut self.bids, &delta.bids);
+        if delta.timestamp > self.timestamp {
+            self.timestamp = delta.timestamp;
+        }
+        if delta.seq_id.is_some() {
+            self.seq_id = delta.seq_id;
+        }
+    }
+
+    /// The best `depth` levels of each side, or the full book if `depth` is `None`.
+    pub fn to_msg(&self, depth: Option<usize>) -> OrderBookMsg {
+        let depth = depth.unwrap_or(usize::MAX);
+        let msg_type = if depth == usize::MAX {
+            MessageType::L2Snapshot
+        } else {
+            MessageType::L2TopK
+        };
+
+        OrderBookMsg {
+            exchange: self.exchange.clone(),
+            market_type: self.market_type,
+            symbol: self.symbol.clone(),
+            pair: self.pair.clone(),
+            msg_type,
+            timestamp: self.timestamp,
+            seq_id: None,
+            prev_seq_id: None,
+            asks: self.asks.values().take(depth).cloned().collect(),
+            bids: self.bids.values().rev().take(depth).cloned().collect(),
+            snapshot: true,
+            json: String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn orders(levels: &[(f64, f64)]) -> Vec<Order> {
+        levels
+            .iter()
+            .map(|&(price, quantity)| Order {
+                price,
+                quantity_base: quantity,
+                quantity_quote: price * quantity,
+                quantity_contract: None,
+            })
+            .collect()
+    }
+
+    fn msg(
+        seq_ids: Option<(u64, u64)>,
+        timestamp: i64,
+        asks: &[(f64, f64)],
+        bids: &[(f64, f64)],
+        snapshot: bool,
+    ) -> OrderBookMsg {
+        OrderBookMsg {
+            exchange: "binance".to_string(),
+            market_type: MarketType::Spot,
+            symbol: "BTCUSDT".to_string(),
+            pair: "BTC/USDT".to_string(),
+            msg_type: if snapshot {
+                MessageType::L2Snapshot
+            } else {
+                MessageType::L2Event
+            },
+            timestamp,
+            seq_id: seq_ids.map(|(seq_id, _)| seq_id),
+            prev_seq_id: seq_ids.map(|(_, prev_seq_id)| prev_seq_id),
+            asks: orders(asks),
+            bids: orders(bids),
+            snapshot,
+            json: String::new(),
+        }
+    }
+
+    fn levels(orders: &[Order]) -> Vec<(f64, f64)> {
+        orders
+            .iter()
+            .map(|order| (order.price, order.quantity_base))
+            .collect()
+    }
+
+    #[test]
+    fn deltas_wait_for_a_snapshot() {
+        let mut book = OrderBook::new("binance", MarketType::Spot, "BTCUSDT");
+        book.apply_delta(msg(None, 1000, &[(100.0, 1.0)], &[], false));
+        assert!(!book.is_synced());
+
+        book.apply_snapshot(msg(None, 900, &[(101.0, 2.0)], &[(99.0, 1.0)], true));
+        assert!(book.is_synced());
+        let msg = book.to_msg(None);
+        assert_eq!(levels(&msg.asks), vec![(100.0, 1.0), (101.0, 2.0)]);
+        assert_eq!(levels(&msg.bids), vec![(99.0, 1.0)]);
+        assert_eq!(msg.pair, "BTC/USDT");
+    }
+
+    #[test]
+    fn replays_buffered_deltas_by_seq_id() {
+        let mut book = OrderBook::new("binance", MarketType::Spot, "BTCUSDT");
+        // in the snapshot already, despite a later timestamp
+        book.apply_delta(msg(Some((9, 8)), 1001, &[(100.0, 5.0)], &[], false));
+        // newer than the snapshot, despite an earlier timestamp
+        book.apply_delta(msg(Some((11, 10)), 999, &[(101.0, 1.0)], &[], false));
+
+        book.apply_snapshot(msg(Some((10, 0)), 1000, &[(100.0, 1.0)], &[], true));
+        assert!(book.is_synced());
+        assert_eq!(
+            levels(&book.to_msg(None).asks),
+            vec![(100.0, 1.0), (101.0, 1.0)]
+        );
+    }
+
+    #[test]
+    fn replays_buffered_deltas_by_timestamp_without_seq_ids() {
+        let mut book = OrderBook::new("binance", MarketType::Spot, "BTCUSDT");
+        book.apply_delta(msg(None, 999, &[(100.0, 5.0)], &[], false));
+        book.apply_delta(msg(None, 1001, &[(101.0, 1.0)], &[], false));
+
+        book.apply_snapshot(msg(None, 1000, &[(100.0, 1.0)], &[], true));
+        assert_eq!(
+            levels(&book.to_msg(None).asks),
+            vec![(100.0, 1.0), (101.0, 1.0)]
+        );
+    }
+
+    #[test]
+    fn a_gap_after_the_snapshot_unsyncs_the_book() {
+        let mut book = OrderBook::new("binance", MarketType::Spot, "BTCUSDT");
+        book.apply_delta(msg(Some((13, 12)), 1001, &[(101.0, 1.0)], &[], false));
+        book.apply_snapshot(msg(Some((10, 0)), 1000, &[(100.0, 1.0)], &[], true));
+        assert!(!book.is_synced());
+
+        book.apply_snapshot(msg(Some((13, 0)), 1002, &[(100.0, 1.0)], &[], true));
+        assert!(book.is_synced());
+        book.apply_delta(msg(Some((15, 14)), 1003, &[(102.0, 1.0)], &[], false));
+        assert!(!book.is_synced());
+    }
+
+    #[test]
+    fn skips_deltas_already_in_the_book() {
+        let mut book = OrderBook::new("binance", MarketType::Spot, "BTCUSDT");
+        book.apply_snapshot(msg(Some((10, 0)), 1000, &[(100.0, 1.0)], &[], true));
+        book.apply_delta(msg(Some((10, 9)), 1001, &[(100.0, 5.0)], &[], false));
+        book.apply_delta(msg(
+            Some((11, 10)),
+            1002,
+            &[(100.0, 0.0)],
+            &[(99.0, 2.0)],
+            false,
+        ));
+
+        assert!(book.is_synced());
+        let msg = book.to_msg(None);
+        assert!(msg.asks.is_empty());
+        assert_eq!(levels(&msg.bids), vec![(99.0, 2.0)]);
+        assert_eq!(book.timestamp(), 1002);
+    }
+
+    #[test]
+    fn top_k_levels() {
+        let mut book = OrderBook::new("binance", MarketType::Spot, "BTCUSDT");
+        book.apply_snapshot(msg(
+            None,
+            1000,
+            &[(102.0, 1.0), (101.0, 1.0), (103.0, 1.0)],
+            &[(98.0, 1.0), (99.0, 1.0), (97.0, 1.0)],
+            true,
+        ));
+
+        let msg = book.to_msg(Some(2));
+        assert_eq!(msg.msg_type, MessageType::L2TopK);
+        assert_eq!(levels(&msg.asks), vec![(101.0, 1.0), (102.0, 1.0)]);
+        assert_eq!(levels(&msg.bids), vec![(99.0, 1.0), (98.0, 1.0)]);
+    }
+}
@@ -0,0 +1,5 @@
+pub(crate) mod book;
+pub(crate) mod service;
+
+pub use book::OrderBook;
+pub use service::{create_orderbook_thread, OrderbookError, OrderbookResult};
@@ -0,0 +1,57 @@
+use clap::clap_app;
+use crypto_market_orderbook::create_orderbook_thread;
+use crypto_market_topic::Transport;
+use crypto_msg_parser::MarketType;
+use log::*;
+use std::{str::FromStr, time::Duration};
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() {
+    env_logger::init();
+    Transport::init_or_exit();
+
+    let matches: clap::ArgMatches = clap_app!(orderbook =>
+            (about: "Rebuild L2 order books from snapshots and deltas")
+            (@arg EXCHANGE:     +required "exchange")
+            (@arg MARKET_TYPE:  +required "market_type")
+            (@arg SYMBOLS: +required +use_delimiter "comma_seperated_symbols")
+            (@arg TOPK: -k --topk +takes_value "number of levels of the top-k book, 20 by default")
+            (@arg INTERVAL: -i --interval +takes_value "milliseconds between full snapshots, 1000 by default")
+    )
+    .get_matches();
+
+    let exchange = matches.value_of("EXCHANGE").unwrap();
+    let market_type_str = matches.value_of("MARKET_TYPE").unwrap();
+    let market_type = match MarketType::from_str(market_type_str) {
+        Ok(market_type) => market_type,
+        Err(_) => {
+            println!("Unknown market type: {}", market_type_str);
+            return;
+        }
+    };
+    let topk = matches
+        .value_of("TOPK")
+        .map(|v| v.parse::<usize>().expect("topk input error"))
+        .unwrap_or(20);
+    let interval = matches
+        .value_of("INTERVAL")
+        .map(|v| v.parse::<u64>().expect("interval input error"))
+        .unwrap_or(1000);
+
+    let mut tasks = Vec::new();
+    for symbol in matches.values_of("SYMBOLS").unwrap() {
+        tasks.push(tokio::task::spawn(create_orderbook_thread(
+            exchange.to_string(),
+            market_type,
+            symbol.to_string(),
+            topk,
+            Duration::from_millis(interval),
+        )));
+    }
+
+    for result in futures::future::join_all(tasks).await {
+        if let Err(err) = result.expect("create_orderbook_thread failed") {
+            error!("{}", err);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use super::OrderBook;
+
+use crypto_market_topic::{MessageType, Publisher, Subscriber, Topic, TopicType, TransportError};
+use crypto_msg_parser::{MarketType, OrderBookMsg};
+use log::*;
+use std::time::Duration;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use wmjtyd_libstock::data::orderbook::{decode_orderbook, encode_orderbook};
+
+#[derive(Clone, Copy)]
+enum Channel {
+    Snapshot,
+    Delta,
+    Gap,
+}
+
+enum BookUpdate {
+    Snapshot(OrderBookMsg),
+    Delta(OrderBookMsg),
+    Gap,
+}
+
+async fn subscribe(topic: Topic, channel: Channel, tx: UnboundedSender<BookUpdate>) {
+    let mut socket = match Subscriber::for_topic(&topic).await {
+        Ok(v) => v,
+        Err(err) => {
+            error!("sub error: {}; msg: {}", topic, err);
+            return;
+        }
+    };
+
+    loop {
+        match socket.recv().await {
+            Ok(buf) => {
+                if let Channel::Gap = channel {
+                    if tx.send(BookUpdate::Gap).is_err() {
+                        break;
+                    }
+                    continue;
+                }
+                let msg = match decode_orderbook(&buf) {
+                    Ok(msg) => msg,
+                    Err(err) => {
+                        warn!("{}: failed to decode orderbook: {:?}", topic, err);
+                        continue;
+                    }
+                };
+                let update = match channel {
+                    Channel::Snapshot => BookUpdate::Snapshot(msg),
+                    _ => BookUpdate::Delta(msg),
+                };
+                if tx.send(update).is_err() {
+                    break;
+                }
+            }
+            Err(err) => {
+                error!("{}: failed to receive payload: {}", topic, err);
+                break;
+            }
+        }
+    }
+}
+
+async fn create_publisher(topic: &Topic) -> OrderbookResult<Publisher> {
+    Ok(Publisher::for_topic(topic).await?)
+}
+
+async fn publish(socket: &mut Publisher, topic: &Topic, msg: &OrderBookMsg) {
+    let data = match encode_orderbook(msg) {
+        Ok(data) => data,
+        Err(err) => {
+            error!("{}: failed to encode orderbook: {:?}", topic, err);
+            return;
+        }
+    };
+    if let Err(err) = socket.send(&data).await {
+        error!("{}: failed to publish: {}", topic, err);
+    }
+}
+
+/// Rebuild the book of one symbol and republish it.
+///
+/// The full book is published on `{exchange}_{market_type}_l2_book_{symbol}`
+/// every `interval`, the top-K on `{exchange}_{market_type}_l2_book_topk_{symbol}`
+/// after every update.
+pub async fn create_orderbook_thread(
+    exchange: String,
+    market_type: MarketType,
+    symbol: String,
+    topk: usize,
+    interval: Duration,
+) -> OrderbookResult<()> {
+    let topic = |topic_type: TopicType| Topic::new(&exchange, market_type, topic_type, &symbol);
+    let book_topic = topic(TopicType::L2Book);
+    let topk_topic = topic(TopicType::L2BookTopK);
+
+    let mut book_socket = create_publisher(&book_topic).await?;
+    let mut topk_socket = create_publisher(&topk_topic).await?;
+
+    let (tx, mut rx) = unbounded_channel();
+    tokio::task::spawn(subscribe(
+        topic(MessageType::L2Snapshot.into()),
+        Channel::Snapshot,
+        tx.clone(),
+    ));
+    tokio::task::spawn(subscribe(
+        topic(MessageType::L2Event.into()),
+        Channel::Delta,
+        tx.clone(),
+    ));
+    tokio::task::spawn(subscribe(topic(TopicType::L2Gap), Channel::Gap, tx));
+
+    let mut book = OrderBook::new(&exchange, market_type, &symbol);
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            update = rx.recv() => {
+                match update {
+                    Some(BookUpdate::Snapshot(msg)) => book.apply_snapshot(msg),
+                    Some(BookUpdate::Delta(msg)) => book.apply_delta(msg),
+                    Some(BookUpdate::Gap) => {
+                        warn!("{}: sequence gap, waiting for the next snapshot", book_topic);
+                        book.invalidate();
+                    }
+                    None => break,
+                }
+                if book.is_synced() {
+                    publish(&mut topk_socket, &topk_topic, &book.to_msg(Some(topk))).await;
+                }
+            }
+            _ = ticker.tick() => {
+                if book.is_synced() {
+                    publish(&mut book_socket, &book_topic, &book.to_msg(None)).await;
+                }
+            }
+        }
+    }
+
+    warn!("{}: all subscriptions have ended", book_topic);
+    Ok(())
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum OrderbookError {
+    #[error("transport error: {0}")]
+    TransportError(#[from] TransportError),
+}
+
+pub type OrderbookResult<T> = Result<T, OrderbookError>;
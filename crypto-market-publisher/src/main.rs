@@ -6,6 +6,7 @@ mod server;
 use std::net::SocketAddr;
 
 use clap::{clap_app, ArgMatches};
+use crypto_market_topic::{Topic, Transport};
 
 use server::create_server;
 
@@ -18,6 +19,17 @@ extern crate log;
 
 const MAX_DATAGRAM_SIZE: usize = 1350;
 
+fn topic_of(m: &ArgMatches) -> Topic {
+    Topic::from_parts(
+        m.value_of("EXCHANGE").unwrap(),
+        m.value_of("MARKET_TYPE").unwrap(),
+        m.value_of("MSG_TYPE").unwrap(),
+        m.value_of("SYMBOL").unwrap(),
+        m.value_of("PERIOD"),
+    )
+    .expect("topic input error")
+}
+
 fn start_server(m: &ArgMatches) {
     let addr: SocketAddr = if let Some(v) = m.value_of("ADDR") {
         v.parse().unwrap()
@@ -29,17 +41,14 @@ fn start_server(m: &ArgMatches) {
 
     let key = m.value_of("KEY").unwrap_or("./examples/cert.key");
 
-    let exchange = m.value_of("EXCHANGE").unwrap();
-    let market_type = m.value_of("MARKET_TYPE").unwrap();
-    let msg_type = m.value_of("MSG_TYPE").unwrap();
-
-    let ipc = if let Some(period) = m.value_of("PERIOD") {
-        format!("{}_{}_{}_{}", exchange, market_type, msg_type, period)
-    } else {
-        format!("{}_{}_{}", exchange, market_type, msg_type)
-    };
+    let ipc = topic_of(m).to_string();
     debug!("ipc: {}", ipc);
 
+    let prefixes = m
+        .values_of("PREFIXES")
+        .map(|v| v.map(|prefix| prefix.to_string()).collect())
+        .unwrap_or_default();
+
     let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
 
     debug!("{} {}", crt, key);
@@ -63,7 +72,7 @@ fn start_server(m: &ArgMatches) {
     config.set_disable_active_migration(true);
     config.enable_early_data();
 
-    create_server(addr, ipc, config);
+    create_server(addr, vec![ipc], prefixes, config);
 }
 
 fn start_client(m: &ArgMatches) {
@@ -75,15 +84,7 @@ fn start_client(m: &ArgMatches) {
 
     let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
 
-    let exchange = m.value_of("EXCHANGE").unwrap();
-    let market_type = m.value_of("MARKET_TYPE").unwrap();
-    let msg_type = m.value_of("MSG_TYPE").unwrap();
-
-    let ipc = if let Some(period) = m.value_of("PERIOD") {
-        format!("{}_{}_{}_{}", exchange, market_type, msg_type, period)
-    } else {
-        format!("{}_{}_{}", exchange, market_type, msg_type)
-    };
+    let ipc = topic_of(m).to_string();
 
     let _subscribe_list = vec![ipc.as_str()];
 
@@ -115,6 +116,7 @@ fn start_client(m: &ArgMatches) {
 #[tokio::main]
 async fn main() {
     env_logger::init().unwrap();
+    Transport::init_or_exit();
     let matches: clap::ArgMatches = clap_app!(quic =>
         (@subcommand server =>
             (about: "start server")
@@ -122,9 +124,11 @@ async fn main() {
             (@arg EXCHANGE:     +required "exchange")
             (@arg MARKET_TYPE:  +required "market_type")
             (@arg MSG_TYPE:     +required "msg_type")
+            (@arg SYMBOL:       +required "symbol")
             (@arg PERIOD: "period")
             (@arg CRT: -c --crt +takes_value "ctr file path")
             (@arg KEY: -k --key +takes_value "key file path")
+            (@arg PREFIXES: -p --prefixes +takes_value +use_delimiter "comma_seperated topic prefixes served through the broker")
         )
         (@subcommand client =>
             (about: "use client")
@@ -132,6 +136,7 @@ async fn main() {
             (@arg EXCHANGE:     +required "exchange")
             (@arg MARKET_TYPE:  +required "market_type")
             (@arg MSG_TYPE:     +required "msg_type")
+            (@arg SYMBOL:       +required "symbol")
             (@arg PERIOD: "period")
         )
     )
@@ -5,12 +5,10 @@ use std::{
     thread,
 };
 
+use crypto_market_topic::{Subscriber, Topic};
 use mio::{net::UdpSocket};
 use quiche::Config;
 use ring::rand::SystemRandom;
-use wmjtyd_libstock::message::zeromq::Zeromq;
-use tokio::io::AsyncReadExt;
-use wmjtyd_libstock::message::zeromq::Sub;
 
 const MAX_DATAGRAM_SIZE: usize = 1350;
 
@@ -42,7 +40,7 @@ struct Client {
     partial_responses: HashMap<u64, PartialResponse>,
 }
 
-pub fn create_server(addr: SocketAddr, ipc: String, config: Config) {
+pub fn create_server(addr: SocketAddr, ipcs: Vec<String>, prefixes: Vec<String>, config: Config) {
     let mut service = Vec::new();
 
     debug!("{:?}", addr);
@@ -54,8 +52,6 @@ pub fn create_server(addr: SocketAddr, ipc: String, config: Config) {
 
     let socket = Arc::new(socket);
 
-    let ipcs= vec![ipc];
-
     // 套字节
     let socket_clone = socket.clone();
 
@@ -72,7 +68,7 @@ pub fn create_server(addr: SocketAddr, ipc: String, config: Config) {
     let socket_clone = socket.clone();
     // 发送
     service.push(thread::spawn(|| {
-        server_send(ipcs, socket_clone);
+        server_send(ipcs, prefixes, socket_clone);
     }));
 
     for s in service {
@@ -324,42 +320,53 @@ fn server_alive_check() {
     }
 }
 
-fn server_send(ipcs: Vec<String>, socket: Arc<UdpSocket>) {
+// All topics are received through one subscriber, with prefixes the topics
+// become subscribable by clients as they show up on the broker
+fn server_send(ipcs: Vec<String>, prefixes: Vec<String>, socket: Arc<UdpSocket>) {
 
     let mut client_sub_lock = CLIENT_SUBSCRIBE.lock().unwrap();
 
+    let mut topics = Vec::new();
     for ipc in ipcs {
-    client_sub_lock.insert(ipc, HashSet::new());
+        match ipc.parse::<Topic>() {
+            Ok(topic) => topics.push(topic),
+            Err(err) => {
+                error!("{}", err);
+                continue;
+            }
+        };
+        client_sub_lock.insert(ipc, HashSet::new());
     }
-    // client_sub_lock.extend(iter)
-
-
-    for topic in client_sub_lock.keys() {
-        let url = format!("ipc:///tmp/{}.ipc", topic);
-        let topic = topic.to_owned();
-        let url = url.to_string();
-        let network_socket = socket.clone();
-
-        debug!("{}", url);
-        tokio::task::spawn(async move {
-            let mut socket = Zeromq::<Sub>::new(&url).await.unwrap();
-            socket.subscribe("").await.unwrap();
-
-            let mut buf = [0_u8;8192];
-            loop {
-                match socket.read(&mut buf).await{
-                    Ok(buf_size) => {
-                        debug!("sub data len: {}", buf.len());
-                        distribute(network_socket.clone(), topic.to_string(), &buf[..buf_size]);
-                    }
-                    Err(err) => {
-                        debug!("Client failed to receive msg '{}'.", err);
-                        break;
+    drop(client_sub_lock);
+
+    debug!("{:?} {:?}", topics, prefixes);
+    tokio::task::spawn(async move {
+        let mut sub = if prefixes.is_empty() {
+            Subscriber::for_topics(&topics).await.unwrap()
+        } else {
+            let prefixes: Vec<String> = prefixes
+                .into_iter()
+                .chain(topics.iter().map(|topic| topic.to_string()))
+                .collect();
+            Subscriber::for_prefixes(&prefixes).await.unwrap()
+        };
+
+        loop {
+            match sub.recv_topic().await{
+                Ok((topic, buf)) => {
+                    debug!("sub data len: {}", buf.len());
+                    if let Ok(mut client_sub) = CLIENT_SUBSCRIBE.lock() {
+                        client_sub.entry(topic.clone()).or_default();
                     }
+                    distribute(socket.clone(), topic, &buf);
+                }
+                Err(err) => {
+                    debug!("Client failed to receive msg '{}'.", err);
+                    break;
                 }
             }
-        });
-    }
+        }
+    });
 }
 
 fn distribute(socket: Arc<UdpSocket>, key: String, data: &[u8]) {
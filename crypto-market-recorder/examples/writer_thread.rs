@@ -1,10 +1,11 @@
-use crypto_market_recorder::create_write_file_thread;
+use crypto_market_recorder::{create_write_file_thread, Subscription};
 
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt::init();
 
-    create_write_file_thread("", "", "", "".to_string())
+    let topic = "binance_spot_trade_BTCUSDT".parse().unwrap();
+    create_write_file_thread(Subscription::Topics(vec![topic]), futures::future::pending())
         .await
         .expect("create_write_file_thread failed");
 }
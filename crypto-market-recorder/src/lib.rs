@@ -1,3 +1,5 @@
+pub(crate) mod shutdown;
 pub(crate) mod writers;
 
-pub use writers::create_write_file_thread;
+pub use shutdown::shutdown_signal;
+pub use writers::{create_write_file_thread, DataEntry, FileOutput, Subscription};
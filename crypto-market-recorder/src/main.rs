@@ -1,43 +1,44 @@
 use clap::clap_app;
-use concat_string::concat_string;
-use crypto_market_recorder::create_write_file_thread;
+use crypto_market_recorder::{create_write_file_thread, shutdown_signal, Subscription};
+use crypto_market_topic::{Topic, Transport};
 
 #[tokio::main(flavor = "multi_thread")]
 async fn main() {
     env_logger::init();
+    Transport::init_or_exit();
 
     let matches: clap::ArgMatches = clap_app!(quic =>
             (about: "use save file")
-            (@arg EXCHANGE:     +required "exchange")
-            (@arg MARKET_TYPE:  +required "market_type")
-            (@arg MSG_TYPE:     +required "msg_type")
-            (@arg SYMBOL: +required "symbol")
+            (@arg EXCHANGE:     "exchange")
+            (@arg MARKET_TYPE:  "market_type")
+            (@arg MSG_TYPE:     "msg_type")
+            (@arg SYMBOL: "symbol")
             (@arg PERIOD: "period")
+            (@arg PREFIXES: -p --prefixes +takes_value +use_delimiter "comma_seperated topic prefixes to record through the broker")
     )
     .get_matches();
 
-    let exchange = matches.value_of("EXCHANGE").unwrap();
-    let market_type = matches.value_of("MARKET_TYPE").unwrap();
-    let msg_type = matches.value_of("MSG_TYPE").unwrap();
-    let symbol = matches.value_of("SYMBOL").unwrap();
-
-    let ipc = if let Some(period) = matches.value_of("PERIOD") {
-        concat_string!(
-            exchange,
-            "_",
-            market_type,
-            "_",
-            msg_type,
-            "_",
-            symbol,
-            "_",
-            period
-        )
+    let subscription = if let Some(prefixes) = matches.values_of("PREFIXES") {
+        Subscription::Prefixes(prefixes.map(|v| v.to_string()).collect())
     } else {
-        concat_string!(exchange, "_", market_type, "_", msg_type, "_", symbol)
+        let topic = match Topic::from_parts(
+            matches.value_of("EXCHANGE").unwrap_or(""),
+            matches.value_of("MARKET_TYPE").unwrap_or(""),
+            matches.value_of("MSG_TYPE").unwrap_or(""),
+            matches.value_of("SYMBOL").unwrap_or(""),
+            matches.value_of("PERIOD"),
+        ) {
+            Ok(topic) => topic,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        Subscription::Topics(vec![topic])
     };
 
-    create_write_file_thread(exchange, market_type, msg_type, ipc.to_string())
-        .await
-        .expect("create_write_file_thread failed");
+    match create_write_file_thread(subscription, shutdown_signal()).await {
+        Ok(()) => std::process::exit(0),
+        Err(_) => std::process::exit(1),
+    }
 }
@@ -0,0 +1,38 @@
+use futures::channel::oneshot;
+use std::future::Future;
+use tracing::{error, warn};
+
+/// Resolves on the first SIGTERM or SIGINT, a second one exits at once
+/// without flushing.
+pub fn shutdown_signal() -> impl Future<Output = ()> {
+    let (tx, rx) = oneshot::channel::<()>();
+
+    #[cfg(not(windows))]
+    std::thread::spawn(move || {
+        use signal_hook::{
+            consts::signal::{SIGINT, SIGTERM},
+            iterator::Signals,
+        };
+
+        let mut signals = Signals::new(&[SIGTERM, SIGINT]).expect("cannot register signals");
+        let mut tx = Some(tx);
+        for signal in signals.forever() {
+            match tx.take() {
+                Some(tx) => {
+                    warn!("Received signal {}, shutting down", signal);
+                    let _ = tx.send(());
+                }
+                None => {
+                    error!("Received signal {} again, exiting without flushing", signal);
+                    std::process::exit(128 + signal);
+                }
+            }
+        }
+    });
+    #[cfg(windows)]
+    std::mem::forget(tx);
+
+    async move {
+        let _ = rx.await;
+    }
+}
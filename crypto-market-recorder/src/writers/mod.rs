@@ -1,73 +1,169 @@
-// pub(super) mod file_writer;
+use crypto_market_topic::{Subscriber, Topic, TransportError};
+use std::{future::Future, thread::JoinHandle, time::Duration};
+use tokio::{
+    runtime::RuntimeMetrics,
+    sync::{mpsc, oneshot},
+};
+use tracing::{error, info, warn};
+use wmjtyd_libstock::file::writer::{DataWriter, WriteError};
 
-use concat_string::concat_string;
-use tracing::error;
-use wmjtyd_libstock::file::writer::{DataEntry, WriteError};
+pub use wmjtyd_libstock::file::writer::DataEntry;
 
-// pub use file_writer::FileWriter;
-pub use wmjtyd_libstock::file::writer::DataWriter;
-
-use wmjtyd_libstock::message::zeromq::Zeromq;
-use tokio::io::AsyncReadExt;
-use wmjtyd_libstock::message::zeromq::Sub;
+// How often a closing file output checks whether everything is written
+const CLOSE_POLL_INTERVAL: Duration = Duration::from_millis(10);
 
 pub trait Writer {
     fn write(&mut self, s: &str);
     fn close(&mut self);
 }
 
-pub async fn create_write_file_thread(
-    _exchange: &str,
-    _market_type: &str,
-    _msg_type: &str,
-    ipc: String,
+/// Topic files written by libstock's `DataWriter`, on a thread and runtime of
+/// their own so that `close` can wait until every entry is written.
+///
+/// The data writer writes in tasks it spawns and doesn't hand out, the runtime
+/// runs nothing else, so everything is written once it has no task left.
+pub struct FileOutput {
+    tx: mpsc::UnboundedSender<DataEntry>,
+    thread: JoinHandle<RecorderWriterResult<()>>,
+}
+
+impl FileOutput {
+    pub async fn start() -> RecorderWriterResult<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let (tx, rx) = mpsc::unbounded_channel::<DataEntry>();
+        let (started_tx, started_rx) = oneshot::channel::<RecorderWriterResult<()>>();
+        let thread = std::thread::Builder::new()
+            .name("file-output".to_string())
+            .spawn(move || {
+                let metrics = runtime.metrics();
+                runtime.block_on(write_files(rx, started_tx, metrics))
+                // dropping the runtime waits for its blocking tasks
+            })?;
+
+        match started_rx.await {
+            Ok(Ok(())) => Ok(FileOutput { tx, thread }),
+            Ok(Err(err)) => Err(err),
+            Err(_) => Err(RecorderWriterError::FileOutputExited),
+        }
+    }
+
+    /// Queue an entry, fails if the writer has stopped on an error, which
+    /// `close` returns.
+    pub fn add(&self, entry: DataEntry) -> RecorderWriterResult<()> {
+        self.tx
+            .send(entry)
+            .map_err(|_| RecorderWriterError::FileOutputExited)
+    }
+
+    /// Write every queued entry and close the files.
+    pub async fn close(self) -> RecorderWriterResult<()> {
+        let FileOutput { tx, thread } = self;
+        drop(tx);
+        tokio::task::spawn_blocking(move || thread.join())
+            .await?
+            .unwrap_or(Err(RecorderWriterError::FileOutputExited))
+    }
+}
+
+async fn write_files(
+    mut rx: mpsc::UnboundedReceiver<DataEntry>,
+    started: oneshot::Sender<RecorderWriterResult<()>>,
+    metrics: RuntimeMetrics,
 ) -> RecorderWriterResult<()> {
-    // concat_string for better performance
-    let ipc_exchange_market_type_msg_type = concat_string!("ipc:///tmp/", ipc, ".ipc");
+    let mut data_writer = DataWriter::new();
+    if let Err(err) = data_writer.start().await {
+        let _ = started.send(Err(err.into()));
+        return Ok(());
+    }
+    let _ = started.send(Ok(()));
+
+    while let Some(entry) = rx.recv().await {
+        data_writer.add(entry)?;
+    }
+    drop(data_writer);
+    while metrics.num_alive_tasks() > 0 {
+        tokio::time::sleep(CLOSE_POLL_INTERVAL).await;
+    }
+    Ok(())
+}
+
+/// What the recorder subscribes to, every topic is written to its own file.
+pub enum Subscription {
+    Topics(Vec<Topic>),
+    /// Topic prefixes, through the broker
+    Prefixes(Vec<String>),
+}
 
+/// Write every received topic to its own file until `shutdown` resolves, then
+/// flush and close the files.
+pub async fn create_write_file_thread<F>(
+    subscription: Subscription,
+    shutdown: F,
+) -> RecorderWriterResult<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
     let task = async move {
-        let mut data_writer = DataWriter::new();
-        data_writer.start().await?;
-        let mut socket = Zeromq::<Sub>::new(&ipc_exchange_market_type_msg_type).await.unwrap();
-        socket.subscribe("").await.unwrap();
-
-        let mut buf = [0u8; 8192];
-        tokio::task::spawn( async move { loop {
-            // 数据 payload
-            let size = socket.read(&mut buf).await;
-            match size {
-                Ok(size) => {
-                    let result = data_writer.add(DataEntry {
-                        filename: ipc.to_string(),
-                        data: buf[..size].to_vec(),
-                    });
-
-                    if let Err(e) = result {
-                        error!("Failed to add data: {e}");
-                        break;
+        let files = FileOutput::start().await?;
+        let mut socket = match subscription {
+            Subscription::Topics(topics) => Subscriber::for_topics(&topics).await?,
+            Subscription::Prefixes(prefixes) => Subscriber::for_prefixes(&prefixes).await?,
+        };
+
+        tokio::task::spawn( async move {
+            let mut shutdown = Box::pin(shutdown);
+            let result: RecorderWriterResult<()> = loop {
+                // 数据 payload
+                let payload = tokio::select! {
+                    payload = socket.recv_topic() => payload,
+                    _ = &mut shutdown => {
+                        info!("Stopped receiving, flushing the files");
+                        break Ok(());
+                    }
+                };
+                match payload {
+                    Ok((topic, data)) => {
+                        // the topic frame comes off the wire, and names the file
+                        let topic = match topic.parse::<Topic>() {
+                            Ok(topic) => topic,
+                            Err(err) => {
+                                warn!("Dropped a message of an invalid topic: {err}");
+                                continue;
+                            }
+                        };
+                        let result = files.add(DataEntry {
+                            filename: topic.file_name(),
+                            data,
+                        });
+
+                        if let Err(e) = result {
+                            error!("Failed to add data: {e}");
+                            break Err(e);
+                        }
+                    }
+                    Err(err) => {
+                        error!("Client failed to receive payload: {err}");
+                        break Err(err.into());
                     }
                 }
-                Err(err) => {
-                    error!("Client failed to receive payload: {err}");
-                    break;
-                }
-            }
-        }}).await?;
-
+            };
 
-        Ok::<(), RecorderWriterError>(())
+            let closed = files.close().await;
+            if let Err(ref err) = closed {
+                error!("Failed to close the files: {err}");
+            }
+            // a failed write stops the output, its error is the one closing returns
+            closed.and(result)
+        }).await?
     };
 
-    tokio::task::spawn(async {
-        let response = task.await;
-
-        if let Err(err) = response {
-            error!("failed to run create_write_file task: {err}");
-        }
-    })
-    .await?;
-
-    Ok(())
+    let response: RecorderWriterResult<()> = tokio::task::spawn(task).await?;
+    if let Err(ref err) = response {
+        error!("failed to run create_write_file task: {err}");
+    }
+    response
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -75,9 +171,18 @@ pub enum RecorderWriterError {
     #[error("writer error: {0}")]
     WriterError(#[from] WriteError),
 
+    #[error("transport error: {0}")]
+    TransportError(#[from] TransportError),
+
     #[error("failed to create the writer thread: {0}")]
     CreateThreadFailed(#[from] tokio::task::JoinError),
 
+    #[error("failed to start the file output: {0}")]
+    FileOutputFailed(#[from] std::io::Error),
+
+    #[error("the file output has exited")]
+    FileOutputExited,
+
     #[error("failed to shutdown the socket: {0}")]
     SocketShutdownFailed(nanomsg::Error),
 
@@ -0,0 +1,170 @@
+use clap::clap_app;
+use crypto_market_integration::{encode_topics, CaptureReader};
+use crypto_market_recorder::{DataEntry, FileOutput};
+use crypto_market_topic::{Publisher, Topic, Transport};
+use log::*;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Where the re-encoded messages go.
+enum Output {
+    /// Topic files, the same as crypto-market-recorder writes
+    Files(FileOutput),
+    Publishers(HashMap<Topic, Publisher>),
+}
+
+impl Output {
+    async fn write(&mut self, topic: Topic, data: Vec<u8>) {
+        match self {
+            Output::Files(files) => {
+                let entry = DataEntry {
+                    filename: topic.file_name(),
+                    data,
+                };
+                if let Err(err) = files.add(entry) {
+                    error!("failed to write {}: {}", topic, err);
+                }
+            }
+            Output::Publishers(publishers) => {
+                if !publishers.contains_key(&topic) {
+                    match Publisher::for_topic(&topic).await {
+                        Ok(publisher) => publishers.insert(topic.clone(), publisher),
+                        Err(err) => {
+                            error!("failed to publish {}: {}", topic, err);
+                            return;
+                        }
+                    };
+                }
+                let publisher = publishers.get_mut(&topic).unwrap();
+                if let Err(err) = publisher.send(&data).await {
+                    error!("failed to publish {}: {}", topic, err);
+                }
+            }
+        }
+    }
+
+    /// Write what is queued and close the files or sockets.
+    async fn close(self) {
+        match self {
+            Output::Files(files) => {
+                if let Err(err) = files.close().await {
+                    error!("failed to close the files: {}", err);
+                }
+            }
+            Output::Publishers(publishers) => {
+                for (topic, publisher) in publishers {
+                    if let Err(err) = publisher.close().await {
+                        error!("failed to close the publisher of {}: {}", topic, err);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Spaces republished messages the way they were received, scaled by
+/// `speed`, so subscribers are not flooded. A speed of 0 does not wait.
+struct Pacer {
+    speed: f64,
+    // when the first message was received and republished
+    start: Option<(u64, Instant)>,
+}
+
+impl Pacer {
+    fn new(speed: f64) -> Self {
+        Pacer { speed, start: None }
+    }
+
+    async fn wait(&mut self, received_at: u64) {
+        if self.speed == 0.0 {
+            return;
+        }
+        let (first_received_at, started) = *self
+            .start
+            .get_or_insert_with(|| (received_at, Instant::now()));
+        let offset = received_at.saturating_sub(first_received_at) as f64 / self.speed;
+        let due = started + Duration::from_secs_f64(offset / 1000.0);
+        tokio::time::sleep_until(due.into()).await;
+    }
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() {
+    env_logger::init();
+    Transport::init_or_exit();
+
+    let matches: clap::ArgMatches = clap_app!(reparse =>
+            (about: "Parse and encode raw message captures again, e.g. after a parser fix")
+            (@arg CAPTURES: +required +multiple "capture files written with --capture_dir")
+            (@arg PERIOD: -p --period +takes_value "period of candlestick captures written without one")
+            (@arg WRITE_FILES: -w --write_files "write topic files like crypto-market-recorder instead of republishing")
+            (@arg SPEED: -s --speed +takes_value "republishing speed relative to the capture, 1 by default, 0 for as fast as possible")
+    )
+    .get_matches();
+
+    let default_period = matches.value_of("PERIOD").unwrap_or("");
+    let speed = match matches.value_of("SPEED").unwrap_or("1").parse::<f64>() {
+        Ok(speed) if speed >= 0.0 => speed,
+        _ => {
+            println!("Invalid speed: {}", matches.value_of("SPEED").unwrap());
+            return;
+        }
+    };
+    let mut output = if matches.is_present("WRITE_FILES") {
+        let files = FileOutput::start()
+            .await
+            .expect("failed to start the data writer");
+        Output::Files(files)
+    } else {
+        Output::Publishers(HashMap::new())
+    };
+
+    let (mut messages, mut payloads, mut failures) = (0u64, 0u64, 0u64);
+    for path in matches.values_of("CAPTURES").unwrap() {
+        let reader = match CaptureReader::open(path) {
+            Ok(reader) => reader,
+            Err(err) => {
+                error!("failed to open {}: {}", path, err);
+                continue;
+            }
+        };
+        info!("re-parsing {}", path);
+
+        let mut pacer = Pacer::new(speed);
+        for (msg, period) in reader {
+            messages += 1;
+            if let Output::Publishers(_) = output {
+                pacer.wait(msg.received_at).await;
+            }
+            let period = if period.is_empty() {
+                default_period
+            } else {
+                period.as_str()
+            };
+            match encode_topics(&msg, period) {
+                Ok(topics) => {
+                    for (topic, data) in topics {
+                        payloads += 1;
+                        output.write(topic, data).await;
+                    }
+                }
+                Err(err) => {
+                    failures += 1;
+                    warn!(
+                        "{} {} {} received at {}: {}",
+                        msg.exchange, msg.market_type, msg.msg_type, msg.received_at, err
+                    );
+                }
+            }
+        }
+    }
+
+    info!(
+        "{} messages re-parsed into {} payloads, {} failed to parse",
+        messages, payloads, failures
+    );
+
+    output.close().await;
+}
@@ -10,6 +10,7 @@ use axum::{
     Json, Router,
 };
 use crypto_crawler::{MarketType, Message};
+use crypto_market_topic::{Topic, TopicResult, Transport};
 
 use chrono::{Duration, TimeZone, Utc};
 use crypto_msg_parser::parse_l2_topk;
@@ -41,6 +42,7 @@ async fn main() {
         std::env::set_var("RUST_LOG", "example_websockets=debug,tower_http=debug")
     }
     tracing_subscriber::fmt::init();
+    Transport::init_or_exit();
     let application_config = init_config().await;
     let port = application_config.port;
     let app = Router::new()
@@ -118,7 +120,11 @@ pub async fn handler(
 ) -> impl IntoResponse {
     println!("1111");
     //Todo: 从配置文件读取配置
-    let src = application_config.record_dir + &filename(&params) + ".csv";
+    let filename = match filename(&params) {
+        Ok(filename) => filename,
+        Err(err) => return Err((StatusCode::BAD_REQUEST, err.to_string())),
+    };
+    let src = application_config.record_dir + &filename + ".csv";
     println!("{}", src);
     // let  mut stream = ReaderStream::new(File::open("./data.csv").await.unwrap());
 
@@ -157,71 +163,22 @@ pub async fn handler(
     Ok((headers, body))
 }
 
-pub fn filename(params: &Params) -> String{
-    // let mut files = Vec::new();
-    let exchange = &params.exchange;
-    let market_type = &params.market_type;
-    let msg_type = &params.msg_type;
-    let symbol = &params.symbols;
-    let date = &params.date;
-    // date+ "/" + exchange+market_type+msg_type+symbol
-    // format!("{}/{}_{}_{}_{}", date,exchange, market_type, msg_type, symbol);
-    // let mut begin_datetime = Utc.timestamp(params.begin_datetime, 0);
-    // let end_datetime = Utc.timestamp(params.end_datetime, 0);
-    // let days = (end_datetime - begin_datetime).num_days();
-    let fileName = if let Some(period) = &params.period {
-        if period.is_empty() {
-            format!("/{}/{}_{}_{}_{}",date,exchange, market_type, msg_type, symbol)
-        }else {
-            format!("/{}/{}_{}_{}_{}_{}", date,exchange, market_type, msg_type, symbol,period)
-        }
-        // format!("/{}/{}_{}_{}_{}_{}", date,exchange, market_type, msg_type, symbol,period)
-    } else {
-        format!("/{}/{}_{}_{}_{}",date,exchange, market_type, msg_type, symbol)
-    };
-    fileName
-    // let mut datetime = format!("{}", begin_datetime.format("%Y%m%d"));
-    //循环
-    // for _i in 0..days {
-    //     let ipc = if let Some(period) = &params.period {
-    //         format!(
-    //             "{}_{}_{}_{}_{}",
-    //             exchange, market_type, msg_type, symbol, period
-    //         )
-    //     } else {
-    //         format!("{}_{}_{}_{}", exchange, market_type, msg_type, symbol)
-    //     };
-    //     let filename = format!("{}/{}", datetime, ipc);
-    //     files.push(filename);
-    //     begin_datetime = begin_datetime.add(Duration::days(1));
-    //     datetime = format!("{}", begin_datetime.format("%Y%m%d"));
-    // }
-    // files
+fn topic(params: &Params) -> TopicResult<Topic> {
+    Topic::from_parts(
+        &params.exchange,
+        &params.market_type,
+        &params.msg_type,
+        &params.symbols,
+        params.period.as_deref(),
+    )
 }
-pub fn fileNamePartData(params: &Params) -> String{
-    // let mut files = Vec::new();
-    let exchange = &params.exchange;
-    let market_type = &params.market_type;
-    let msg_type = &params.msg_type;
-    let symbol = &params.symbols;
-    let date = &params.date;
-    // date+ "/" + exchange+market_type+msg_type+symbol
-    // format!("{}/{}_{}_{}_{}", date,exchange, market_type, msg_type, symbol);
-    // let mut begin_datetime = Utc.timestamp(params.begin_datetime, 0);
-    // let end_datetime = Utc.timestamp(params.end_datetime, 0);
-    // let days = (end_datetime - begin_datetime).num_days();
-    let fileName = if let Some(period) = &params.period {
-        if period.is_empty() {
-            format!("{}_{}_{}_{}",exchange, market_type, msg_type, symbol)
-        }else {
-            format!("{}_{}_{}_{}_{}",exchange, market_type, msg_type, symbol,period)
-        }
 
-    } else {
-        format!("{}_{}_{}_{}",exchange, market_type, msg_type, symbol)
-    };
-    fileName
+pub fn filename(params: &Params) -> TopicResult<String> {
+    Ok(format!("/{}/{}", params.date, topic(params)?.file_name()))
+}
 
+pub fn fileNamePartData(params: &Params) -> TopicResult<String> {
+    Ok(topic(params)?.file_name())
 }
 
 #[derive(Deserialize)]
@@ -253,7 +210,10 @@ pub async fn processing_requests(str: &str, state: &AppState, socket: &mut WebSo
     if let Some(echo) = params.echo {
         let result = String::new();
         let param:Params= serde_json::from_str(&params.params.to_string()).unwrap();
-        let fileName = fileNamePartData(&param);
+        let fileName = match fileNamePartData(&param) {
+            Ok(fileName) => fileName,
+            Err(err) => return json!({ "error": err.to_string() }).to_string(),
+        };
         let day = if let Some(day) = param.day {
              day
         }else{
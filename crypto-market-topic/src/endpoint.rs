@@ -0,0 +1,62 @@
+use std::{fmt, str::FromStr};
+
+use super::{TransportError, TransportResult};
+
+/// Whether a socket binds its endpoint or connects to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Role {
+    Bind,
+    Connect,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Bind => write!(f, "bind"),
+            Role::Connect => write!(f, "connect"),
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = TransportError;
+
+    fn from_str(s: &str) -> TransportResult<Self> {
+        match s {
+            "bind" => Ok(Role::Bind),
+            "connect" => Ok(Role::Connect),
+            _ => Err(TransportError::UnknownRole(s.to_string())),
+        }
+    }
+}
+
+/// The ZeroMQ endpoint a topic is published on, and whether the socket
+/// binds it or connects to it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Endpoint {
+    url: String,
+    role: Role,
+}
+
+impl Endpoint {
+    pub fn new(url: &str, role: Role) -> Self {
+        Endpoint {
+            url: url.to_string(),
+            role,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.url
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.role, self.url)
+    }
+}
@@ -0,0 +1,61 @@
+use lazy_static::lazy_static;
+use std::{collections::HashMap, sync::Mutex};
+use tokio::sync::mpsc::{self, error::TrySendError, Receiver, Sender};
+
+// Messages a subscriber holds before newer ones are dropped, as the high
+// water mark of a ZeroMQ socket does
+const CAPACITY: usize = 1000;
+
+struct Subscription {
+    prefixes: Vec<String>,
+    tx: Sender<(String, Vec<u8>)>,
+}
+
+lazy_static! {
+    // The context every inproc socket of the process shares, subscriptions
+    // keyed by endpoint
+    static ref CONTEXT: Mutex<HashMap<String, Vec<Subscription>>> = Mutex::new(HashMap::new());
+}
+
+/// Whether an endpoint is served by the in-process context.
+pub(crate) fn is_inproc(url: &str) -> bool {
+    url.starts_with("inproc://")
+}
+
+/// Subscribe to the topics starting with one of `prefixes` on the inproc
+/// `urls`, whichever side binds.
+pub(crate) fn subscribe(urls: &[&str], prefixes: &[String]) -> Receiver<(String, Vec<u8>)> {
+    let (tx, rx) = mpsc::channel(CAPACITY);
+    let mut context = CONTEXT.lock().unwrap();
+    for url in urls {
+        context
+            .entry(url.to_string())
+            .or_default()
+            .push(Subscription {
+                prefixes: prefixes.to_vec(),
+                tx: tx.clone(),
+            });
+    }
+    rx
+}
+
+/// Send a message to the subscribers of `url`, dropping it for those that
+/// are full and forgetting those that are gone.
+pub(crate) fn publish(url: &str, topic: &str, data: &[u8]) {
+    let mut context = CONTEXT.lock().unwrap();
+    if let Some(subscriptions) = context.get_mut(url) {
+        subscriptions.retain(|subscription| {
+            if !subscription
+                .prefixes
+                .iter()
+                .any(|prefix| topic.starts_with(prefix.as_str()))
+            {
+                return !subscription.tx.is_closed();
+            }
+            !matches!(
+                subscription.tx.try_send((topic.to_string(), data.to_vec())),
+                Err(TrySendError::Closed(_))
+            )
+        });
+    }
+}
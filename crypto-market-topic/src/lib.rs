@@ -0,0 +1,12 @@
+pub(crate) mod endpoint;
+pub(crate) mod inproc;
+pub(crate) mod socket;
+pub(crate) mod topic;
+pub(crate) mod transport;
+
+pub use crypto_market_type::MarketType;
+pub use crypto_msg_type::MessageType;
+pub use endpoint::{Endpoint, Role};
+pub use socket::{Publisher, Subscriber};
+pub use topic::{Topic, TopicError, TopicResult, TopicType};
+pub use transport::{Transport, TransportError, TransportResult};
@@ -0,0 +1,209 @@
+use super::{inproc, Endpoint, Role, Topic, Transport, TransportError, TransportResult};
+use std::collections::HashSet;
+use tokio::sync::mpsc::Receiver;
+use zeromq::{PubSocket, Socket, SocketRecv, SocketSend, SubSocket, ZmqMessage};
+
+async fn open<S: Socket>(socket: &mut S, endpoint: &Endpoint) -> TransportResult<()> {
+    let result = match endpoint.role() {
+        Role::Bind => socket.bind(endpoint.as_str()).await.map(|_| ()),
+        Role::Connect => socket.connect(endpoint.as_str()).await,
+    };
+    result.map_err(|err| TransportError::SocketFailed(endpoint.to_string(), err.to_string()))
+}
+
+// A ZeroMQ socket, or the in-process context on inproc endpoints
+enum PubInner {
+    Zmq(PubSocket),
+    Inproc,
+}
+
+enum SubInner {
+    Zmq(SubSocket),
+    Inproc(Receiver<(String, Vec<u8>)>),
+}
+
+/// A PUB socket publishing one topic.
+///
+/// Every message is sent as two frames, the topic and the payload, so
+/// subscribers of a broker can filter by topic prefix. On `inproc://`
+/// endpoints messages go through a context shared by the sockets of the
+/// process instead.
+pub struct Publisher {
+    socket: PubInner,
+    endpoint: Endpoint,
+    topic: String,
+}
+
+impl Publisher {
+    pub async fn new(endpoint: Endpoint, topic: &Topic) -> TransportResult<Self> {
+        let socket = if inproc::is_inproc(endpoint.as_str()) {
+            PubInner::Inproc
+        } else {
+            let mut socket = PubSocket::new();
+            open(&mut socket, &endpoint).await?;
+            PubInner::Zmq(socket)
+        };
+        Ok(Publisher {
+            socket,
+            endpoint,
+            topic: topic.to_string(),
+        })
+    }
+
+    /// Open the publisher of `topic` on the endpoint of the global transport.
+    pub async fn for_topic(topic: &Topic) -> TransportResult<Self> {
+        Publisher::new(Transport::global()?.publisher(topic)?, topic).await
+    }
+
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    pub async fn send(&mut self, data: &[u8]) -> TransportResult<()> {
+        let socket = match self.socket {
+            PubInner::Zmq(ref mut socket) => socket,
+            PubInner::Inproc => {
+                inproc::publish(self.endpoint.as_str(), &self.topic, data);
+                return Ok(());
+            }
+        };
+        let mut msg = ZmqMessage::from(self.topic.clone());
+        msg.push_back(data.to_vec().into());
+        socket
+            .send(msg)
+            .await
+            .map_err(|err| TransportError::SendFailed(self.endpoint.to_string(), err.to_string()))
+    }
+
+    /// Close the socket, unbinding or disconnecting its endpoint.
+    pub async fn close(self) -> TransportResult<()> {
+        let socket = match self.socket {
+            PubInner::Zmq(socket) => socket,
+            PubInner::Inproc => return Ok(()),
+        };
+        match socket.close().await.into_iter().next() {
+            Some(err) => Err(TransportError::SocketFailed(
+                self.endpoint.to_string(),
+                err.to_string(),
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A SUB socket receiving the messages of a set of topics, or of every
+/// topic matching a set of prefixes, through as few connections as the
+/// transport allows.
+pub struct Subscriber {
+    socket: SubInner,
+    endpoints: Vec<Endpoint>,
+    // Subscriptions match by prefix, exact subscriptions drop the topics
+    // that merely share it, e.g. `..._BTCUSDT` for `..._BTCUSD`.
+    topics: Option<HashSet<String>>,
+}
+
+impl Subscriber {
+    pub(crate) async fn new(
+        endpoints: Vec<Endpoint>,
+        prefixes: &[String],
+        topics: Option<HashSet<String>>,
+    ) -> TransportResult<Self> {
+        // endpoints of one template share its scheme
+        if endpoints
+            .iter()
+            .all(|endpoint| inproc::is_inproc(endpoint.as_str()))
+        {
+            let urls: Vec<&str> = endpoints.iter().map(|endpoint| endpoint.as_str()).collect();
+            let rx = inproc::subscribe(&urls, prefixes);
+            return Ok(Subscriber {
+                socket: SubInner::Inproc(rx),
+                endpoints,
+                topics,
+            });
+        }
+
+        let mut socket = SubSocket::new();
+        for endpoint in endpoints.iter() {
+            open(&mut socket, endpoint).await?;
+        }
+        for prefix in prefixes {
+            socket.subscribe(prefix).await.map_err(|err| {
+                TransportError::SocketFailed(format!("{:?}", endpoints), err.to_string())
+            })?;
+        }
+        Ok(Subscriber {
+            socket: SubInner::Zmq(socket),
+            endpoints,
+            topics,
+        })
+    }
+
+    /// Subscribe to `topic` on the endpoint of the global transport.
+    pub async fn for_topic(topic: &Topic) -> TransportResult<Self> {
+        Subscriber::for_topics(std::slice::from_ref(topic)).await
+    }
+
+    /// Subscribe to `topics` with one socket, connected once to every
+    /// distinct endpoint, i.e. a single connection through a broker.
+    pub async fn for_topics(topics: &[Topic]) -> TransportResult<Self> {
+        let transport = Transport::global()?;
+        let mut endpoints = Vec::new();
+        for topic in topics {
+            let endpoint = transport.subscriber(topic)?;
+            if !endpoints.contains(&endpoint) {
+                endpoints.push(endpoint);
+            }
+        }
+        let names: Vec<String> = topics.iter().map(|topic| topic.to_string()).collect();
+        let exact = names.iter().cloned().collect();
+        Subscriber::new(endpoints, &names, Some(exact)).await
+    }
+
+    /// Subscribe to every topic starting with one of `prefixes`, e.g.
+    /// `binance_spot_trade_`, which needs a broker.
+    pub async fn for_prefixes(prefixes: &[String]) -> TransportResult<Self> {
+        let endpoint = Transport::global()?.broker()?;
+        Subscriber::new(vec![endpoint], prefixes, None).await
+    }
+
+    pub fn endpoints(&self) -> &[Endpoint] {
+        &self.endpoints
+    }
+
+    /// Receive the payload of the next message.
+    pub async fn recv(&mut self) -> TransportResult<Vec<u8>> {
+        self.recv_topic().await.map(|(_, data)| data)
+    }
+
+    /// Receive the topic and the payload of the next message.
+    pub async fn recv_topic(&mut self) -> TransportResult<(String, Vec<u8>)> {
+        loop {
+            let (topic, data) = match self.socket {
+                SubInner::Zmq(ref mut socket) => {
+                    let msg = socket.recv().await.map_err(|err| {
+                        TransportError::RecvFailed(format!("{:?}", self.endpoints), err.to_string())
+                    })?;
+                    match (msg.get(0), msg.get(1)) {
+                        (Some(topic), Some(data)) => {
+                            (String::from_utf8_lossy(topic).to_string(), data.to_vec())
+                        }
+                        _ => continue,
+                    }
+                }
+                // the context holds the sending side while the subscriber lives
+                SubInner::Inproc(ref mut rx) => rx.recv().await.ok_or_else(|| {
+                    TransportError::RecvFailed(
+                        format!("{:?}", self.endpoints),
+                        "inproc context closed".to_string(),
+                    )
+                })?,
+            };
+            if let Some(ref topics) = self.topics {
+                if !topics.contains(&topic) {
+                    continue;
+                }
+            }
+            return Ok((topic, data));
+        }
+    }
+}
@@ -0,0 +1,388 @@
+use crypto_market_type::MarketType;
+use crypto_msg_type::MessageType;
+use std::{fmt, str::FromStr};
+
+const NORMALIZED_PREFIX: &str = "normalized_";
+const RAW_PREFIX: &str = "raw_";
+
+/// The message type part of a topic.
+///
+/// Besides the crawled message types, topics carry messages derived from
+/// them by the pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TopicType {
+    /// Messages crawled from an exchange
+    Msg(MessageType),
+    /// Crawled messages without a parser, published as the exchange JSON
+    Raw(MessageType),
+    /// Full order book rebuilt from snapshots and deltas
+    L2Book,
+    /// Top-K levels of the rebuilt order book
+    L2BookTopK,
+    /// Sequence gaps of an L2 feed
+    L2Gap,
+    /// Messages that failed to parse, the symbol is the crawled message type
+    DeadLetter,
+    /// Symbol listing and connection changes, the symbol is the crawled message type
+    Control,
+    /// Instrument reference data
+    Instrument,
+}
+
+impl fmt::Display for TopicType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TopicType::Msg(msg_type) => write!(f, "{}", msg_type),
+            TopicType::Raw(msg_type) => write!(f, "{}{}", RAW_PREFIX, msg_type),
+            TopicType::L2Book => write!(f, "l2_book"),
+            TopicType::L2BookTopK => write!(f, "l2_book_topk"),
+            TopicType::L2Gap => write!(f, "l2_gap"),
+            TopicType::DeadLetter => write!(f, "dead_letter"),
+            TopicType::Control => write!(f, "control"),
+            TopicType::Instrument => write!(f, "instrument"),
+        }
+    }
+}
+
+impl FromStr for TopicType {
+    type Err = TopicError;
+
+    fn from_str(s: &str) -> TopicResult<Self> {
+        Ok(match s {
+            "l2_book" => TopicType::L2Book,
+            "l2_book_topk" => TopicType::L2BookTopK,
+            "l2_gap" => TopicType::L2Gap,
+            "dead_letter" => TopicType::DeadLetter,
+            "control" => TopicType::Control,
+            "instrument" => TopicType::Instrument,
+            _ => {
+                let msg_type = |s: &str| {
+                    MessageType::from_str(s).map_err(|_| TopicError::UnknownMsgType(s.to_string()))
+                };
+                match s.strip_prefix(RAW_PREFIX) {
+                    Some(raw) => TopicType::Raw(msg_type(raw)?),
+                    None => TopicType::Msg(msg_type(s)?),
+                }
+            }
+        })
+    }
+}
+
+impl From<MessageType> for TopicType {
+    fn from(msg_type: MessageType) -> Self {
+        TopicType::Msg(msg_type)
+    }
+}
+
+/// A topic of the pipeline, `exchange_market_type_msg_type_symbol[_period]`.
+///
+/// Topics of the normalized namespace are prefixed with `normalized_` and
+/// keyed by the normalized pair, e.g. `normalized_binance_spot_trade_BTC/USDT`.
+/// Only candlestick topics have a period, in seconds.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Topic {
+    pub exchange: String,
+    pub market_type: MarketType,
+    pub msg_type: TopicType,
+    pub symbol: String,
+    pub period: Option<String>,
+    pub normalized: bool,
+}
+
+impl Topic {
+    pub fn new<T: Into<TopicType>>(
+        exchange: &str,
+        market_type: MarketType,
+        msg_type: T,
+        symbol: &str,
+    ) -> Self {
+        Topic {
+            exchange: exchange.to_string(),
+            market_type,
+            msg_type: msg_type.into(),
+            symbol: symbol.to_string(),
+            period: None,
+            normalized: false,
+        }
+    }
+
+    /// Set the period, an empty period means none.
+    pub fn with_period(mut self, period: &str) -> Self {
+        self.period = if period.is_empty() {
+            None
+        } else {
+            Some(period.to_string())
+        };
+        self
+    }
+
+    /// Move the topic to the normalized namespace, keyed by `key`: the
+    /// normalized pair, followed by the contract of dated futures and options.
+    pub fn normalized(mut self, key: &str) -> Self {
+        self.symbol = key.to_string();
+        self.normalized = true;
+        self
+    }
+
+    /// Build a topic from command line or request parameters.
+    pub fn from_parts(
+        exchange: &str,
+        market_type: &str,
+        msg_type: &str,
+        symbol: &str,
+        period: Option<&str>,
+    ) -> TopicResult<Self> {
+        let market_type = MarketType::from_str(market_type)
+            .map_err(|_| TopicError::UnknownMarketType(market_type.to_string()))?;
+        let msg_type = TopicType::from_str(msg_type)?;
+        let topic =
+            Topic::new(exchange, market_type, msg_type, symbol).with_period(period.unwrap_or(""));
+        topic.validate()?;
+        Ok(topic)
+    }
+
+    /// Check that every part is non-empty and the topic can be parsed back.
+    pub fn validate(&self) -> TopicResult<()> {
+        if self.exchange.is_empty()
+            || self
+                .exchange
+                .contains(|c: char| c == '/' || c.is_whitespace())
+        {
+            return Err(TopicError::InvalidExchange(self.exchange.clone()));
+        }
+        if self.symbol.is_empty() || self.symbol.contains(char::is_whitespace) {
+            return Err(TopicError::InvalidSymbol(self.symbol.clone()));
+        }
+        if let Some(ref period) = self.period {
+            if period.is_empty() || !period.chars().all(|c| c.is_ascii_digit()) {
+                return Err(TopicError::InvalidPeriod(period.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// The topic with `/` of normalized pairs replaced, usable as a file name.
+    pub fn file_name(&self) -> String {
+        self.to_string().replace('/', "-")
+    }
+}
+
+impl fmt::Display for Topic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.normalized {
+            write!(f, "{}", NORMALIZED_PREFIX)?;
+        }
+        write!(
+            f,
+            "{}_{}_{}_{}",
+            self.exchange, self.market_type, self.msg_type, self.symbol
+        )?;
+        if let Some(ref period) = self.period {
+            write!(f, "_{}", period)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Topic {
+    type Err = TopicError;
+
+    /// Exchanges, market types, message types and symbols may all contain
+    /// `_`, so the split is searched for: the shortest exchange followed by
+    /// a known market type and the longest known message type. A trailing
+    /// all-digit part of a candlestick topic is its period.
+    fn from_str(s: &str) -> TopicResult<Self> {
+        let (normalized, rest) = match s.strip_prefix(NORMALIZED_PREFIX) {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let parts: Vec<&str> = rest.split('_').collect();
+
+        for i in 1..parts.len() {
+            for j in i + 1..parts.len() {
+                let market_type = match MarketType::from_str(&parts[i..j].join("_")) {
+                    Ok(market_type) => market_type,
+                    Err(_) => continue,
+                };
+                for k in (j + 1..parts.len()).rev() {
+                    let msg_type = match TopicType::from_str(&parts[j..k].join("_")) {
+                        Ok(msg_type) => msg_type,
+                        Err(_) => continue,
+                    };
+
+                    let mut symbol_parts = &parts[k..];
+                    let mut period = None;
+                    if msg_type == TopicType::Msg(MessageType::Candlestick)
+                        && symbol_parts.len() > 1
+                    {
+                        let last = symbol_parts[symbol_parts.len() - 1];
+                        if !last.is_empty() && last.chars().all(|c| c.is_ascii_digit()) {
+                            period = Some(last.to_string());
+                            symbol_parts = &symbol_parts[..symbol_parts.len() - 1];
+                        }
+                    }
+
+                    let topic = Topic {
+                        exchange: parts[..i].join("_"),
+                        market_type,
+                        msg_type,
+                        symbol: symbol_parts.join("_"),
+                        period,
+                        normalized,
+                    };
+                    topic.validate()?;
+                    return Ok(topic);
+                }
+            }
+        }
+        Err(TopicError::InvalidTopic(s.to_string()))
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum TopicError {
+    #[error("invalid topic: {0}")]
+    InvalidTopic(String),
+
+    #[error("unknown market type: {0}")]
+    UnknownMarketType(String),
+
+    #[error("unknown msg type: {0}")]
+    UnknownMsgType(String),
+
+    #[error("invalid exchange: {0}")]
+    InvalidExchange(String),
+
+    #[error("invalid symbol: {0}")]
+    InvalidSymbol(String),
+
+    #[error("invalid period: {0}")]
+    InvalidPeriod(String),
+}
+
+pub type TopicResult<T> = Result<T, TopicError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(s: &str) -> Topic {
+        let topic = Topic::from_str(s).unwrap();
+        assert_eq!(topic.to_string(), s);
+        topic
+    }
+
+    #[test]
+    fn parses_a_plain_topic() {
+        let topic = round_trip("binance_spot_trade_BTCUSDT");
+        assert_eq!(
+            topic,
+            Topic::new("binance", MarketType::Spot, MessageType::Trade, "BTCUSDT")
+        );
+    }
+
+    #[test]
+    fn parses_periods_of_candlesticks_only() {
+        let topic = round_trip("binance_spot_candlestick_BTCUSDT_60");
+        assert_eq!(topic.symbol, "BTCUSDT");
+        assert_eq!(topic.period.as_deref(), Some("60"));
+
+        let topic = round_trip("binance_inverse_future_candlestick_BTCUSD_210625_3600");
+        assert_eq!(topic.symbol, "BTCUSD_210625");
+        assert_eq!(topic.period.as_deref(), Some("3600"));
+
+        // a trailing number of other message types is part of the symbol
+        let topic = round_trip("binance_inverse_future_trade_BTCUSD_210625");
+        assert_eq!(topic.market_type, MarketType::InverseFuture);
+        assert_eq!(topic.symbol, "BTCUSD_210625");
+        assert_eq!(topic.period, None);
+    }
+
+    #[test]
+    fn parses_symbols_with_underscores() {
+        let topic = round_trip("gate_spot_l2_event_BTC_USDT");
+        assert_eq!(topic.msg_type, TopicType::Msg(MessageType::L2Event));
+        assert_eq!(topic.symbol, "BTC_USDT");
+
+        let topic = round_trip("binance_spot_control_l2_event");
+        assert_eq!(topic.msg_type, TopicType::Control);
+        assert_eq!(topic.symbol, "l2_event");
+    }
+
+    #[test]
+    fn parses_normalized_pairs() {
+        let topic = round_trip("normalized_binance_spot_trade_BTC/USDT");
+        assert!(topic.normalized);
+        assert_eq!(topic.symbol, "BTC/USDT");
+        assert_eq!(topic.file_name(), "normalized_binance_spot_trade_BTC-USDT");
+
+        let topic = round_trip("normalized_binance_inverse_future_candlestick_BTC/USD-210625_60");
+        assert_eq!(topic.symbol, "BTC/USD-210625");
+        assert_eq!(topic.period.as_deref(), Some("60"));
+    }
+
+    #[test]
+    fn parses_pipeline_types() {
+        let topic = round_trip("binance_spot_raw_l3_event_BTCUSDT");
+        assert_eq!(topic.msg_type, TopicType::Raw(MessageType::L3Event));
+
+        let topic = round_trip("binance_spot_l2_book_topk_BTCUSDT");
+        assert_eq!(topic.msg_type, TopicType::L2BookTopK);
+
+        let topic = round_trip("binance_spot_instrument_ALL");
+        assert_eq!(topic.msg_type, TopicType::Instrument);
+    }
+
+    #[test]
+    fn rejects_invalid_topics() {
+        for s in [
+            "",
+            "binance",
+            "binance_spot_trade",
+            "binance_moon_trade_BTCUSDT",
+            "binance_spot_gossip_BTCUSDT",
+        ] {
+            assert!(
+                matches!(Topic::from_str(s), Err(TopicError::InvalidTopic(_))),
+                "{}",
+                s
+            );
+        }
+        assert!(matches!(
+            Topic::from_str("_spot_trade_BTCUSDT"),
+            Err(TopicError::InvalidExchange(_))
+        ));
+        assert!(matches!(
+            Topic::from_str("binance_spot_trade_BTC USDT"),
+            Err(TopicError::InvalidSymbol(_))
+        ));
+    }
+
+    #[test]
+    fn from_parts_validates() {
+        let topic =
+            Topic::from_parts("binance", "spot", "candlestick", "BTCUSDT", Some("60")).unwrap();
+        assert_eq!(topic.to_string(), "binance_spot_candlestick_BTCUSDT_60");
+
+        assert!(matches!(
+            Topic::from_parts("binance", "moon", "trade", "BTCUSDT", None),
+            Err(TopicError::UnknownMarketType(_))
+        ));
+        assert!(matches!(
+            Topic::from_parts("binance", "spot", "gossip", "BTCUSDT", None),
+            Err(TopicError::UnknownMsgType(_))
+        ));
+        assert!(matches!(
+            Topic::from_parts("binance", "spot", "candlestick", "BTCUSDT", Some("1m")),
+            Err(TopicError::InvalidPeriod(_))
+        ));
+        assert!(matches!(
+            Topic::from_parts("binance", "spot", "trade", "", None),
+            Err(TopicError::InvalidSymbol(_))
+        ));
+        assert!(matches!(
+            Topic::from_parts("bin/ance", "spot", "trade", "BTCUSDT", None),
+            Err(TopicError::InvalidExchange(_))
+        ));
+    }
+}
@@ -0,0 +1,332 @@
+use super::{Endpoint, Role, Topic};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+
+const DEFAULT_TEMPLATE: &str = "ipc:///tmp/{topic}.ipc";
+// inproc endpoints are served by a context shared by the process
+const SCHEMES: [&str; 3] = ["inproc://", "ipc://", "tcp://"];
+
+lazy_static! {
+    static ref TRANSPORT: TransportResult<Transport> = Transport::from_env();
+}
+
+/// How topics are mapped to ZeroMQ endpoints.
+///
+/// The endpoint template may contain `{topic}`, replaced by the topic file
+/// name, and `{port}`, looked up in a port table keyed by topic, e.g.
+/// `tcp://10.0.0.1:{port}`. Topics missing from the table have no endpoint.
+/// Publishers bind and subscribers connect by default, either side can be
+/// flipped so a crawler can publish to a remote recorder that binds.
+///
+/// With a broker, publishers connect to its XSUB frontend and subscribers
+/// to its XPUB backend instead, whatever the template.
+#[derive(Debug, Clone)]
+pub struct Transport {
+    template: String,
+    publisher_role: Role,
+    subscriber_role: Role,
+    ports: HashMap<String, u16>,
+    broker_frontend: Option<String>,
+    broker_backend: Option<String>,
+}
+
+impl Default for Transport {
+    fn default() -> Self {
+        Transport {
+            template: DEFAULT_TEMPLATE.to_string(),
+            publisher_role: Role::Bind,
+            subscriber_role: Role::Connect,
+            ports: HashMap::new(),
+            broker_frontend: None,
+            broker_backend: None,
+        }
+    }
+}
+
+impl Transport {
+    pub fn new(template: &str) -> TransportResult<Self> {
+        if !SCHEMES.iter().any(|scheme| template.starts_with(scheme))
+            || !(template.contains("{topic}") || template.contains("{port}"))
+        {
+            return Err(TransportError::InvalidTemplate(template.to_string()));
+        }
+        Ok(Transport {
+            template: template.to_string(),
+            ..Default::default()
+        })
+    }
+
+    pub fn with_roles(mut self, publisher: Role, subscriber: Role) -> Self {
+        self.publisher_role = publisher;
+        self.subscriber_role = subscriber;
+        self
+    }
+
+    /// The port table of `{port}`, two topics can't share a port.
+    pub fn with_ports(mut self, ports: HashMap<String, u16>) -> TransportResult<Self> {
+        let mut topics: HashMap<u16, &str> = HashMap::new();
+        for (topic, port) in ports.iter() {
+            if let Some(other) = topics.insert(*port, topic) {
+                let (first, second) = if other < topic.as_str() {
+                    (other, topic.as_str())
+                } else {
+                    (topic.as_str(), other)
+                };
+                return Err(TransportError::DuplicatePort(
+                    *port,
+                    first.to_string(),
+                    second.to_string(),
+                ));
+            }
+        }
+        self.ports = ports;
+        Ok(self)
+    }
+
+    pub fn with_broker(mut self, frontend: Option<String>, backend: Option<String>) -> Self {
+        self.broker_frontend = frontend;
+        self.broker_backend = backend;
+        self
+    }
+
+    /// Read the transport from the environment:
+    ///
+    /// - `ZMQ_ENDPOINT`, the endpoint template, `ipc:///tmp/{topic}.ipc` by default
+    /// - `ZMQ_PUB_ROLE`, `bind` by default
+    /// - `ZMQ_SUB_ROLE`, `connect` by default
+    /// - `ZMQ_PORTS`, a file of `topic port` lines used for `{port}`, needed
+    ///   by such templates
+    /// - `ZMQ_BROKER_FRONTEND`, the XSUB endpoint of the broker publishers connect to
+    /// - `ZMQ_BROKER_BACKEND`, the XPUB endpoint of the broker subscribers connect to
+    pub fn from_env() -> TransportResult<Self> {
+        let template =
+            std::env::var("ZMQ_ENDPOINT").unwrap_or_else(|_| DEFAULT_TEMPLATE.to_string());
+        let publisher = match std::env::var("ZMQ_PUB_ROLE") {
+            Ok(role) => role.parse()?,
+            Err(_) => Role::Bind,
+        };
+        let subscriber = match std::env::var("ZMQ_SUB_ROLE") {
+            Ok(role) => role.parse()?,
+            Err(_) => Role::Connect,
+        };
+        let ports = match std::env::var("ZMQ_PORTS") {
+            Ok(path) => load_ports(&path)?,
+            Err(_) if template.contains("{port}") => return Err(TransportError::NoPorts),
+            Err(_) => HashMap::new(),
+        };
+
+        Ok(Transport::new(&template)?
+            .with_roles(publisher, subscriber)
+            .with_ports(ports)?
+            .with_broker(
+                std::env::var("ZMQ_BROKER_FRONTEND").ok(),
+                std::env::var("ZMQ_BROKER_BACKEND").ok(),
+            ))
+    }
+
+    /// The transport of this process, read from the environment on first use.
+    pub fn global() -> TransportResult<&'static Transport> {
+        TRANSPORT.as_ref().map_err(Clone::clone)
+    }
+
+    /// Read the transport of this process at startup, so a bad `ZMQ_*`
+    /// configuration is reported before any socket is opened. Prints the
+    /// error and exits if it is invalid.
+    pub fn init_or_exit() {
+        if let Err(err) = Transport::global() {
+            println!("{}", err);
+            std::process::exit(1);
+        }
+    }
+
+    pub fn publisher(&self, topic: &Topic) -> TransportResult<Endpoint> {
+        match self.broker_frontend {
+            Some(ref url) => Ok(Endpoint::new(url, Role::Connect)),
+            None => self.endpoint(topic, self.publisher_role),
+        }
+    }
+
+    pub fn subscriber(&self, topic: &Topic) -> TransportResult<Endpoint> {
+        match self.broker_backend {
+            Some(ref url) => Ok(Endpoint::new(url, Role::Connect)),
+            None => self.endpoint(topic, self.subscriber_role),
+        }
+    }
+
+    /// The XPUB endpoint of the broker, needed to subscribe by prefix.
+    pub fn broker(&self) -> TransportResult<Endpoint> {
+        match self.broker_backend {
+            Some(ref url) => Ok(Endpoint::new(url, Role::Connect)),
+            None => Err(TransportError::NoBroker),
+        }
+    }
+
+    fn endpoint(&self, topic: &Topic, role: Role) -> TransportResult<Endpoint> {
+        let mut url = self.template.replace("{topic}", &topic.file_name());
+        if url.contains("{port}") {
+            url = url.replace("{port}", &self.port(topic)?.to_string());
+        }
+        Ok(Endpoint::new(&url, role))
+    }
+
+    /// The port of a topic in the port table.
+    pub fn port(&self, topic: &Topic) -> TransportResult<u16> {
+        let topic = topic.to_string();
+        self.ports
+            .get(&topic)
+            .copied()
+            .ok_or(TransportError::UnlistedTopic(topic))
+    }
+}
+
+fn load_ports(path: &str) -> TransportResult<HashMap<String, u16>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|err| TransportError::InvalidPorts(path.to_string(), err.to_string()))?;
+
+    let mut ports = HashMap::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let port = match line.split_whitespace().collect::<Vec<&str>>()[..] {
+            [topic, port] => port.parse::<u16>().ok().map(|port| (topic, port)),
+            _ => None,
+        };
+        match port {
+            Some((topic, port)) => {
+                if ports.insert(topic.to_string(), port).is_some() {
+                    return Err(TransportError::InvalidPorts(
+                        path.to_string(),
+                        format!("{} is listed twice", topic),
+                    ));
+                }
+            }
+            None => {
+                return Err(TransportError::InvalidPorts(
+                    path.to_string(),
+                    line.to_string(),
+                ))
+            }
+        };
+    }
+    Ok(ports)
+}
+
+#[derive(thiserror::Error, Debug, Clone)]
+pub enum TransportError {
+    #[error("invalid endpoint template: {0}")]
+    InvalidTemplate(String),
+
+    #[error("unknown socket role: {0}")]
+    UnknownRole(String),
+
+    #[error("invalid port table {0}: {1}")]
+    InvalidPorts(String, String),
+
+    #[error("endpoint templates with {{port}} need ZMQ_PORTS")]
+    NoPorts,
+
+    #[error("port {0} is listed for both {1} and {2}")]
+    DuplicatePort(u16, String, String),
+
+    #[error("{0} is missing from the port table")]
+    UnlistedTopic(String),
+
+    #[error("prefix subscriptions need ZMQ_BROKER_BACKEND")]
+    NoBroker,
+
+    #[error("failed to open socket {0}: {1}")]
+    SocketFailed(String, String),
+
+    #[error("failed to send on {0}: {1}")]
+    SendFailed(String, String),
+
+    #[error("failed to receive on {0}: {1}")]
+    RecvFailed(String, String),
+}
+
+pub type TransportResult<T> = Result<T, TransportError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MarketType, MessageType, Publisher, Subscriber};
+    use std::collections::HashSet;
+
+    fn topic(symbol: &str) -> Topic {
+        Topic::new("binance", MarketType::Spot, MessageType::Trade, symbol)
+    }
+
+    #[test]
+    fn accepts_templates_of_known_schemes() {
+        assert!(Transport::new("inproc://{topic}").is_ok());
+        assert!(Transport::new("udp://127.0.0.1:{port}").is_err());
+        assert!(Transport::new("tcp://127.0.0.1:5555").is_err());
+        assert!(Transport::new("tcp://127.0.0.1:{port}").is_ok());
+    }
+
+    #[test]
+    fn listed_topics_use_their_port() {
+        let ports = HashMap::from([("binance_spot_trade_BTCUSDT".to_string(), 20001)]);
+        let transport = Transport::new("tcp://127.0.0.1:{port}")
+            .unwrap()
+            .with_ports(ports)
+            .unwrap();
+        assert_eq!(
+            transport.publisher(&topic("BTCUSDT")).unwrap().as_str(),
+            "tcp://127.0.0.1:20001"
+        );
+    }
+
+    #[test]
+    fn unlisted_topics_have_no_port() {
+        let transport = Transport::new("tcp://127.0.0.1:{port}").unwrap();
+        assert!(matches!(
+            transport.subscriber(&topic("ETHUSDT")),
+            Err(TransportError::UnlistedTopic(topic)) if topic == "binance_spot_trade_ETHUSDT"
+        ));
+    }
+
+    #[test]
+    fn rejects_shared_ports() {
+        let ports = HashMap::from([
+            ("binance_spot_trade_BTCUSDT".to_string(), 20001),
+            ("binance_spot_trade_ETHUSDT".to_string(), 20001),
+        ]);
+        let err = Transport::default().with_ports(ports).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "port 20001 is listed for both binance_spot_trade_BTCUSDT and binance_spot_trade_ETHUSDT"
+        );
+    }
+
+    #[tokio::test]
+    async fn inproc_sockets_share_the_process_context() {
+        let transport = Transport::new("inproc://{topic}").unwrap();
+        let btc = topic("BTCUSDT");
+        let mut subscriber = Subscriber::new(
+            vec![transport.subscriber(&btc).unwrap()],
+            &[btc.to_string()],
+            Some(HashSet::from([btc.to_string()])),
+        )
+        .await
+        .unwrap();
+        let mut publisher = Publisher::new(transport.publisher(&btc).unwrap(), &btc)
+            .await
+            .unwrap();
+        let mut other = Publisher::new(
+            transport.publisher(&topic("ETHUSDT")).unwrap(),
+            &topic("ETHUSDT"),
+        )
+        .await
+        .unwrap();
+
+        other.send(b"eth").await.unwrap();
+        publisher.send(b"btc").await.unwrap();
+        assert_eq!(
+            subscriber.recv_topic().await.unwrap(),
+            (btc.to_string(), b"btc".to_vec())
+        );
+    }
+}